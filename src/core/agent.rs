@@ -1,17 +1,20 @@
 use crate::core::parser::{parse_action, AgentAction};
 use crate::mcp::registry;
+use crate::core::context;
+use crate::core::session::SessionMessage;
+use crate::memory::{replay, Correlation, MemoryStore};
 use colored::*;
 use std::sync::Arc;
-use std::collections::HashMap;
 
 
 pub async fn step_agent_session(
     session_id: String, 
-    memory: Arc<crate::memory::Memory>,
+    memory: Arc<dyn MemoryStore>,
     cortex: Arc<crate::core::cortex::Cortex>,
-    mcp_clients: Arc<tokio::sync::Mutex<HashMap<String, Arc<crate::mcp::client::McpClient>>>>,
+    mcp_tools: Arc<registry::ToolRegistry>,
     overlay: Arc<crate::safety::OverlayFS>,
     max_loops: usize,
+    context_config: &crate::core::state::ContextConfig,
 ) {
     let mut session = match memory.get_session(&session_id).await {
         Ok(Some(s)) => s,
@@ -23,36 +26,78 @@ pub async fn step_agent_session(
         return;
     }
 
-    // 1. Fetch Metadata once (Value-Oriented)
-    let tool_metadata = registry::get_all_tool_metadata(&mcp_clients).await;
-    let tool_defs = registry::get_tool_definitions(&tool_metadata).await;
-    
-    let full_context = session.messages.join("\n\n");
-    let mut prompt = full_context;
+    // 1. Subscribed resources that changed since the last step
+    let updates = mcp_tools.take_updates(&session_id);
+    let updated = !updates.is_empty();
+    for (server, uri) in updates {
+        let observation = match registry::read_resource(&mcp_tools, &uri, Some(&server), None).await {
+            Ok((_, contents)) => format!("**Observation (Resource '{}' updated):**\n{}", uri, contents),
+            Err(e) => format!("**Observation (Error reading updated resource '{}'):**\n{}", uri, e),
+        };
+        session = session.with_message(SessionMessage::observation(observation));
+    }
 
-    if session.depth == 0 {
-        if !tool_defs.is_empty() {
-            prompt = format!("{}\n\n{}", prompt, tool_defs);
+    // 2. Held tool calls decided since the last step
+    let decisions = mcp_tools.take_decisions(&session_id);
+    let decided = !decisions.is_empty();
+    for (call, approved) in &decisions {
+        let data = serde_json::json!({ "id": call.id, "approved": approved });
+        let _ = memory.record_correlated(registry::APPROVAL_CONSUMED, data, &correlation(&session));
+    }
+    if decided {
+        session = session.with_status(crate::core::session::SessionStatus::Idle);
+    }
+    for (call, approved) in decisions {
+        session = if approved {
+            run_tool(&call.tool, call.arguments, session, &mcp_tools, memory.as_ref(), context_config).await
+        } else {
+            session.with_message(SessionMessage::tool(format!("**Observation (Call to '{}' rejected):** The user did not approve this call.", call.tool)))
+        };
+    }
+    // The updates and decisions are spent and approved calls have run: save
+    // that now, so a failed generation below cannot lose it.
+    if updated || decided {
+        if let Err(e) = memory.update_session(&session).await {
+            eprintln!("{} Failed to save resource updates and tool calls for {}: {}", "⚠️".yellow(), session_id, e);
         }
+    }
+
+    let mut preamble = String::new();
+    if session.depth == 0 {
+        // Tool, resource and prompt metadata from the registry (no MCP round-trips)
+        let tool_defs = registry::get_tool_definitions(&mcp_tools.snapshot()).await;
+        let context_defs = registry::get_context_definitions(&mcp_tools.resources(), &mcp_tools.prompts());
+        preamble = format!("{}{}", tool_defs, context_defs);
         // Inject Datalog Schema for grounding
-        prompt = format!("{}\n\n## KNOWLEDGE GRAPH SCHEMA (Datalog Ready)\nNodes: `nodes {{ id => content, type, path, embedding }}`\nEdges: `edges {{ parent => child }}`\n", prompt);
+        preamble = format!("{}\n\n## KNOWLEDGE GRAPH SCHEMA (Datalog Ready)\nNodes: `nodes {{ id => content, type, path, embedding }}`\nEdges: `edges {{ parent => child }}`\n", preamble);
     }
 
+    let window = context::build(&session, preamble.trim_start(), context_config);
+    if !window.elided.is_empty() {
+        println!("{} [Session {}] Context ~{} tokens, {} message(s) shortened", "✂️".yellow(), session_id, window.tokens, window.elided.len());
+    }
+    if let Err(e) = context::remember_elided(memory.as_ref(), &session, &window).await {
+        eprintln!("{} Failed to store elided messages: {}", "⚠️".yellow(), e);
+    }
+    let prompt = window.prompt;
+    // Images from tool results since the last step
+    let images = mcp_tools.take_images(&session_id);
+
     println!("{} [Session {}] Thinking...", "🤔".magenta(), session_id);
-    match cortex.generate(&prompt, crate::core::cortex::ThinkingLevel::High).await {
+    match cortex.generate_with_images(&prompt, &images, crate::core::cortex::ThinkingLevel::High).await {
         Ok(response) => {
             println!("{}\n{}", "🤖 Sly (Managed Session):".green().bold(), response);
             
             // Functional Update
             let step_depth = session.depth;
-            session = session.with_message(format!("**Sly (Step {}):**\n{}", step_depth, response.clone()))
+            session = session.with_message(SessionMessage::response(format!("**Sly (Step {}):**\n{}", step_depth, response.clone())))
                              .with_depth_increment();
             
             match parse_action(&response) {
                 Ok(actions) => {
                     for action in actions {
                         // Pass ownership and get new session back
-                        session = handle_action(action, session, &tool_metadata, overlay.clone()).await;
+                        session = handle_action(action, session, &mcp_tools, memory.as_ref(), overlay.clone(), context_config).await;
                     }
                 }
                 Err(e) => {
@@ -64,6 +109,8 @@ pub async fn step_agent_session(
         }
         Err(e) => {
             eprintln!("Cortex error: {}", e);
+            // Shown with the next attempt instead
+            mcp_tools.attach_images(&session_id, images);
         }
     }
 }
@@ -72,36 +119,74 @@ pub async fn step_agent_session(
 async fn handle_action(
     action: AgentAction, 
     session: crate::core::session::AgentSession, 
-    tool_metadata: &[registry::McpToolMetadata],
+    mcp_tools: &registry::ToolRegistry,
+    memory: &dyn MemoryStore,
     overlay: Arc<crate::safety::OverlayFS>,
+    context_config: &crate::core::state::ContextConfig,
 ) -> crate::core::session::AgentSession {
     match action {
         AgentAction::CallTool { tool_name, arguments } => {
-            println!("{} 🛠️  Calling Tool: {}...", "⚙️".cyan(), tool_name);
-            match registry::call_mcp_tool(tool_metadata, &tool_name, arguments).await {
-                Ok(tool_output) => {
-                    session.with_message(format!("**Observation (Tool '{}'):**\n```json\n{}\n```", tool_name, tool_output))
+            let held = registry::resolve_tool(&mcp_tools.snapshot(), &tool_name).is_ok_and(|meta| meta.needs_approval);
+            if !held {
+                return run_tool(&tool_name, arguments, session, mcp_tools, memory, context_config).await;
+            }
+            let call = mcp_tools.hold(&session.id, &tool_name, arguments);
+            println!("{} ⏸️  {} awaits approval: sly mcp approve {} (or reject {})", "⚙️".cyan(), tool_name, call.id, call.id);
+            let _ = memory.record_correlated(registry::APPROVAL_REQUESTED, serde_json::to_value(&call).unwrap_or_default(), &correlation(&session));
+            session.with_message(SessionMessage::tool(format!("**Observation (Call to '{}' awaits approval as {}):** It runs, or is rejected, before your next step.", tool_name, call.id)))
+                   .with_status(crate::core::session::SessionStatus::AwaitingObservation)
+        }
+        AgentAction::ReadOutput { handle, offset } => {
+            println!("{} 📄 ReadOutput: {} @ {}", "⚙️".cyan(), handle, offset);
+            match memory.get_memory(&handle).await {
+                Ok(Some(text)) => {
+                    let (page, next) = context::output_page(&text, offset, context_config.max_tool_output_tokens);
+                    let mut observation = format!("**Observation (Output '{}' from byte {}):**\n{}", handle, offset, page.trim_end());
+                    match next {
+                        Some(next) => observation.push_str(&format!("\n{}", context::continuation(&handle, next, crate::memory::chunker::estimate_tokens(&text)))),
+                        None => observation.push_str("\n[end of output]"),
+                    }
+                    session.with_message(SessionMessage::tool(observation))
                 }
-                Err(e) => {
-                    session.with_message(format!("**Observation (Error from '{}'):**\n{}", tool_name, e))
+                Ok(None) => session.with_message(SessionMessage::observation(format!("**Observation (Error):** No stored output '{}'", handle))),
+                Err(e) => session.with_message(SessionMessage::observation(format!("**Observation (Error reading '{}'):** {}", handle, e))),
+            }
+        }
+        AgentAction::ReadResource { uri, server, subscribe } => {
+            println!("{} 📚 ReadResource: {}", "⚙️".cyan(), uri);
+            let subscriber = subscribe.then_some(session.id.clone());
+            match registry::read_resource(mcp_tools, &uri, server.as_deref(), subscriber.as_deref()).await {
+                Ok((server, contents)) => {
+                    session.with_message(SessionMessage::observation(format!("**Observation (Resource '{}' from {}):**\n{}", uri, server, contents)))
                 }
+                Err(e) => session.with_message(SessionMessage::observation(format!("**Observation (Error reading '{}'):**\n{}", uri, e))),
+            }
+        }
+        AgentAction::GetPrompt { name, arguments } => {
+            println!("{} 📋 GetPrompt: {}", "⚙️".cyan(), name);
+            let arguments = if arguments.is_null() { serde_json::json!({}) } else { arguments };
+            match registry::get_prompt(mcp_tools, &name, arguments).await {
+                Ok(text) => session.with_message(SessionMessage::observation(format!("**Observation (Prompt '{}'):**\n{}", name, text))),
+                Err(e) => session.with_message(SessionMessage::observation(format!("**Observation (Error from prompt '{}'):**\n{}", name, e))),
             }
         }
         AgentAction::WriteFile { path, content } => {
              use crate::core::fs::{FileSystemAction, execute_action};
              let fs_action = FileSystemAction::Write { 
                  path: std::path::PathBuf::from(&path), 
-                 content 
+                 content: content.clone()
              };
              println!("{} 📝 FileSystemAction: {:?}", "💾".blue(), fs_action);
               // Unbundled execute_action
              match execute_action(&overlay, fs_action) {
                  Ok(_) => {
-                     session.with_message(format!("**Observation:** Action successfully executed in OverlayFS."))
+                     let event = serde_json::json!({ "path": path, "content": content });
+                     let _ = memory.record_correlated(replay::OVERLAY_WRITE, event, &correlation(&session));
+                     session.with_message(SessionMessage::observation(format!("**Observation:** Action successfully executed in OverlayFS.")))
                  }
                  Err(e) => {
                      eprintln!("     {} Action Failed: {}", "⚠️".red(), e);
-                     session.with_message(format!("**Observation (Error):** Failed to execute action: {}", e))
+                     session.with_message(SessionMessage::observation(format!("**Observation (Error):** Failed to execute action: {}", e)))
                  }
              }
         }
@@ -113,23 +198,47 @@ async fn handle_action(
                         output.status.code().unwrap_or(-1), 
                         String::from_utf8_lossy(&output.stdout), 
                         String::from_utf8_lossy(&output.stderr));
-                     session.with_message(format!("**Observation (Shell '{}'):**\n```\n{}\n```", command, result))
+                     session.with_message(SessionMessage::observation(format!("**Observation (Shell '{}'):**\n```\n{}\n```", command, result)))
                  }
-                 Err(e) => session.with_message(format!("**Observation (Error):** Command '{}' failed: {}", command, e)),
+                 Err(e) => session.with_message(SessionMessage::observation(format!("**Observation (Error):** Command '{}' failed: {}", command, e))),
              }
         }
-        AgentAction::QueryMemory { query: _query, .. } => {
-            session.with_message("**Observation:** Memory query not yet implemented in unbundled OODA step.".to_string())
+        AgentAction::QueryMemory { query, strategy } => {
+            println!("{} 🔎 QueryMemory: {}", "🧠".cyan(), query);
+            let mut opts = crate::memory::SearchOptions::with_limit(8);
+            match strategy.as_deref() {
+                Some("GraphExpand") => opts.hops = 2,
+                Some("Rerank") => opts.rerank = Some(true),
+                Some("Fast") => opts.rerank = Some(false),
+                _ => {}
+            }
+            match memory.search(&query, &opts).await {
+                Ok(hits) if hits.is_empty() => {
+                    session.with_message(SessionMessage::observation(format!("**Observation (Memory '{}'):** No matching nodes.", query)))
+                }
+                Ok(hits) => {
+                    let rendered: Vec<String> = hits.iter().map(|h| {
+                        let snippet: String = h.content.chars().take(400).collect();
+                        format!("- `{}` ({}, {}) score={:.4} via {}\n  {}",
+                            h.id, h.path, h.node_type, h.score,
+                            serde_json::to_string(&h.reasons).unwrap_or_default(), snippet)
+                    }).collect();
+                    session.with_message(SessionMessage::observation(format!("**Observation (Memory '{}'):**\n{}", query, rendered.join("\n"))))
+                }
+                Err(e) => session.with_message(SessionMessage::observation(format!("**Observation (Memory Error):** {}", e))),
+            }
         }
         AgentAction::CommitOverlay { message } => {
             println!("{} 🚀 Committing Overlay: {}", "📦".green().bold(), message);
             match overlay.commit() {
                 Ok(_) => {
-                    session.with_message("**Observation:** Overlay committed to workspace successfully.".to_string())
+                    let event = serde_json::json!({ "message": message });
+                    let _ = memory.record_correlated(replay::OVERLAY_COMMIT, event, &correlation(&session));
+                    session.with_message(SessionMessage::observation("**Observation:** Overlay committed to workspace successfully.".to_string()))
                            .with_status(crate::core::session::SessionStatus::Completed)
                 }
                 Err(e) => {
-                    session.with_message(format!("**Observation (Commit Error):** {}", e))
+                    session.with_message(SessionMessage::observation(format!("**Observation (Commit Error):** {}", e)))
                 }
             }
         }
@@ -140,3 +249,33 @@ async fn handle_action(
     }
 }
 
+async fn run_tool(
+    tool_name: &str,
+    arguments: serde_json::Value,
+    session: crate::core::session::AgentSession,
+    mcp_tools: &registry::ToolRegistry,
+    memory: &dyn MemoryStore,
+    context_config: &crate::core::state::ContextConfig,
+) -> crate::core::session::AgentSession {
+    println!("{} 🛠️  Calling Tool: {}...", "⚙️".cyan(), tool_name);
+    let output = match registry::call_mcp_tool(&mcp_tools.snapshot(), tool_name, arguments).await {
+        Ok(result) => registry::render_tool_result(&result),
+        Err(e) => {
+            return session.with_message(SessionMessage::tool(format!("**Observation (Error from '{}'):**\n{}", tool_name, e)));
+        }
+    };
+    mcp_tools.attach_images(&session.id, output.images);
+    let text = match context::fit_output(memory, &session, tool_name, &output.text, context_config.max_tool_output_tokens).await {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{} Could not store the full output of {}: {}", "⚠️".yellow(), tool_name, e);
+            context::output_page(&output.text, 0, context_config.max_tool_output_tokens).0.to_string()
+        }
+    };
+    let title = if output.is_error { "Error from" } else { "Tool" };
+    session.with_message(SessionMessage::tool(format!("**Observation ({} '{}'):**\n{}", title, tool_name, text)))
+}
+
+fn correlation(session: &crate::core::session::AgentSession) -> Correlation {
+    Correlation { session_id: Some(session.id.clone()), directive_id: None }
+}
@@ -0,0 +1,301 @@
+// src/core/context.rs - Prompt assembly for agent sessions
+//
+// Sessions grow without bound; the prompt may not. The goal, the preamble
+// (tool definitions, schema) and the most recent messages always go in
+// verbatim. Older observations that are large get shortened to their header
+// line, and if the prompt is still over budget older messages are shortened
+// oldest first. Every shortened message is stored in memory under a handle
+// the agent can read it back from with ReadOutput. Tool outputs too large for a
+// single observation are cut on arrival and stored whole under a handle the
+// agent pages through with ReadOutput.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::core::session::{AgentSession, MessageKind};
+use crate::core::state::ContextConfig;
+use crate::memory::chunker::estimate_tokens;
+use crate::memory::metadata::StoredMetadata;
+use crate::memory::{Correlation, EventQuery, MemoryStore};
+
+/// Event recorded when messages are first shortened in a session's prompt.
+pub const CONTEXT_ELIDED: &str = "context_elided";
+
+/// A message that did not go into the prompt verbatim.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Elided {
+    pub index: usize,
+    pub kind: MessageKind,
+    pub tokens: usize,
+    /// First line of the original, kept in the stub.
+    pub header: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextWindow {
+    pub prompt: String,
+    /// Estimated tokens of `prompt`.
+    pub tokens: usize,
+    pub elided: Vec<Elided>,
+}
+
+// --- Pure Functions ---
+
+/// Assembles the prompt for the next step of `session`.
+pub fn build(session: &AgentSession, preamble: &str, config: &ContextConfig) -> ContextWindow {
+    let messages = &session.messages;
+    let recent_from = messages.len().saturating_sub(config.keep_recent);
+    // The goal: the first prompt, never shortened.
+    let goal = messages.iter().position(|m| m.kind == MessageKind::Prompt);
+    let protected = |i: usize| Some(i) == goal || i >= recent_from;
+
+    let mut shortened: Vec<bool> = messages.iter().enumerate()
+        .map(|(i, m)| !protected(i) && is_observation(m.kind) && m.tokens > config.elide_over_tokens)
+        .collect();
+
+    let stub_of = |i: usize| stub(&messages[i].content, messages[i].tokens, &elided_memory_id(&session.id, i));
+    let mut current: Vec<String> = messages.iter().enumerate()
+        .map(|(i, m)| if shortened[i] { stub_of(i) } else { m.content.clone() })
+        .collect();
+    // Kept up to date as messages are shortened, so long sessions stay linear.
+    let mut total = current.iter().map(|p| estimate_tokens(p)).sum::<usize>() + estimate_tokens(preamble);
+
+    // Over budget: shorten older messages, oldest first, then the recent
+    // ones except the last. The goal always stays.
+    let last = messages.len().saturating_sub(1);
+    let order: Vec<usize> = (0..recent_from).chain(recent_from..last).filter(|i| Some(*i) != goal).collect();
+    for i in order {
+        if total <= config.max_tokens {
+            break;
+        }
+        let short = stub_of(i);
+        if !shortened[i] && estimate_tokens(&short) < messages[i].tokens {
+            total = total - estimate_tokens(&current[i]) + estimate_tokens(&short);
+            shortened[i] = true;
+            current[i] = short;
+        }
+    }
+
+    let elided: Vec<Elided> = messages.iter().enumerate()
+        .filter(|(i, _)| shortened[*i])
+        .map(|(i, m)| Elided { index: i, kind: m.kind, tokens: m.tokens, header: header(&m.content) })
+        .collect();
+    if !elided.is_empty() {
+        let notice = format!(
+            "_({} earlier message(s) were shortened to fit the context window. Each keeps its first line \
+             and the ReadOutput handle to read it in full.)_",
+            elided.len()
+        );
+        let at = goal.map(|g| g + 1).unwrap_or(0);
+        current.insert(at, notice);
+    }
+
+    let mut prompt = current.join("\n\n");
+    if !preamble.is_empty() {
+        prompt = format!("{}\n\n{}", prompt, preamble);
+    }
+    ContextWindow { tokens: estimate_tokens(&prompt), prompt, elided }
+}
+
+/// Stable memory id for the full text of an elided message.
+pub fn elided_memory_id(session_id: &str, index: usize) -> String {
+    format!("elided:{}:{}", session_id, index)
+}
+
+/// Memory id (the ReadOutput handle) of the full output shown, cut, as
+/// message `index` of a session.
+pub fn output_handle(session_id: &str, index: usize) -> String {
+    format!("output:{}:{}", session_id, index)
+}
+
+/// About `max_tokens` of `text` from byte `offset`, ending at a line break
+/// when there is one in the second half. Returns the page and the offset of
+/// the next one (`None` at the end).
+pub fn output_page(text: &str, offset: usize, max_tokens: usize) -> (&str, Option<usize>) {
+    let start = floor_char_boundary(text, offset.min(text.len()));
+    let limit = start + max_tokens.max(1) * 4;
+    if limit >= text.len() {
+        return (&text[start..], None);
+    }
+    // At least four bytes past `start`, so a page always holds a character
+    let mut end = floor_char_boundary(text, limit);
+    if let Some(newline) = text[start..end].rfind('\n').filter(|n| *n > (end - start) / 2) {
+        end = start + newline + 1;
+    }
+    (&text[start..end], Some(end))
+}
+
+/// The line appended to a cut page, telling the agent how to read on.
+pub fn continuation(handle: &str, next: usize, total_tokens: usize) -> String {
+    format!(
+        "[… cut at byte {} of ~{} tokens; read on with {{\"directive\": \"ReadOutput\", \"handle\": \"{}\", \"offset\": {}}}]",
+        next, total_tokens, handle, next
+    )
+}
+
+fn floor_char_boundary(text: &str, mut i: usize) -> usize {
+    while !text.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+fn is_observation(kind: MessageKind) -> bool {
+    matches!(kind, MessageKind::Observation | MessageKind::Tool)
+}
+
+fn header(content: &str) -> String {
+    content.lines().find(|l| !l.trim().is_empty()).unwrap_or_default().chars().take(160).collect()
+}
+
+fn stub(content: &str, tokens: usize, handle: &str) -> String {
+    format!(
+        "{}\n[… {} tokens elided; read it in full with {{\"directive\": \"ReadOutput\", \"handle\": \"{}\", \"offset\": 0}}]",
+        header(content), tokens, handle
+    )
+}
+
+// --- IO ---
+
+/// Stores the full text of messages shortened for the first time and
+/// records which ones they were. Returns how many were new.
+pub async fn remember_elided(memory: &dyn MemoryStore, session: &AgentSession, window: &ContextWindow) -> Result<usize> {
+    if window.elided.is_empty() {
+        return Ok(0);
+    }
+    let query = EventQuery {
+        ops: vec![CONTEXT_ELIDED.to_string()],
+        session_id: Some(session.id.clone()),
+        limit: 10_000,
+        ..Default::default()
+    };
+    let known: HashSet<u64> = memory.query_events(&query).await?.iter()
+        .flat_map(|e| e.data["indices"].as_array().cloned().unwrap_or_default())
+        .filter_map(|i| i.as_u64())
+        .collect();
+
+    let fresh: Vec<&Elided> = window.elided.iter().filter(|e| !known.contains(&(e.index as u64))).collect();
+    if fresh.is_empty() {
+        return Ok(0);
+    }
+    let now = chrono::Utc::now().timestamp_millis();
+    for elided in &fresh {
+        let meta = StoredMetadata {
+            kind: "elided".to_string(),
+            source_session: Some(session.id.clone()),
+            tags: vec!["context".to_string(), elided.kind.as_str().to_string()],
+            confidence: 1.0,
+            expires_at: None,
+            created_at: now,
+        };
+        let content = &session.messages[elided.index].content;
+        memory.restore_memory(&elided_memory_id(&session.id, elided.index), content, &meta).await?;
+    }
+
+    let data = serde_json::json!({
+        "indices": fresh.iter().map(|e| e.index).collect::<Vec<_>>(),
+        "tokens": fresh.iter().map(|e| e.tokens).sum::<usize>(),
+        "prompt_tokens": window.tokens
+    });
+    let correlation = Correlation { session_id: Some(session.id.clone()), directive_id: None };
+    memory.record_correlated(CONTEXT_ELIDED, data, &correlation)?;
+    Ok(fresh.len())
+}
+
+/// What goes into the session for a tool output: `text` itself when it
+/// fits, else its first page plus a continuation line, the whole output
+/// stored under `output_handle` for ReadOutput.
+pub async fn fit_output(memory: &dyn MemoryStore, session: &AgentSession, tool: &str, text: &str, max_tokens: usize) -> Result<String> {
+    let (page, next) = output_page(text, 0, max_tokens);
+    let Some(next) = next else {
+        return Ok(text.to_string());
+    };
+    let handle = output_handle(&session.id, session.messages.len());
+    let meta = StoredMetadata {
+        kind: "tool_output".to_string(),
+        source_session: Some(session.id.clone()),
+        tags: vec!["tool_output".to_string(), tool.to_string()],
+        confidence: 1.0,
+        expires_at: None,
+        created_at: chrono::Utc::now().timestamp_millis(),
+    };
+    memory.restore_memory(&handle, text, &meta).await?;
+    Ok(format!("{}\n{}", page.trim_end(), continuation(&handle, next, estimate_tokens(text))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::SessionMessage;
+    use crate::memory::{GraphStore, InMemoryStore};
+
+    fn long_session() -> AgentSession {
+        let big = format!("**Observation (Shell 'cargo build'):**\n{}", "error: x\n".repeat(2_000));
+        AgentSession::new("Fix the build".to_string())
+            .with_message(SessionMessage::response("**Sly (Step 0):**\nrun cargo".to_string()))
+            .with_message(SessionMessage::observation(big))
+            .with_message(SessionMessage::response("**Sly (Step 1):**\nlook at errors".to_string()))
+            .with_message(SessionMessage::observation("**Observation:** ok".to_string()))
+    }
+
+    #[test]
+    fn test_large_old_observations_are_shortened() {
+        let config = ContextConfig { keep_recent: 2, ..Default::default() };
+        let window = build(&long_session(), "## TOOLS", &config);
+        assert_eq!(window.elided.len(), 1);
+        assert_eq!(window.elided[0].index, 2);
+        assert!(window.prompt.starts_with("Fix the build"));
+        assert!(window.prompt.contains("**Observation (Shell 'cargo build'):**\n[… "));
+        assert!(window.prompt.ends_with("## TOOLS"));
+
+        // Keeping everything recent leaves the prompt untouched when it fits.
+        let roomy = ContextConfig { keep_recent: 10, ..Default::default() };
+        assert!(build(&long_session(), "", &roomy).elided.is_empty());
+
+        // A tight budget reaches into the recent messages too, but only
+        // shortens what a stub would make smaller.
+        let tight = ContextConfig { max_tokens: 50, keep_recent: 10, elide_over_tokens: 100_000, ..Default::default() };
+        let indices: Vec<usize> = build(&long_session(), "", &tight).elided.iter().map(|e| e.index).collect();
+        assert_eq!(indices, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_large_tool_output_is_paged() {
+        let memory = InMemoryStore::new();
+        let session = long_session();
+        assert_eq!(fit_output(&memory, &session, "logs", "short", 10).await.unwrap(), "short");
+
+        let text = "line one\nline two\nline three é\n".repeat(20);
+        let shown = fit_output(&memory, &session, "logs", &text, 10).await.unwrap();
+        let handle = output_handle(&session.id, session.messages.len());
+        assert!(shown.starts_with("line one\nline two\nline three é\n[… cut at byte 32 of"));
+        assert!(shown.contains(&format!("\"handle\": \"{}\", \"offset\": 32}}", handle)));
+
+        let stored = memory.get_memory(&handle).await.unwrap().unwrap();
+        let mut pages = Vec::new();
+        let mut offset = Some(0);
+        while let Some(at) = offset {
+            let (page, next) = output_page(&stored, at, 10);
+            pages.push(page);
+            offset = next;
+        }
+        assert_eq!(pages.concat(), text);
+    }
+
+    #[tokio::test]
+    async fn test_elided_messages_are_stored_once() {
+        let memory = InMemoryStore::new();
+        let session = long_session();
+        let window = build(&session, "", &ContextConfig { keep_recent: 2, ..Default::default() });
+        assert_eq!(remember_elided(&memory, &session, &window).await.unwrap(), 1);
+        assert_eq!(remember_elided(&memory, &session, &window).await.unwrap(), 0);
+        let stored = memory.stored_memories().await.unwrap();
+        assert_eq!(stored[0].0, elided_memory_id(&session.id, 2));
+
+        // The stub names the handle ReadOutput reads the full message from.
+        let handle = elided_memory_id(&session.id, 2);
+        assert!(window.prompt.contains(&format!("\"handle\": \"{}\", \"offset\": 0}}", handle)));
+        assert_eq!(memory.get_memory(&handle).await.unwrap().as_deref(), Some(session.messages[2].content.as_str()));
+    }
+}
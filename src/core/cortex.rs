@@ -63,6 +63,7 @@ You communicate exclusively via structured JSON directives.
   "strategy": "GraphExpand"
 }
 ```
+Strategies: `GraphExpand` (follow edges two hops), `Rerank` (cross-encoder precision pass), `Fast` (skip re-ranking).
 
 **4. Advanced Logic (Datalog)**
 Use this for structural graph queries. **TABLE: `nodes { id => content, type, path, embedding }`**
@@ -142,6 +143,14 @@ impl ThinkingLevel {
     }
 }
 
+/// Image data sent along with a prompt (e.g. an MCP tool's screenshot).
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineImage {
+    pub mime_type: String,
+    /// Base64, as MCP delivers it.
+    pub data: String,
+}
+
 pub struct Cortex {
     pub api_key: String,
     pub client: reqwest::Client,
@@ -203,6 +212,14 @@ impl Cortex {
     }
 
     pub async fn generate(&self, prompt: &str, level: ThinkingLevel) -> Result<String> {
+        self.generate_with_images(prompt, &[], level).await
+    }
+
+    /// Like `generate`, with images after the prompt text.
+    pub async fn generate_with_images(&self, prompt: &str, images: &[InlineImage], level: ThinkingLevel) -> Result<String> {
+        let mut parts = vec![json!({ "text": prompt })];
+        parts.extend(images.iter().map(|image| json!({ "inlineData": { "mimeType": image.mime_type, "data": image.data } })));
+
         // Gemini 3 Flash (Primary) with Thinking Config
         let primary_result = async {
             let model = "gemini-3-flash-preview";
@@ -225,7 +242,7 @@ impl Cortex {
                 "systemInstruction": {
                     "parts": [{ "text": dynamic_prompt }]
                 },
-                "contents": [{"parts": [{"text": prompt}]}],
+                "contents": [{"parts": parts.clone()}],
                 "generationConfig": generation_config
             });
 
@@ -259,7 +276,7 @@ impl Cortex {
             "systemInstruction": {
                 "parts": [{ "text": SYSTEM_PROMPT }]
             },
-            "contents": [{"parts": [{"text": prompt}]}]
+            "contents": [{"parts": parts}]
         });
 
         let res = self.client.post(&url_fallback)
@@ -296,6 +313,46 @@ impl Cortex {
         extract_text(&body).context("No text in response")
     }
 
+    /// A completion for someone else's conversation (MCP sampling): their
+    /// system prompt instead of Sly's, `turns` as (role, text) with role
+    /// "user" or "assistant", capped at `max_tokens`.
+    pub async fn complete(&self, system: Option<&str>, turns: &[(String, String)], max_tokens: u32, temperature: Option<f64>) -> Result<String> {
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
+            self.config.primary_model
+        );
+
+        let contents: Vec<Value> = turns.iter()
+            .map(|(role, text)| json!({
+                "role": if role == "assistant" { "model" } else { "user" },
+                "parts": [{ "text": text }]
+            }))
+            .collect();
+        let mut payload = json!({
+            "contents": contents,
+            "generationConfig": { "maxOutputTokens": max_tokens }
+        });
+        if let Some(system) = system {
+            payload["systemInstruction"] = json!({ "parts": [{ "text": system }] });
+        }
+        if let Some(temperature) = temperature {
+            payload["generationConfig"]["temperature"] = json!(temperature);
+        }
+
+        let res = self.client.post(&url)
+            .header("x-goog-api-key", &self.api_key)
+            .json(&payload)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let err_text = res.text().await.unwrap_or_default();
+            return Err(anyhow!("Completion failed. Status: {}, Body: {}", status, err_text));
+        }
+        let body: Value = res.json().await?;
+        extract_text(&body).context("No text in response")
+    }
+
     pub async fn conduct_debate(&self, topic: &str, context: &str) -> Result<DebateSynthesis> {
         println!(
             "{}",
@@ -2,6 +2,8 @@ use serde_json::Value;
 
 #[derive(Debug, Clone)]
 pub struct Directive {
+    /// Correlates the events this directive causes.
+    pub id: String,
     pub type_name: String,
     pub payload: Value,
 }
@@ -9,6 +11,7 @@ pub struct Directive {
 impl Directive {
     pub fn new(type_name: &str, payload: Value) -> Self {
         Self {
+            id: uuid::Uuid::new_v4().to_string(),
             type_name: type_name.to_string(),
             payload,
         }
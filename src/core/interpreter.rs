@@ -3,6 +3,7 @@ use crate::core::directives::Directive;
 use crate::core::state::GlobalState;
 use crate::core::agent;
 use crate::core::bus::DirectiveHandler;
+use crate::memory::Correlation;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde_json::Value;
@@ -12,17 +13,24 @@ pub struct DirectiveInterpreter;
 
 impl DirectiveInterpreter {
     pub async fn interpret(directive: Directive, state: Arc<GlobalState>) {
+        let correlation = Correlation {
+            session_id: directive.payload["session_id"].as_str().map(str::to_string),
+            directive_id: Some(directive.id.clone()),
+        };
+
         // Record INTENT (Hickey: Data as the Truth)
-        let _ = state.memory_raw.record_event(
+        let _ = state.memory.record_correlated(
             &format!("EXEC:{}", directive.type_name), 
-            directive.payload.clone()
+            directive.payload.clone(),
+            &correlation
         );
 
         if let Err(e) = state.bus.dispatch(&directive.type_name, directive.payload, state.clone()).await {
             eprintln!("{} Directive dispatch failed: {}", "⚠️".red(), e);
-            let _ = state.memory_raw.record_event(
+            let _ = state.memory.record_correlated(
                 &format!("ERROR:{}", directive.type_name),
-                serde_json::json!({ "error": e.to_string() })
+                serde_json::json!({ "error": e.to_string() }),
+                &correlation
             );
         }
     }
@@ -36,6 +44,7 @@ impl DirectiveInterpreter {
         bus.register("fs_batch", FsBatchHandler).await;
         bus.register("bootstrap_skills", BootstrapSkillsHandler).await;
         bus.register("propose_plan", ProposePlanHandler).await;
+        bus.register("maintenance", MaintenanceHandler).await;
         bus.register("shutdown", ShutdownHandler).await;
     }
 }
@@ -47,17 +56,19 @@ impl DirectiveHandler for InitiateSessionHandler {
         let input = data["input"].as_str().unwrap_or_default().to_string();
         let session = crate::core::session::AgentSession::new(input);
         let session_id = session.id.clone();
-        state.memory_raw.create_session(&session).await?;
+        state.memory.create_session(&session).await?;
         println!("{} Persistent Session Initiated: {}", "🔋".green(), session_id);
         
+        let overlay = state.overlay_for(&session_id);
         // Unbundled call
         agent::step_agent_session(
             session_id, 
-            state.memory_raw.clone(),
+            state.memory.clone(),
             state.cortex.clone(),
-            state.mcp_clients.clone(),
-            state.overlay.clone(),
-            state.config.max_autonomous_loops
+            state.mcp_tools.clone(),
+            overlay,
+            state.config.max_autonomous_loops,
+            &state.config.context
         ).await;
         Ok(())
     }
@@ -69,14 +80,16 @@ impl DirectiveHandler for ThinkHandler {
     async fn handle(&self, data: Value, state: Arc<GlobalState>) -> Result<()> {
         let session_id = data["session_id"].as_str().unwrap_or_default().to_string();
         
+        let overlay = state.overlay_for(&session_id);
         // Unbundled call
         agent::step_agent_session(
             session_id, 
-            state.memory_raw.clone(),
+            state.memory.clone(),
             state.cortex.clone(),
-            state.mcp_clients.clone(),
-            state.overlay.clone(),
-            state.config.max_autonomous_loops
+            state.mcp_tools.clone(),
+            overlay,
+            state.config.max_autonomous_loops,
+            &state.config.context
         ).await;
         Ok(())
     }
@@ -88,18 +101,20 @@ impl DirectiveHandler for ObserveHandler {
     async fn handle(&self, data: Value, state: Arc<GlobalState>) -> Result<()> {
         let session_id = data["session_id"].as_str().unwrap_or_default().to_string();
         let observation = data["observation"].as_str().unwrap_or_default().to_string();
-        if let Ok(Some(session)) = state.memory_raw.get_session(&session_id).await {
-            let session = session.with_message(observation);
-            state.memory_raw.update_session(&session).await?;
+        if let Ok(Some(session)) = state.memory.get_session(&session_id).await {
+            let session = session.with_message(crate::core::session::SessionMessage::observation(observation));
+            state.memory.update_session(&session).await?;
             
+            let overlay = state.overlay_for(&session_id);
             // Unbundled call
             agent::step_agent_session(
                 session_id, 
-                state.memory_raw.clone(),
+                state.memory.clone(),
                 state.cortex.clone(),
-                state.mcp_clients.clone(),
-                state.overlay.clone(),
-                state.config.max_autonomous_loops
+                state.mcp_tools.clone(),
+                overlay,
+                state.config.max_autonomous_loops,
+                &state.config.context
             ).await;
         }
         Ok(())
@@ -113,7 +128,7 @@ impl DirectiveHandler for IngestFileHandler {
         let path_str = data["path"].as_str().unwrap_or_default();
         let path = std::path::PathBuf::from(path_str);
         println!("{} Executing Ingest Directive: {:?}", "📝".blue(), path);
-        crate::knowledge::ingest_file(&state.memory_raw, &path).await?;
+        crate::knowledge::ingest_file(state.memory.as_ref(), &path).await?;
         Ok(())
     }
 }
@@ -131,7 +146,7 @@ impl DirectiveHandler for FsBatchHandler {
         
         if !paths.is_empty() {
             println!("{} Executing Batch Ingest Directive: {} paths", "📝".blue(), paths.len());
-            crate::knowledge::ingest_batch(&state.memory_raw, &paths).await?;
+            crate::knowledge::ingest_batch(state.memory.as_ref(), &paths).await?;
         }
         Ok(())
     }
@@ -141,7 +156,7 @@ struct BootstrapSkillsHandler;
 #[async_trait]
 impl DirectiveHandler for BootstrapSkillsHandler {
     async fn handle(&self, _data: Value, state: Arc<GlobalState>) -> Result<()> {
-        crate::knowledge::ensure_skills_loaded(&state.memory_raw).await?;
+        crate::knowledge::ensure_skills_loaded(state.memory.as_ref()).await?;
         println!("{} Skills DB Loaded (Data-Driven Bus)", "📦".purple());
         Ok(())
     }
@@ -156,6 +171,26 @@ impl DirectiveHandler for ProposePlanHandler {
     }
 }
 
+struct MaintenanceHandler;
+#[async_trait]
+impl DirectiveHandler for MaintenanceHandler {
+    async fn handle(&self, _data: Value, state: Arc<GlobalState>) -> Result<()> {
+        let purged = state.memory.purge_expired().await?;
+        if purged > 0 {
+            println!("{} Janitor purged {} expired memories", "🧹".blue(), purged);
+        }
+        let report = state.memory.apply_retention(&state.config.events, chrono::Utc::now().timestamp_millis()).await?;
+        if report.expired > 0 || report.compacted > 0 {
+            println!("{} Janitor expired {} events, compacted {} into {} summaries", "🧹".blue(), report.expired, report.compacted, report.summaries);
+        }
+        let evicted = state.memory.prune_embedding_cache(&state.config.embedding, chrono::Utc::now().timestamp())?;
+        if evicted > 0 {
+            println!("{} Janitor evicted {} cached embeddings", "🧹".blue(), evicted);
+        }
+        Ok(())
+    }
+}
+
 struct ShutdownHandler;
 #[async_trait]
 impl DirectiveHandler for ShutdownHandler {
@@ -7,5 +7,6 @@ pub mod agent;
 pub mod directives;
 pub mod interpreter;
 pub mod session;
+pub mod context;
 pub mod fs;
 pub mod supervisor;
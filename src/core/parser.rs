@@ -11,6 +11,24 @@ pub enum AgentAction {
 
     CommitOverlay { message: String },
     CallTool { tool_name: String, arguments: Value },
+    ReadResource {
+        uri: String,
+        #[serde(default)]
+        server: Option<String>,
+        #[serde(default)]
+        subscribe: bool,
+    },
+    GetPrompt {
+        name: String,
+        #[serde(default)]
+        arguments: Value,
+    },
+    /// Pages through a tool output that was cut (see `context::fit_output`).
+    ReadOutput {
+        handle: String,
+        #[serde(default)]
+        offset: usize,
+    },
     UseSkill { name: String, args: Vec<i32> },
     QueryDatalog { script: String },
     // Fallback for straight text or analysis
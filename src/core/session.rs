@@ -1,10 +1,15 @@
+use anyhow::{anyhow, Result};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::memory::{replay, Correlation, EventQuery, MemoryStore};
+use crate::safety::overlay::{session_overlay_id, OverlayFS};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AgentSession {
     pub id: String,
-    pub messages: Vec<String>,
+    pub messages: Vec<SessionMessage>,
     pub depth: usize,
     pub status: SessionStatus,
 }
@@ -18,17 +23,79 @@ pub enum SessionStatus {
     Error(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+    Tool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// What the user asked for.
+    Prompt,
+    /// Model output for one step.
+    Response,
+    /// Result of an action the agent took (file write, shell, memory query).
+    Observation,
+    /// Output of an MCP tool call.
+    Tool,
+}
+
+/// One entry of a session transcript.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredMessage")]
+pub struct SessionMessage {
+    pub role: Role,
+    pub kind: MessageKind,
+    pub content: String,
+    /// Milliseconds since the epoch; 0 for messages written before v7.
+    pub timestamp: i64,
+    /// Estimated, see `chunker::estimate_tokens`.
+    pub tokens: usize,
+}
+
+/// Messages used to be bare strings; both shapes are still read.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMessage {
+    Typed {
+        role: Role,
+        kind: MessageKind,
+        content: String,
+        #[serde(default)]
+        timestamp: i64,
+        #[serde(default)]
+        tokens: usize,
+    },
+    Legacy(String),
+}
+
+impl From<StoredMessage> for SessionMessage {
+    fn from(stored: StoredMessage) -> Self {
+        match stored {
+            StoredMessage::Typed { role, kind, content, timestamp, tokens } => Self { role, kind, content, timestamp, tokens },
+            StoredMessage::Legacy(content) => {
+                let kind = MessageKind::infer(&content);
+                Self { role: kind.role(), kind, tokens: crate::memory::chunker::estimate_tokens(&content), content, timestamp: 0 }
+            }
+        }
+    }
+}
+
 impl AgentSession {
     pub fn new(initial_prompt: String) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
-            messages: vec![initial_prompt],
+            messages: vec![SessionMessage::new(MessageKind::Prompt, initial_prompt)],
             depth: 0,
             status: SessionStatus::Idle,
         }
     }
 
-    pub fn with_message(mut self, msg: String) -> Self {
+    pub fn with_message(mut self, msg: SessionMessage) -> Self {
         self.messages.push(msg);
         self
     }
@@ -42,4 +109,248 @@ impl AgentSession {
         self.status = status;
         self
     }
+
+    /// Message contents in order, as the model sees them.
+    pub fn transcript(&self) -> String {
+        self.messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>().join("\n\n")
+    }
+
+    /// A copy under a new id, idle. With `at`, history stops after step
+    /// `at` (its response and the observations that followed it).
+    pub fn fork(&self, at: Option<usize>) -> Self {
+        let keep = at
+            .and_then(|step| {
+                self.messages.iter()
+                    .enumerate()
+                    .filter(|(_, m)| m.kind == MessageKind::Response)
+                    .nth(step + 1)
+                    .map(|(i, _)| i)
+            })
+            .unwrap_or(self.messages.len());
+        let messages = self.messages[..keep].to_vec();
+        let depth = match at {
+            Some(_) => messages.iter().filter(|m| m.kind == MessageKind::Response).count(),
+            None => self.depth,
+        };
+        Self { id: Uuid::new_v4().to_string(), messages, depth, status: SessionStatus::Idle }
+    }
+
+    /// Time of the first message a fork of `self` left out, when known.
+    pub fn fork_cutoff(&self, fork: &AgentSession) -> Option<i64> {
+        self.messages.get(fork.messages.len()).map(|m| m.timestamp).filter(|t| *t > 0)
+    }
+
+    /// Readable transcript for `sly session export --format md`.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# Session {}\n\n- Status: {}\n- Steps: {}\n- Messages: {}\n",
+            self.id, self.status.name(), self.depth, self.messages.len());
+        if let Some(error) = self.status.error() {
+            out.push_str(&format!("- Error: {}\n", error));
+        }
+        for (i, message) in self.messages.iter().enumerate() {
+            let at = chrono::DateTime::from_timestamp_millis(message.timestamp)
+                .filter(|_| message.timestamp > 0)
+                .map(|t| format!(", {}", t.format("%Y-%m-%d %H:%M:%S")))
+                .unwrap_or_default();
+            out.push_str(&format!("\n## {}. {} ({}, ~{} tokens{})\n\n{}\n",
+                i + 1, message.kind.as_str(), message.role.as_str(), message.tokens, at, message.content.trim_end()));
+        }
+        out
+    }
+}
+
+impl SessionMessage {
+    pub fn new(kind: MessageKind, content: String) -> Self {
+        Self {
+            role: kind.role(),
+            kind,
+            tokens: crate::memory::chunker::estimate_tokens(&content),
+            content,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    pub fn response(content: String) -> Self {
+        Self::new(MessageKind::Response, content)
+    }
+
+    pub fn observation(content: String) -> Self {
+        Self::new(MessageKind::Observation, content)
+    }
+
+    pub fn tool(content: String) -> Self {
+        Self::new(MessageKind::Tool, content)
+    }
+}
+
+// --- Pure Functions ---
+
+impl MessageKind {
+    pub fn role(self) -> Role {
+        match self {
+            MessageKind::Prompt | MessageKind::Observation => Role::User,
+            MessageKind::Response => Role::Assistant,
+            MessageKind::Tool => Role::Tool,
+        }
+    }
+
+    /// Best guess for untyped messages, from the prefixes the agent writes.
+    /// Mirrored by the v7 migration of `session_messages`.
+    pub fn infer(content: &str) -> Self {
+        if content.starts_with("**Sly") {
+            MessageKind::Response
+        } else if content.starts_with("**Observation (Tool") {
+            MessageKind::Tool
+        } else if content.starts_with("**Observation") {
+            MessageKind::Observation
+        } else {
+            MessageKind::Prompt
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageKind::Prompt => "prompt",
+            MessageKind::Response => "response",
+            MessageKind::Observation => "observation",
+            MessageKind::Tool => "tool",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "prompt" => Some(MessageKind::Prompt),
+            "response" => Some(MessageKind::Response),
+            "observation" => Some(MessageKind::Observation),
+            "tool" => Some(MessageKind::Tool),
+            _ => None,
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Role::User),
+            "assistant" => Some(Role::Assistant),
+            "tool" => Some(Role::Tool),
+            _ => None,
+        }
+    }
+}
+
+impl SessionStatus {
+    /// Variant name, as stored in `sessions.status`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionStatus::Idle => "Idle",
+            SessionStatus::Thinking => "Thinking",
+            SessionStatus::AwaitingObservation => "AwaitingObservation",
+            SessionStatus::Completed => "Completed",
+            SessionStatus::Error(_) => "Error",
+        }
+    }
+
+    /// The message of an `Error`, stored in `sessions.error`.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            SessionStatus::Error(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Inverse of `name` and `error`. Unknown names read as `Idle`.
+    pub fn from_parts(name: &str, error: Option<&str>) -> Self {
+        match name {
+            "Thinking" => SessionStatus::Thinking,
+            "AwaitingObservation" => SessionStatus::AwaitingObservation,
+            "Completed" => SessionStatus::Completed,
+            "Error" => SessionStatus::Error(error.unwrap_or_default().to_string()),
+            _ => SessionStatus::Idle,
+        }
+    }
+}
+
+// --- IO ---
+
+/// Forks session `id` into a new stored session. The fork gets its own
+/// overlay, seeded with the files the parent had written but not committed
+/// up to the fork point (rebuilt from the parent's correlated events); the
+/// seeded files are logged as the fork's own writes.
+pub async fn fork(store: &dyn MemoryStore, base_dir: &Path, id: &str, at: Option<usize>) -> Result<AgentSession> {
+    let parent = store.get_session(id).await?.ok_or_else(|| anyhow!("No session with id {}", id))?;
+    let child = parent.fork(at);
+    store.create_session(&child).await?;
+
+    let query = EventQuery {
+        ops: vec![replay::OVERLAY_WRITE.to_string(), replay::OVERLAY_COMMIT.to_string()],
+        session_id: Some(parent.id.clone()),
+        until: parent.fork_cutoff(&child),
+        limit: 10_000,
+        ..Default::default()
+    };
+    let (history, _) = replay::ReplayState::rebuild(&store.query_events(&query).await?);
+    let pending: Vec<(&String, &String)> = history.overlay_files()
+        .filter(|(_, _, f)| !f.committed)
+        .map(|(_, path, f)| (path, &f.content))
+        .collect();
+
+    // Recorded first, so the seeded writes below replay into the fork's overlay
+    let correlation = Correlation { session_id: Some(child.id.clone()), directive_id: None };
+    store.record_correlated(replay::SESSION_FORKED, serde_json::json!({ "from": parent.id, "at": at, "files": pending.len() }), &correlation)?;
+    let overlay = OverlayFS::open(base_dir, &session_overlay_id(&child.id))?;
+    for (path, content) in pending {
+        overlay.write_file(Path::new(path), content)?;
+        store.record_correlated(replay::OVERLAY_WRITE, serde_json::json!({ "path": path, "content": content }), &correlation)?;
+    }
+    Ok(child)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_string_messages_still_deserialize() {
+        let session: AgentSession = serde_json::from_value(serde_json::json!({
+            "id": "s1",
+            "messages": ["fix the build", "**Sly (Step 0):**\nok", { "role": "tool", "kind": "tool", "content": "{}" }],
+            "depth": 1,
+            "status": { "Error": "boom" }
+        })).unwrap();
+        let kinds: Vec<MessageKind> = session.messages.iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, vec![MessageKind::Prompt, MessageKind::Response, MessageKind::Tool]);
+        assert_eq!(session.messages[1].role, Role::Assistant);
+
+        let status = SessionStatus::from_parts(session.status.name(), session.status.error());
+        assert_eq!(status, SessionStatus::Error("boom".to_string()));
+    }
+
+    #[test]
+    fn test_fork_at_step() {
+        let session = AgentSession::new("goal".to_string())
+            .with_message(SessionMessage::response("step 0".to_string()))
+            .with_message(SessionMessage::observation("saw 0".to_string()))
+            .with_message(SessionMessage::response("step 1".to_string()))
+            .with_depth_increment()
+            .with_depth_increment()
+            .with_status(SessionStatus::Completed);
+
+        let fork = session.fork(Some(0));
+        assert_ne!(fork.id, session.id);
+        assert_eq!(fork.messages.len(), 3);
+        assert_eq!((fork.depth, fork.status.clone()), (1, SessionStatus::Idle));
+        assert_eq!(session.fork_cutoff(&fork), Some(session.messages[3].timestamp));
+
+        assert_eq!(session.fork(None).messages, session.messages);
+        assert!(session.to_markdown().contains("## 4. response (assistant"));
+    }
 }
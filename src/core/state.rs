@@ -4,6 +4,8 @@ use crate::memory::MemoryStore;
 use crate::safety::OverlayFS;
 use super::cortex::Cortex;
 use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::{Context, Result};
 
 
 use serde::{Deserialize, Serialize};
@@ -31,12 +33,254 @@ pub struct SlyConfig {
     #[serde(default)]
     pub mcp_servers: HashMap<String, McpServerConfig>,
     pub telegram_chat_id: Option<i64>,
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+    #[serde(default)]
+    pub rerank: RerankConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub mcp_sampling: McpSamplingConfig,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// An MCP server: a `command` spawned over stdio, or a `url` of one
+/// running as a service.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct McpServerConfig {
+    #[serde(default)]
     pub command: String,
+    #[serde(default)]
     pub args: Vec<String>,
+    pub url: Option<String>,
+    /// With `url`: "http" (streamable HTTP, the default) or "sse" (legacy).
+    pub transport: Option<String>,
+    /// Extra request headers for `url` servers.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub auth_token: Option<String>,
+    /// Environment of a `command` server. Values, like `headers` and
+    /// `auth_token`, may refer to `${VAR}` from the environment or `.env`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Working directory of a `command` server.
+    pub cwd: Option<String>,
+    /// Seconds to wait for the handshake (default 5).
+    pub startup_timeout_secs: Option<u64>,
+    /// Seconds to wait for each request, tool calls included (default 60).
+    pub call_timeout_secs: Option<u64>,
+    /// Keeps the server configured without starting it.
+    #[serde(default)]
+    pub disabled: bool,
+    /// Tools the agent sees; empty means all. `name*` matches a prefix.
+    #[serde(default)]
+    pub allow_tools: Vec<String>,
+    /// Tools hidden from the agent even when allowed.
+    #[serde(default)]
+    pub deny_tools: Vec<String>,
+    /// Holds each tool call until approved with `sly mcp approve`.
+    #[serde(default)]
+    pub require_approval: bool,
+    /// Tools that run without approval even with `require_approval`.
+    #[serde(default)]
+    pub auto_approve: Vec<String>,
+}
+
+impl McpServerConfig {
+    /// Where the server lives, for log lines.
+    pub fn endpoint(&self) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => self.command.clone(),
+        }
+    }
+
+    pub fn startup_timeout(&self) -> Duration {
+        self.startup_timeout_secs.map_or(MCP_STARTUP_TIMEOUT, Duration::from_secs)
+    }
+
+    pub fn call_timeout(&self) -> Duration {
+        self.call_timeout_secs.map_or(crate::mcp::client::DEFAULT_REQUEST_TIMEOUT, Duration::from_secs)
+    }
+
+    /// `env`, `headers` and `auth_token` with `${VAR}` replaced, looking in
+    /// `.env` too.
+    pub fn expanded(&self) -> Result<McpServerConfig> {
+        dotenvy::dotenv().ok();
+        let lookup = |name: &str| std::env::var(name).ok();
+        let expand_map = |map: &HashMap<String, String>| -> Result<HashMap<String, String>> {
+            map.iter().map(|(k, v)| Ok((k.clone(), expand_vars(v, lookup)?))).collect()
+        };
+        Ok(McpServerConfig {
+            env: expand_map(&self.env)?,
+            headers: expand_map(&self.headers)?,
+            auth_token: self.auth_token.as_deref().map(|t| expand_vars(t, lookup)).transpose()?,
+            ..self.clone()
+        })
+    }
+}
+
+const MCP_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Replaces each `${VAR}` in `value` by `lookup(VAR)`; an unset variable
+/// is an error rather than an empty string.
+pub fn expand_vars(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').map(|i| start + i).with_context(|| format!("Unclosed ${{ in '{}'", value))?;
+        let name = &rest[start + 2..end];
+        out.push_str(&rest[..start]);
+        out.push_str(&lookup(name).with_context(|| format!("Environment variable {} is not set", name))?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Whether MCP servers may ask Sly's model for completions
+/// (`sampling/createMessage`). Off unless enabled.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct McpSamplingConfig {
+    pub enabled: bool,
+    /// Servers allowed to sample; empty allows every configured server.
+    pub servers: Vec<String>,
+    /// Upper bound on a request's `maxTokens`.
+    pub max_tokens: u32,
+    /// Requests each server may make per minute.
+    pub per_minute: usize,
+}
+
+impl Default for McpSamplingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            servers: Vec::new(),
+            max_tokens: 1024,
+            per_minute: 10,
+        }
+    }
+}
+
+/// Bi-encoder used for every stored vector. Changing `model` re-embeds the
+/// store on the next start; the vector width follows the model's config.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    /// Hub id or a local directory with config.json, tokenizer.json and
+    /// model.safetensors.
+    pub model: String,
+    /// Never hit the network; Hub ids must already be in the HF cache.
+    pub offline: bool,
+    /// Cached vectors older than this are evicted. Unset keeps them.
+    pub cache_max_age_days: Option<u64>,
+    /// The oldest cached vectors beyond this many are evicted. Unset keeps them.
+    pub cache_max_rows: Option<usize>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model: "BAAI/bge-small-en-v1.5".to_string(),
+            offline: false,
+            cache_max_age_days: Some(90),
+            cache_max_rows: Some(200_000),
+        }
+    }
+}
+
+/// Cross-encoder re-ranking of retrieval hits. When enabled the model is
+/// loaded at startup and every query is re-ranked unless it opts out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RerankConfig {
+    pub enabled: bool,
+    pub model: String,
+    /// Fused hits handed to the cross-encoder before trimming to the limit.
+    pub candidates: usize,
+    pub batch_size: usize,
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+            candidates: 50,
+            batch_size: 16,
+        }
+    }
+}
+
+/// Event log housekeeping, applied by the maintenance directive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// Delete events older than this. Unset keeps the full history. The
+    /// ops replay and held tool calls depend on (`events::RETAINED_OPS`)
+    /// are kept regardless.
+    pub retention_days: Option<u64>,
+    /// Fold `compact_ops` events older than this into per-day summaries.
+    /// `events::RETAINED_OPS` are never folded.
+    pub compact_after_days: Option<u64>,
+    /// High-volume telemetry ops that carry no state worth replaying.
+    pub compact_ops: Vec<String>,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: None,
+            compact_after_days: Some(7),
+            compact_ops: vec!["store_cache".to_string(), "batch_add_nodes".to_string(), "batch_add_library".to_string()],
+        }
+    }
+}
+
+/// Prompt budget for agent sessions, in estimated tokens.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ContextConfig {
+    pub max_tokens: usize,
+    /// Most recent messages kept verbatim unless the budget forces otherwise.
+    pub keep_recent: usize,
+    /// Older observations larger than this are shortened even under budget.
+    pub elide_over_tokens: usize,
+    /// Tool outputs above this are cut; the rest is paged in with ReadOutput.
+    pub max_tool_output_tokens: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 120_000,
+            keep_recent: 6,
+            elide_over_tokens: 2_000,
+            max_tool_output_tokens: 4_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub engine: StorageEngine,
+}
+
+/// Where memory lives. `memory` keeps nothing across runs and has no
+/// embedding model; recall falls back to keyword overlap.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    #[default]
+    RocksDb,
+    Sqlite,
+    Memory,
 }
 
 impl SlyConfig {
@@ -68,6 +312,12 @@ impl Default for SlyConfig {
             role: SlyRole::Executor,
             mcp_servers: HashMap::new(),
             telegram_chat_id: None,
+            embedding: EmbeddingConfig::default(),
+            rerank: RerankConfig::default(),
+            storage: StorageConfig::default(),
+            events: EventsConfig::default(),
+            context: ContextConfig::default(),
+            mcp_sampling: McpSamplingConfig::default(),
         }
     }
 }
@@ -78,29 +328,57 @@ impl Default for SlyConfig {
 pub struct GlobalState {
     pub config: Arc<SlyConfig>,
     pub memory: Arc<dyn MemoryStore>,
-    pub memory_raw: Arc<crate::memory::Memory>,
     pub overlay: Arc<OverlayFS>,
     pub cortex: Arc<Cortex>,
     pub bus: Arc<crate::core::bus::DirectiveBus>,
     pub mcp_clients: Arc<tokio::sync::Mutex<HashMap<String, Arc<crate::mcp::client::McpClient>>>>,
+    /// Tools of the connected MCP servers, kept current by the servers' notifications.
+    pub mcp_tools: Arc<crate::mcp::registry::ToolRegistry>,
+    /// Reconnects MCP servers that die and tracks their health.
+    pub mcp_supervisor: Arc<crate::mcp::supervisor::McpSupervisor>,
+    /// Overlays of sessions that have their own (forks), by session id.
+    session_overlays: Arc<std::sync::Mutex<HashMap<String, Arc<OverlayFS>>>>,
 }
 
 impl GlobalState {
     pub fn new(
         config: SlyConfig,
         memory: Arc<dyn MemoryStore>,
-        memory_raw: Arc<crate::memory::Memory>,
         overlay: Arc<OverlayFS>,
         cortex: Arc<Cortex>,
     ) -> Self {
+        let mcp_clients = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        let mcp_tools = Arc::new(crate::mcp::registry::ToolRegistry::new());
+        let mcp_host = Arc::new(crate::mcp::host::McpHost::new(&overlay, config.mcp_sampling.clone(), cortex.clone()));
         Self {
             config: Arc::new(config),
             memory,
-            memory_raw,
             overlay,
             cortex,
             bus: Arc::new(crate::core::bus::DirectiveBus::new()),
-            mcp_clients: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            mcp_supervisor: Arc::new(crate::mcp::supervisor::McpSupervisor::new(mcp_clients.clone(), mcp_tools.clone(), mcp_host)),
+            mcp_clients,
+            mcp_tools,
+            session_overlays: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The session's own overlay when it has one, the shared one otherwise.
+    pub fn overlay_for(&self, session_id: &str) -> Arc<OverlayFS> {
+        let overlay_id = crate::safety::overlay::session_overlay_id(session_id);
+        if !crate::safety::overlay::overlay_path(&overlay_id).exists() {
+            return self.overlay.clone();
+        }
+        let mut overlays = self.session_overlays.lock().unwrap();
+        if let Some(overlay) = overlays.get(session_id) {
+            return overlay.clone();
+        }
+        match OverlayFS::open(self.overlay.base_dir(), &overlay_id) {
+            Ok(overlay) => overlays.entry(session_id.to_string()).or_insert(Arc::new(overlay)).clone(),
+            Err(e) => {
+                eprintln!("{} Session overlay unavailable, using the shared one: {}", "⚠️".yellow(), e);
+                self.overlay.clone()
+            }
         }
     }
 
@@ -3,7 +3,8 @@ use std::env;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use crate::memory::Memory;
+use crate::io::query_socket::{self, Endpoint, QueryClient, QueryRequest};
+use crate::mcp::supervisor::{ServerHealth, ServerState};
 use colored::*;
 use crate::io::telegram::TelegramClient;
 use crate::io::telegram::html_escape;
@@ -13,6 +14,8 @@ pub struct Supervisor {
     executor: Arc<Mutex<Option<tokio::process::Child>>>,
     auto_heal: bool,
     last_event_ts: Arc<Mutex<i64>>,
+    /// Decisions made while no agent was running, sent once one is.
+    pending_decisions: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
 }
 
 impl Supervisor {
@@ -22,6 +25,7 @@ impl Supervisor {
             executor: Arc::new(Mutex::new(None)),
             auto_heal: true,
             last_event_ts: Arc::new(Mutex::new(chrono::Utc::now().timestamp_millis())), // No lookback, fresh start
+            pending_decisions: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -85,7 +89,8 @@ impl Supervisor {
             // Priority 2: Process Outbox Fact (Decomplected high-priority telemetry)
             let _ = self.process_outbox(&mut batch).await;
 
-            // Priority 3: Poll Event Log for Telemetry (Light Memory access)
+            // Priority 3: Deliver queued decisions, then poll the Event Log for Telemetry
+            let _ = self.flush_decisions().await;
             self.poll_events(&mut batch).await;
 
             // Priority 4: Flush Batch to Telegram (Summarized)
@@ -146,12 +151,18 @@ impl Supervisor {
                     let _ = self.notify("🧹 *Logs Flushed*").await;
                 }
                 "approve_plan" => {
-                    self.record_decision("PLAN_APPROVED").await?;
+                    let delivered = self.record_decision("PLAN_APPROVED").await?;
                     let _ = self.notify("✅ *Plan Approved*. Signalling Agent...").await;
+                    if !delivered {
+                        let _ = self.notify("⏳ Agent not running; the decision is queued until it starts.").await;
+                    }
                 }
                 "reject_plan" => {
-                    self.record_decision("PLAN_REJECTED").await?;
+                    let delivered = self.record_decision("PLAN_REJECTED").await?;
                     let _ = self.notify("❌ *Plan Rejected*. Signaling Agent...").await;
+                    if !delivered {
+                        let _ = self.notify("⏳ Agent not running; the decision is queued until it starts.").await;
+                    }
                 }
                 _ => {}
             }
@@ -159,10 +170,27 @@ impl Supervisor {
         Ok(())
     }
 
-    async fn record_decision(&self, op: &str) -> Result<()> {
-        let mem = Memory::new_light(".sly/cozo", false).await?;
-        mem.record_event(op, serde_json::json!({ "source": "telegram_remote" }))?;
-        Ok(())
+    /// Queues the decision and sends the queue to the agent; returns whether
+    /// it got there. Only the agent writes the store.
+    async fn record_decision(&self, op: &str) -> Result<bool> {
+        let data = serde_json::json!({ "source": "telegram_remote" });
+        self.pending_decisions.lock().await.push((op.to_string(), data));
+        self.flush_decisions().await
+    }
+
+    /// Sends queued decisions in order while the agent is reachable; true
+    /// once none are left.
+    async fn flush_decisions(&self) -> Result<bool> {
+        let mut pending = self.pending_decisions.lock().await;
+        if pending.is_empty() {
+            return Ok(true);
+        }
+        let Some(mut client) = Self::agent_client().await else { return Ok(false) };
+        while let Some((event, data)) = pending.first().cloned() {
+            client.request(&QueryRequest::RecordEvent { event, data }).await?;
+            pending.remove(0);
+        }
+        Ok(true)
     }
 
     async fn handle_task(&self, text: &str) -> Result<()> {
@@ -237,7 +265,16 @@ impl Supervisor {
         let child_lock = self.executor.lock().await;
         let status = if child_lock.is_some() { "🟢 <b>RUNNING</b>" } else { "🔴 <b>STOPPED</b>" };
         let heal_status = if self.auto_heal { "🛡️ <b>ON</b>" } else { "⚠️ <b>OFF</b>" };
-        let msg = format!("📊 <b>Status</b>: {}\nAuto-Healing: {}\nMode: Godmode\nSafety: OverlayFS Active", status, heal_status);
+        let mut msg = format!("📊 <b>Status</b>: {}\nAuto-Healing: {}\nMode: Godmode\nSafety: OverlayFS Active", status, heal_status);
+        if let Some(servers) = Self::mcp_status().await.filter(|s| !s.is_empty()) {
+            msg.push_str("\n\n🔌 <b>MCP Servers</b>:");
+            for server in servers {
+                msg.push_str(&format!("\n{}", html_escape(&server.summary())));
+                if server.state != ServerState::Running && !server.stderr.is_empty() {
+                    msg.push_str(&format!("\n<pre>{}</pre>", html_escape(&server.stderr.join("\n"))));
+                }
+            }
+        }
         
         use crate::io::telegram::{InlineKeyboardMarkup, InlineKeyboardButton};
         let keyboard = InlineKeyboardMarkup {
@@ -265,8 +302,11 @@ impl Supervisor {
     }
 
     async fn execute_datalog(&self, script: &str) -> Result<()> {
-        let mem = Memory::new_light(".sly/cozo", true).await?;
-        match mem.backend_run_script(script) {
+        let result = match Self::endpoint().await {
+            Ok(mut endpoint) => endpoint.request(&QueryRequest::Query { script: script.to_string() }).await,
+            Err(e) => Err(e),
+        };
+        match result {
             Ok(res) => {
                 let json = serde_json::to_string_pretty(&res)?;
                 let truncated = if json.len() > 3000 { format!("{}...", &json[..3000]) } else { json };
@@ -307,58 +347,44 @@ impl Supervisor {
     }
 
     async fn poll_events(&self, batch: &mut Vec<(String, serde_json::Value)>) {
-        // Open memory in TRANSIENT mode (don't hold the lock)
-        let mem = match Memory::new_light(".sly/cozo", true).await {
-            Ok(m) => m,
+        let last_ts = *self.last_event_ts.lock().await;
+        let events = match Self::endpoint().await {
+            Ok(mut endpoint) => endpoint.events_since(last_ts, 50).await,
+            Err(e) => Err(e),
+        };
+
+        match events {
+            Ok(events) => {
+                let mut max_ts = last_ts;
+                for event in events {
+                    max_ts = max_ts.max(event.timestamp);
+                    batch.push((event.op, event.data));
+                }
+                *self.last_event_ts.lock().await = max_ts;
+            }
             Err(e) => {
                 let err_msg = e.to_string();
                 if !err_msg.contains("Resource temporarily unavailable") {
                     eprintln!("⚠️ Supervisor poll error: {}", err_msg);
                 }
-                return;
             }
-        };
+        }
+    }
 
-        let last_ts = *self.last_event_ts.lock().await;
-        let query = format!(
-            "?[op, data, ts] := *event_log{{op, data, timestamp: ts}}, ts > {} :sort ts :limit 50",
-            last_ts
-        );
+    /// MCP server health from the running agent, if it is reachable.
+    async fn mcp_status() -> Option<Vec<ServerHealth>> {
+        let value = Self::agent_client().await?.request(&QueryRequest::McpStatus).await.ok()?;
+        serde_json::from_value(value).ok()
+    }
 
-        match mem.backend_run_script(&query) {
-            Ok(res) => {
-                let mut max_ts = last_ts;
-                for row in res.rows {
-                    let op = match row.get(0) {
-                        Some(cozo::DataValue::Str(s)) => s.to_string(),
-                        _ => continue,
-                    };
-                    let data = match row.get(1) {
-                        Some(cozo::DataValue::Json(j)) => j.0.clone(),
-                        _ => serde_json::Value::Null,
-                    };
-                    let ts = match row.get(2) {
-                        Some(cozo::DataValue::Num(n)) => {
-                            let s = format!("{:?}", n);
-                            let clean = s.trim_start_matches("Num(").trim_end_matches(')');
-                            if let Ok(f) = clean.parse::<f64>() {
-                                f as i64
-                            } else {
-                                continue;
-                            }
-                        },
-                        _ => continue,
-                    };
-
-                    if ts > max_ts { 
-                        max_ts = ts; 
-                    }
-                    batch.push((op, data));
-                }
-                *self.last_event_ts.lock().await = max_ts;
-            }
-            Err(_e) => {}
-        }
+    /// Connection to the running agent's query socket, if there is one.
+    async fn agent_client() -> Option<QueryClient> {
+        QueryClient::connect(&query_socket::socket_path(Path::new(".sly"))).await.ok()
+    }
+
+    /// The running agent, or the store read-only when there is none.
+    async fn endpoint() -> Result<Endpoint> {
+        Endpoint::open(Path::new(".sly")).await
     }
 
     async fn process_outbox(&self, batch: &mut Vec<(String, serde_json::Value)>) -> Result<()> {
@@ -2,3 +2,4 @@ pub mod events;
 pub mod watcher;
 pub mod telemetry;
 pub mod telegram;
+pub mod query_socket;
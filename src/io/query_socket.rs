@@ -0,0 +1,348 @@
+// src/io/query_socket.rs - Local read endpoint for the running agent
+//
+// RocksDB allows a single process to hold the store. While the agent runs it
+// serves reads over a Unix socket, one JSON request per line, one JSON
+// response per line. Other processes (supervisor, CLI) go through here
+// instead of opening `.sly/cozo` themselves. Besides reads it takes the few
+// writes a remote caller needs: recording an event, starting, forking and
+// resuming sessions, deciding on held tool calls. With no agent running,
+// `Endpoint` answers reads from the store opened read-only and refuses writes.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::Sender;
+
+use crate::core::session;
+use crate::io::events::Impulse;
+use crate::mcp::registry::APPROVAL_DECIDED;
+use crate::mcp::supervisor::McpSupervisor;
+use crate::memory::{replay, Correlation, EventQuery, EventRecord, Memory, MemoryStore, SearchOptions};
+
+/// Socket file name under `.sly/`.
+pub const SOCKET_FILE: &str = "sly.sock";
+
+/// Error for a write attempted while no agent holds the store.
+pub const AGENT_NOT_RUNNING: &str = "Agent not running: this change needs the agent that holds the store (start it with `sly`)";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum QueryRequest {
+    Ping,
+    /// Read-only Datalog; mutations are rejected by the store.
+    Query { script: String },
+    /// Filtered events, oldest first.
+    Events(EventQuery),
+    Session { id: String },
+    /// Session ids, oldest first.
+    ListSessions,
+    /// One page of a session's messages.
+    SessionMessages { id: String, offset: usize, limit: usize },
+    /// Live sessions and memories, for comparison with a replayed log.
+    Snapshot,
+    /// Appends to the event log, so remote decisions can be recorded while
+    /// the agent holds the store.
+    RecordEvent { event: String, data: Value },
+    /// Copies a session (up to step `at`) under a new id; returns it.
+    Fork { id: String, at: Option<usize> },
+    /// Queues another think step for the session.
+    Resume { id: String },
+    /// Health of the configured MCP servers.
+    McpStatus,
+    /// Hybrid code search over the knowledge graph.
+    Search { query: String, limit: usize },
+    /// Nodes at `path` and the ones linked to them.
+    Neighborhood { path: String },
+    /// Dependency docs; `rerank` overrides the `[rerank]` config default.
+    SearchLibrary {
+        query: String,
+        limit: usize,
+        #[serde(default)]
+        rerank: Option<bool>,
+    },
+    /// Paths of every ingested file.
+    IndexedFiles,
+    /// Creates a session for `input` and, when the agent is there to run
+    /// it, queues its first think step. Returns `{ id, started }`.
+    StartSession { input: String },
+    /// MCP tool calls held for approval.
+    PendingToolCalls,
+    /// Approves or rejects a held tool call and queues its session's next
+    /// step, which runs (or skips) the call.
+    DecideToolCall { id: String, approved: bool },
+}
+
+impl QueryRequest {
+    /// Whether answering this changes the store or the agent's state.
+    pub fn writes(&self) -> bool {
+        matches!(self,
+            QueryRequest::RecordEvent { .. }
+            | QueryRequest::Fork { .. }
+            | QueryRequest::Resume { .. }
+            | QueryRequest::StartSession { .. }
+            | QueryRequest::DecideToolCall { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryResponse {
+    Ok(Value),
+    Error(String),
+}
+
+pub fn socket_path(sly_dir: &Path) -> PathBuf {
+    sly_dir.join(SOCKET_FILE)
+}
+
+/// Binds the socket and serves connections in the background. A leftover
+/// socket file from a crashed run is replaced; a live one is an error.
+/// Without `impulses`, `Resume` requests are refused; without `mcp`,
+/// `McpStatus` reports no servers.
+pub async fn serve(path: PathBuf, memory: Arc<dyn MemoryStore>, impulses: Option<Sender<Impulse>>, mcp: Option<Arc<McpSupervisor>>) -> Result<()> {
+    if path.exists() {
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(anyhow!("Another agent is already serving {}", path.display()));
+        }
+        std::fs::remove_file(&path).with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(&path).with_context(|| format!("Failed to bind {}", path.display()))?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let memory = memory.clone();
+                    let impulses = impulses.clone();
+                    let mcp = mcp.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, memory, impulses, mcp).await {
+                            eprintln!("⚠️ Query socket connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("⚠️ Query socket accept failed: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(stream: UnixStream, memory: Arc<dyn MemoryStore>, impulses: Option<Sender<Impulse>>, mcp: Option<Arc<McpSupervisor>>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<QueryRequest>(&line) {
+            Ok(request) => match dispatch(request, memory.as_ref(), impulses.as_ref(), mcp.as_deref()).await {
+                Ok(value) => QueryResponse::Ok(value),
+                Err(e) => QueryResponse::Error(e.to_string()),
+            },
+            Err(e) => QueryResponse::Error(format!("Invalid request: {}", e)),
+        };
+        let mut out = serde_json::to_vec(&response)?;
+        out.push(b'\n');
+        writer.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+/// Answers one request against `memory`; also used in-process when no
+/// agent is running.
+pub async fn dispatch(request: QueryRequest, memory: &dyn MemoryStore, impulses: Option<&Sender<Impulse>>, mcp: Option<&McpSupervisor>) -> Result<Value> {
+    match request {
+        QueryRequest::Ping => Ok(Value::String("pong".to_string())),
+        QueryRequest::Query { script } => memory.query(&script),
+        QueryRequest::Events(query) => Ok(serde_json::to_value(memory.query_events(&query).await?)?),
+        QueryRequest::Session { id } => Ok(serde_json::to_value(memory.get_session(&id).await?)?),
+        QueryRequest::SessionMessages { id, offset, limit } => Ok(serde_json::to_value(memory.session_messages(&id, offset, limit).await?)?),
+        QueryRequest::Snapshot => Ok(serde_json::to_value(replay::snapshot(memory).await?)?),
+        QueryRequest::ListSessions => Ok(serde_json::to_value(memory.list_sessions().await?)?),
+        QueryRequest::RecordEvent { event, data } => {
+            memory.record_event(&event, data)?;
+            Ok(Value::Null)
+        }
+        QueryRequest::Fork { id, at } => {
+            let fork = session::fork(memory, &std::env::current_dir()?, &id, at).await?;
+            Ok(serde_json::to_value(fork)?)
+        }
+        QueryRequest::Resume { id } => {
+            let impulses = impulses.ok_or_else(|| anyhow!("This endpoint does not accept impulses"))?;
+            if memory.get_session(&id).await?.is_none() {
+                return Err(anyhow!("No session with id {}", id));
+            }
+            impulses.send(Impulse::ThinkStep(id)).await.map_err(|_| anyhow!("The agent loop has stopped"))?;
+            Ok(Value::Null)
+        }
+        QueryRequest::McpStatus => match mcp {
+            Some(mcp) => Ok(serde_json::to_value(mcp.status().await)?),
+            None => Ok(Value::Array(vec![])),
+        },
+        QueryRequest::Search { query, limit } => {
+            let hits = memory.search(&query, &SearchOptions { limit, ..Default::default() }).await?;
+            Ok(serde_json::to_value(hits)?)
+        }
+        QueryRequest::Neighborhood { path } => Ok(serde_json::to_value(memory.neighborhood(&path).await?)?),
+        QueryRequest::SearchLibrary { query, limit, rerank } => Ok(serde_json::to_value(memory.search_library(&query, limit, rerank).await?)?),
+        QueryRequest::IndexedFiles => Ok(serde_json::to_value(memory.indexed_paths().await?)?),
+        QueryRequest::StartSession { input } => {
+            let session = session::AgentSession::new(input);
+            memory.create_session(&session).await?;
+            let started = match impulses {
+                Some(impulses) => impulses.send(Impulse::ThinkStep(session.id.clone())).await.is_ok(),
+                None => false,
+            };
+            Ok(serde_json::json!({ "id": session.id, "started": started }))
+        }
+        QueryRequest::PendingToolCalls => match mcp {
+            Some(mcp) => Ok(serde_json::to_value(mcp.registry().waiting())?),
+            None => Ok(Value::Array(vec![])),
+        },
+        QueryRequest::DecideToolCall { id, approved } => {
+            let mcp = mcp.ok_or_else(|| anyhow!("This endpoint has no MCP servers"))?;
+            let call = mcp.registry().decide(&id, approved)?;
+            let correlation = Correlation { session_id: Some(call.session_id.clone()), directive_id: None };
+            memory.record_correlated(APPROVAL_DECIDED, serde_json::json!({ "id": id, "tool": call.tool, "approved": approved }), &correlation)?;
+            if let Some(impulses) = impulses {
+                impulses.send(Impulse::ThinkStep(call.session_id.clone())).await.map_err(|_| anyhow!("The agent loop has stopped"))?;
+            }
+            Ok(serde_json::to_value(call)?)
+        }
+    }
+}
+
+/// Client side of the endpoint. `connect` fails when no agent is running,
+/// in which case the caller may open the store directly.
+pub struct QueryClient {
+    lines: tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>,
+    writer: tokio::net::unix::OwnedWriteHalf,
+}
+
+impl QueryClient {
+    pub async fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path).await
+            .with_context(|| format!("No agent listening on {}", path.display()))?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self { lines: BufReader::new(reader).lines(), writer })
+    }
+
+    pub async fn request(&mut self, request: &QueryRequest) -> Result<Value> {
+        let mut out = serde_json::to_vec(request)?;
+        out.push(b'\n');
+        self.writer.write_all(&out).await?;
+        let line = self.lines.next_line().await?.context("Agent closed the query socket")?;
+        match serde_json::from_str(&line)? {
+            QueryResponse::Ok(value) => Ok(value),
+            QueryResponse::Error(e) => Err(anyhow!(e)),
+        }
+    }
+
+    pub async fn query_events(&mut self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        let value = self.request(&QueryRequest::Events(query.clone())).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub async fn events_since(&mut self, since: i64, limit: usize) -> Result<Vec<EventRecord>> {
+        self.query_events(&EventQuery { since: Some(since), limit, ..Default::default() }).await
+    }
+}
+
+/// The running agent if there is one, else the store opened read-only
+/// in-process. Writes through the fallback fail with `AGENT_NOT_RUNNING`.
+pub enum Endpoint {
+    Agent(QueryClient),
+    ReadOnly(Box<dyn MemoryStore>),
+}
+
+impl Endpoint {
+    pub async fn open(sly_dir: &Path) -> Result<Self> {
+        match QueryClient::connect(&socket_path(sly_dir)).await {
+            Ok(client) => Ok(Endpoint::Agent(client)),
+            Err(_) => {
+                let store = Memory::new_light(&sly_dir.join("cozo").to_string_lossy(), true).await
+                    .context("No agent running and the store could not be opened read-only")?;
+                Ok(Endpoint::ReadOnly(Box::new(store)))
+            }
+        }
+    }
+
+    pub fn is_agent(&self) -> bool {
+        matches!(self, Endpoint::Agent(_))
+    }
+
+    pub async fn request(&mut self, request: &QueryRequest) -> Result<Value> {
+        match self {
+            Endpoint::Agent(client) => client.request(request).await,
+            Endpoint::ReadOnly(_) if request.writes() => Err(anyhow!(AGENT_NOT_RUNNING)),
+            Endpoint::ReadOnly(store) => dispatch(request.clone(), store.as_ref(), None, None).await,
+        }
+    }
+
+    pub async fn query_events(&mut self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        let value = self.request(&QueryRequest::Events(query.clone())).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub async fn events_since(&mut self, since: i64, limit: usize) -> Result<Vec<EventRecord>> {
+        self.query_events(&EventQuery { since: Some(since), limit, ..Default::default() }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::AgentSession;
+    use crate::memory::{InMemoryStore, SessionStore};
+
+    #[tokio::test]
+    async fn test_round_trip_over_socket() {
+        let path = std::env::temp_dir().join(format!("sly-{}.sock", uuid::Uuid::new_v4()));
+        let memory: Arc<dyn MemoryStore> = Arc::new(InMemoryStore::new());
+        let session = AgentSession::new("hello".to_string());
+        memory.create_session(&session).await.unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        serve(path.clone(), memory.clone(), Some(tx), None).await.unwrap();
+
+        let mut client = QueryClient::connect(&path).await.unwrap();
+        assert_eq!(client.request(&QueryRequest::Ping).await.unwrap(), "pong");
+        client.request(&QueryRequest::RecordEvent { event: "PLAN_APPROVED".to_string(), data: Value::Null }).await.unwrap();
+        let events = client.events_since(0, 10).await.unwrap();
+        assert_eq!(events.last().unwrap().op, "PLAN_APPROVED");
+
+        let found = client.request(&QueryRequest::Session { id: session.id.clone() }).await.unwrap();
+        assert_eq!(found["messages"][0]["content"], "hello");
+        assert_eq!(found["messages"][0]["kind"], "prompt");
+        assert!(client.request(&QueryRequest::Query { script: "?[a] := a = 1".to_string() }).await.is_err());
+
+        client.request(&QueryRequest::Resume { id: session.id.clone() }).await.unwrap();
+        assert!(matches!(rx.recv().await, Some(Impulse::ThinkStep(id)) if id == session.id));
+        assert!(client.request(&QueryRequest::Resume { id: "missing".to_string() }).await.is_err());
+        assert_eq!(client.request(&QueryRequest::McpStatus).await.unwrap(), Value::Array(vec![]));
+        assert!(serve(path.clone(), memory, None, None).await.is_err(), "a live socket is not replaced");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_endpoint_refuses_writes() {
+        let memory = InMemoryStore::new();
+        let session = AgentSession::new("hello".to_string());
+        memory.create_session(&session).await.unwrap();
+        let mut endpoint = Endpoint::ReadOnly(Box::new(memory));
+
+        let ids = endpoint.request(&QueryRequest::ListSessions).await.unwrap();
+        assert_eq!(ids, serde_json::json!([session.id.clone()]));
+        let err = endpoint.request(&QueryRequest::RecordEvent { event: "PLAN_APPROVED".to_string(), data: Value::Null }).await.unwrap_err();
+        assert_eq!(err.to_string(), AGENT_NOT_RUNNING);
+        assert!(endpoint.request(&QueryRequest::Fork { id: session.id, at: None }).await.is_err());
+        assert!(endpoint.events_since(0, 10).await.is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::memory::{Memory, GraphNode};
+use crate::memory::{GraphNode, LibraryDoc, MemoryStore};
 use anyhow::{anyhow, Result};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -27,11 +27,11 @@ pub struct DetectedLibrary {
 
 // --- Pure Functions & Data Transformation ---
 
-pub async fn ingest_file(memory: &Memory, path: &Path) -> Result<()> {
+pub async fn ingest_file(memory: &dyn MemoryStore, path: &Path) -> Result<()> {
     ingest_batch(memory, &[path.to_path_buf()]).await
 }
 
-pub async fn ingest_batch(memory: &Memory, paths: &[PathBuf]) -> Result<()> {
+pub async fn ingest_batch(memory: &dyn MemoryStore, paths: &[PathBuf]) -> Result<()> {
     if paths.is_empty() {
         return Ok(());
     }
@@ -64,14 +64,18 @@ pub async fn ingest_batch(memory: &Memory, paths: &[PathBuf]) -> Result<()> {
         .collect();
 
     // 4. Batch Commit (Side effects)
+    let cache_before = memory.embedding_cache_stats();
     for (nodes, file) in all_nodes_and_files {
         commit_nodes(memory, nodes, &file).await?;
     }
+    if let (Some(before), Some(after)) = (cache_before, memory.embedding_cache_stats()) {
+        println!("📦 Embedding cache: {}", after.since(&before));
+    }
     
     Ok(())
 }
 
-async fn should_reindex(memory: &Memory, file: &FileValue) -> Result<bool> {
+async fn should_reindex(memory: &dyn MemoryStore, file: &FileValue) -> Result<bool> {
     let path_str = file.path.to_str().unwrap_or_default();
     if let Ok(Some((_, old_hash))) = memory.check_sync_status(path_str).await {
         if old_hash == file.hash {
@@ -81,10 +85,10 @@ async fn should_reindex(memory: &Memory, file: &FileValue) -> Result<bool> {
     Ok(true)
 }
 
-async fn commit_nodes(memory: &Memory, nodes: Vec<GraphNode>, file: &FileValue) -> Result<()> {
+async fn commit_nodes(memory: &dyn MemoryStore, nodes: Vec<GraphNode>, file: &FileValue) -> Result<()> {
     let path_str = file.path.to_str().unwrap_or_default();
     if !nodes.is_empty() {
-        memory.batch_add_nodes(nodes).await?;
+        memory.add_nodes(nodes).await?;
     }
     memory.update_sync_status(path_str, &file.hash).await?;
     Ok(())
@@ -167,30 +171,27 @@ pub fn scan_python_dependencies() -> Result<Vec<DetectedLibrary>> {
 
 // --- Documentation Sync (IO + DB) ---
 
-pub async fn sync_libraries(memory: &Memory, client: &reqwest::Client, libs: &[DetectedLibrary]) -> Result<()> {
-    let mut entries = Vec::new();
+pub async fn sync_libraries(memory: &dyn MemoryStore, client: &reqwest::Client, libs: &[DetectedLibrary]) -> Result<()> {
+    let mut docs = Vec::new();
     for lib in libs {
         println!("     Processing {}...", lib.name);
-        let id = format!("{}_{}", lib.name, lib.version);
-        
         let doc_content = match fetch_documentation(client, lib).await {
             Ok(doc) => doc,
             Err(_) => String::new(),
         };
 
-        let embedding = if !doc_content.is_empty() {
-            memory.embed(&doc_content).unwrap_or(vec![0.0; 384])
-        } else {
-            vec![0.0; 384]
-        };
-        
         let chunk_type = if doc_content.is_empty() { "metadata" } else { "documentation" };
         let content = if doc_content.is_empty() { format!("Library: {} {}", lib.name, lib.version) } else { doc_content };
 
-        entries.push((id, lib.name.clone(), lib.version.clone(), content, String::new(), chunk_type.to_string(), embedding));
+        docs.push(LibraryDoc {
+            id: format!("{}_{}", lib.name, lib.version),
+            name: lib.name.clone(),
+            version: lib.version.clone(),
+            content,
+            chunk_type: chunk_type.to_string(),
+        });
     }
-    if !entries.is_empty() { memory.batch_add_library_entries(entries).await?; }
-    Ok(())
+    memory.add_library_docs(docs).await
 }
 
 async fn fetch_documentation(client: &reqwest::Client, lib: &DetectedLibrary) -> Result<String> {
@@ -258,7 +259,7 @@ async fn fetch_rust_docs(client: &reqwest::Client, lib: &DetectedLibrary) -> Res
 
 // --- Skill Bootstrapping (Data -> DB) ---
 
-pub async fn ensure_skills_loaded(memory: &Memory) -> Result<()> {
+pub async fn ensure_skills_loaded(memory: &dyn MemoryStore) -> Result<()> {
     let skills = vec![
         ("sly_sum",
          r#"(module (func (export "run") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add))"#,
@@ -1,5 +1,5 @@
-use sly::memory::{Memory, MemoryStore};
-use sly::core::state::{GlobalState, SlyConfig};
+use sly::memory::{InMemoryStore, Memory, MemoryStore};
+use sly::core::state::{GlobalState, SlyConfig, StorageEngine};
 use sly::core::r#loop::cortex_loop;
 use sly::io::watcher::setup_watcher;
 use sly::safety::OverlayFS;
@@ -18,6 +18,7 @@ use std::sync::Arc;
 use tokio::time::Duration;
 
 pub const SLY_DIR: &str = ".sly";
+const OVERLAY_ID: &str = sly::safety::overlay::SHARED_OVERLAY_ID;
 
 // Modules are now re-exported from lib.rs (sly crate)
 
@@ -38,7 +39,7 @@ async fn main() -> Result<()> {
     }
     if args.iter().any(|a| a == "--help" || a == "-h" || a == "help") {
         println!("Sly - Autonomous Agent (v{})", env!("CARGO_PKG_VERSION"));
-        println!("Usage: sly [init | supervisor | session <query> | --version | --help]");
+        println!("Usage: sly [init | supervisor | session <query|list|show|resume|fork|export> | db <migrate|export|import> | events <tail|query|replay|diff> | query <datalog> | mcp <list|test|approve|reject> | mcp-serve | --version | --help]");
         return Ok(());
     }
 
@@ -72,6 +73,26 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if args.len() > 1 && args[1] == "db" {
+        return run_db_command(&args[2..]);
+    }
+
+    if args.len() > 1 && args[1] == "events" {
+        return run_events_command(&args[2..]).await;
+    }
+
+    if args.len() > 1 && args[1] == "query" {
+        return run_query_command(&args[2..]).await;
+    }
+
+    if args.len() > 1 && args[1] == "mcp" {
+        return run_mcp_command(&args[2..]).await;
+    }
+
+    if args.len() > 1 && args[1] == "mcp-serve" {
+        return run_mcp_serve_command().await;
+    }
+
     if args.iter().any(|a| a == "supervisor") {
         if args.iter().any(|a| a == "install") {
             return sly::core::supervisor::Supervisor::install_service();
@@ -85,21 +106,40 @@ async fn main() -> Result<()> {
 
     let mut initial_impulse = None;
     if args.len() > 2 && args[1] == "session" {
-        initial_impulse = Some(sly::io::events::Impulse::InitiateSession(args[2..].join(" ")));
+        if SESSION_COMMANDS.contains(&args[2].as_str()) {
+            match run_session_command(&args[2..]).await? {
+                Some(impulse) => initial_impulse = Some(impulse),
+                None => return Ok(()),
+            }
+        } else {
+            initial_impulse = Some(sly::io::events::Impulse::InitiateSession(args[2..].join(" ")));
+        }
     }
 
 
     // 1. Initialize State and Memory (Only for Agent execution)
     let config = SlyConfig::load(); 
-    let memory = Arc::new(Memory::new(&format!("{}/cozo", SLY_DIR), false).await.context("Failed to init memory")?);
-    let memory_raw = memory.clone();
-    let memory_store: Arc<dyn MemoryStore> = memory.clone();
+    let memory: Arc<dyn MemoryStore> = match config.storage.engine {
+        StorageEngine::Memory => {
+            println!("{} Using ephemeral in-memory store (nothing is persisted)", "⚠️".yellow());
+            Arc::new(InMemoryStore::new())
+        }
+        engine => {
+            let mut memory = Memory::open(&format!("{}/cozo", SLY_DIR), false, engine, &config.embedding).await.context("Failed to init memory")?;
+            if config.rerank.enabled {
+                if let Err(e) = memory.enable_reranker(&config.rerank) {
+                    eprintln!("{} Re-ranker unavailable, using fused ranking only: {}", "⚠️".yellow(), e);
+                }
+            }
+            Arc::new(memory)
+        }
+    };
 
     println!("{} Scanning Local Environment for New Knowledge...", "🧠".cyan());
     
     // Bootstrap Skills (Critical since sly-learn is disabled)
     // Pure function call (no manager object)
-    if let Err(e) = sly::knowledge::ensure_skills_loaded(&memory).await {
+    if let Err(e) = sly::knowledge::ensure_skills_loaded(memory.as_ref()).await {
         eprintln!("{} Failed to bootstrap skills: {}", "⚠️".yellow(), e);
     } else {
         println!("   {} Native Skills Loaded", "🧩".green());
@@ -122,43 +162,37 @@ async fn main() -> Result<()> {
 
 
     // Safety Shield
-    let overlay = Arc::new(OverlayFS::new(&std::env::current_dir()?, "godmode_session")?);
+    let overlay = Arc::new(OverlayFS::new(&std::env::current_dir()?, OVERLAY_ID)?);
     println!("{} Safety Shield (OverlayFS) Active", "🛡️".green());
 
-    let state = Arc::new(GlobalState::new(config.clone(), memory_store, memory_raw.clone(), overlay, cortex));
-
-    // Phase 6: Register Core Handlers (Dynamic Dispatch)
-    sly::core::interpreter::DirectiveInterpreter::register_core_handlers(state.clone()).await;
+    let state = Arc::new(GlobalState::new(config.clone(), memory, overlay, cortex));
 
     // 2. Setup Event Bus (Nervous System QoS)
     let (priority_tx, priority_rx) = mpsc::channel(100);
     let (background_tx, background_rx) = mpsc::channel(1000);
+    restore_held_tool_calls(&state, &priority_tx).await;
+
+    // Local read endpoint so the supervisor never opens the locked store
+    match sly::io::query_socket::serve(sly::io::query_socket::socket_path(Path::new(SLY_DIR)), state.memory.clone(), Some(priority_tx.clone()), Some(state.mcp_supervisor.clone())).await {
+        Ok(()) => println!("{} Query socket listening", "🔌".cyan()),
+        Err(e) => eprintln!("{} Query socket unavailable: {}", "⚠️".yellow(), e),
+    }
+
+    // Phase 6: Register Core Handlers (Dynamic Dispatch)
+    sly::core::interpreter::DirectiveInterpreter::register_core_handlers(state.clone()).await;
 
     // 3. Start Background Services
     
 
 
-    // Start MCP Clients
-    {
-        let mut clients = state.mcp_clients.lock().await;
-        for (name, server_config) in &config.mcp_servers {
-             println!("   {} Starting MCP Server: {} ({})", "🔌".cyan(), name, server_config.command);
-             match sly::mcp::transport::StdioTransport::new(&server_config.command, &server_config.args) {
-                 Ok(transport) => {
-                     let client = Arc::new(sly::mcp::client::McpClient::new(Box::new(transport)));
-                     // Timeout the init handshake to avoid hanging boot
-                     match tokio::time::timeout(Duration::from_secs(5), client.initialize()).await {
-                         Ok(Ok(_)) => {
-                             println!("     {} Connected to {}", "✅".green(), name);
-                             clients.insert(name.clone(), client);
-                         },
-                         Ok(Err(e)) => eprintln!("     {} Handshake failed for {}: {}", "⚠️".red(), name, e),
-                         Err(_) => eprintln!("     {} Connection timed out for {}", "⚠️".red(), name),
-                     }
-                 },
-                 Err(e) => eprintln!("     {} Failed to spawn {}: {}", "⚠️".red(), name, e),
-             }
+    // Start MCP Clients (kept connected by the MCP supervisor)
+    for (name, server_config) in &config.mcp_servers {
+        if server_config.disabled {
+            println!("   {} MCP Server {} is disabled", "⏸️".bright_black(), name);
+            continue;
         }
+        println!("   {} Starting MCP Server: {} ({})", "🔌".cyan(), name, server_config.endpoint());
+        state.mcp_supervisor.start(name, server_config.clone()).await;
     }
 
 
@@ -193,6 +227,500 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Tool calls held for approval when the agent last stopped wait again, so
+/// `sly mcp approve|reject` can still settle them; sessions with a decided
+/// call they never acted on get a step to act on it now.
+async fn restore_held_tool_calls(state: &GlobalState, impulses: &mpsc::Sender<sly::io::events::Impulse>) {
+    use sly::io::events::Impulse;
+    use sly::mcp::registry::{self, APPROVAL_CONSUMED, APPROVAL_DECIDED, APPROVAL_REQUESTED};
+    use sly::memory::EventQuery;
+    use std::collections::BTreeSet;
+
+    let query = EventQuery {
+        ops: [APPROVAL_REQUESTED, APPROVAL_DECIDED, APPROVAL_CONSUMED].map(String::from).to_vec(),
+        limit: 100_000,
+        latest: true,
+        ..Default::default()
+    };
+    let open = match state.memory.query_events(&query).await {
+        Ok(events) => registry::open_approvals(&events),
+        Err(e) => {
+            eprintln!("{} Could not restore held tool calls: {}", "⚠️".yellow(), e);
+            return;
+        }
+    };
+    if !open.waiting.is_empty() {
+        println!("{} {} tool call(s) still await approval (sly mcp list)", "⏸️".yellow(), open.waiting.len());
+    }
+    let sessions: BTreeSet<String> = open.decided.iter().map(|(call, _)| call.session_id.clone()).collect();
+    state.mcp_tools.restore_approvals(open);
+    for session_id in sessions {
+        println!("{} Resuming {} to act on its decided tool call(s)", "▶️".green(), session_id);
+        let _ = impulses.send(Impulse::ThinkStep(session_id)).await;
+    }
+}
+
+/// `sly mcp list|test|approve|reject`: check the configured MCP servers
+/// and decide on tool calls the running agent holds for approval.
+async fn run_mcp_command(args: &[String]) -> Result<()> {
+    use sly::io::query_socket::{self, QueryClient, QueryRequest};
+    use sly::mcp::registry::{PendingCall, ToolPolicy};
+    use sly::mcp::supervisor::ServerHealth;
+
+    const USAGE: &str = "Usage: sly mcp list\n       sly mcp test <server>\n       sly mcp approve <id>\n       sly mcp reject <id>";
+
+    let config = SlyConfig::load();
+    let agent = QueryClient::connect(&query_socket::socket_path(Path::new(SLY_DIR))).await.ok();
+    match (args.first().map(String::as_str), args.get(1)) {
+        (Some("list"), _) => {
+            if config.mcp_servers.is_empty() {
+                println!("No MCP servers configured (add them under [mcp_servers] in .sly/config.toml)");
+                return Ok(());
+            }
+            let (health, pending) = match agent {
+                Some(mut agent) => (
+                    serde_json::from_value::<Vec<ServerHealth>>(agent.request(&QueryRequest::McpStatus).await?)?,
+                    serde_json::from_value::<Vec<PendingCall>>(agent.request(&QueryRequest::PendingToolCalls).await?)?,
+                ),
+                None => (vec![], vec![]),
+            };
+            let mut names: Vec<&String> = config.mcp_servers.keys().collect();
+            names.sort();
+            for name in names {
+                let server = &config.mcp_servers[name];
+                match (server.disabled, health.iter().find(|h| &h.name == name)) {
+                    (true, _) => println!("{}: ⏸️ disabled", name),
+                    (false, Some(h)) => println!("{}", h.summary()),
+                    (false, None) => println!("{}: ⚪ not running", name),
+                }
+                println!("    endpoint: {}", server.endpoint());
+                println!("    timeouts: startup {}s, calls {}s", server.startup_timeout().as_secs(), server.call_timeout().as_secs());
+                if let Some(cwd) = &server.cwd {
+                    println!("    cwd: {}", cwd);
+                }
+                if !server.env.is_empty() {
+                    let mut keys: Vec<&String> = server.env.keys().collect();
+                    keys.sort();
+                    println!("    env: {}", keys.iter().map(|k| k.as_str()).collect::<Vec<_>>().join(", "));
+                }
+                if !server.allow_tools.is_empty() || !server.deny_tools.is_empty() {
+                    println!("    tools: allow [{}], deny [{}]", server.allow_tools.join(", "), server.deny_tools.join(", "));
+                }
+                if server.require_approval {
+                    println!("    approval required, except for [{}]", server.auto_approve.join(", "));
+                }
+            }
+            for call in pending {
+                println!("{} {} awaits approval as {} (session {}): {}", "⏸️".yellow(), call.tool, call.id, call.session_id, call.arguments);
+            }
+        }
+        (Some("test"), Some(name)) => {
+            let server = config.mcp_servers.get(name).with_context(|| format!("No MCP server named {} in .sly/config.toml", name))?;
+            if server.disabled {
+                println!("{} {} is disabled; testing it anyway", "⏸️".bright_black(), name);
+            }
+            println!("{} Connecting to {} ({})...", "🔌".cyan(), name, server.endpoint());
+            let client = sly::mcp::client::McpClient::new(sly::mcp::transport::open(server).await?).with_timeout(server.call_timeout());
+            if let Err(e) = sly::mcp::supervisor::initialize(&client, server).await {
+                for line in client.stderr_tail() {
+                    eprintln!("    stderr: {}", line);
+                }
+                return Err(e);
+            }
+            println!("   {} Handshake done", "✅".green());
+            let policy = ToolPolicy::from_config(server);
+            for tool in client.list_tools().await? {
+                let mark = match (policy.allows(&tool.name), policy.needs_approval(&tool.name)) {
+                    (false, _) => "hidden".bright_black(),
+                    (true, true) => "needs approval".yellow(),
+                    (true, false) => "available".green(),
+                };
+                println!("    {} ({})", tool.name, mark);
+            }
+            let rtt = client.ping(server.call_timeout()).await?;
+            println!("   {} Ping answered in {}ms", "✅".green(), rtt.as_millis());
+        }
+        (Some(decision @ ("approve" | "reject")), Some(id)) => {
+            let mut agent = agent.context("No agent running; held tool calls live in the agent")?;
+            let value = agent.request(&QueryRequest::DecideToolCall { id: id.clone(), approved: decision == "approve" }).await?;
+            let call: PendingCall = serde_json::from_value(value)?;
+            println!("{} {} {} for session {}", "✅".green(), if decision == "approve" { "Approved" } else { "Rejected" }, call.tool, call.session_id);
+        }
+        _ => eprintln!("{}", USAGE),
+    }
+    Ok(())
+}
+
+/// `sly mcp-serve`: Sly's knowledge graph, library docs, overlay and
+/// sessions as an MCP server on stdin/stdout, through the running agent
+/// when there is one.
+async fn run_mcp_serve_command() -> Result<()> {
+    use sly::io::query_socket::{self, QueryClient};
+    use sly::mcp::server::{Backend, McpServer, OpenWritable};
+
+    let backend = match QueryClient::connect(&query_socket::socket_path(Path::new(SLY_DIR))).await {
+        Ok(client) => {
+            eprintln!("{} Serving MCP through the running agent", "🔌".cyan());
+            Backend::Agent(tokio::sync::Mutex::new(client))
+        }
+        Err(_) => {
+            // Read-only, so the agent can still take the store's lock; only
+            // creating a session opens it writable, for that one write.
+            let config = SlyConfig::load();
+            let path = format!("{}/cozo", SLY_DIR);
+            let (store, open_writable): (Arc<dyn MemoryStore>, OpenWritable) = match config.storage.engine {
+                StorageEngine::Memory => {
+                    let store: Arc<dyn MemoryStore> = Arc::new(InMemoryStore::new());
+                    let shared = store.clone();
+                    (store, Box::new(move || {
+                        let store = shared.clone();
+                        Box::pin(async move { Ok(store) })
+                    }))
+                }
+                engine => {
+                    let store = Memory::open(&path, true, engine, &config.embedding).await.context("Failed to open memory read-only")?;
+                    (Arc::new(store), Box::new(move || {
+                        let path = path.clone();
+                        Box::pin(async move { Ok(Arc::new(Memory::new_light(&path, false).await?) as Arc<dyn MemoryStore>) })
+                    }))
+                }
+            };
+            eprintln!("{} No agent running; serving MCP from {}/cozo read-only (sessions started now wait for `sly session resume`)", "🔌".cyan(), SLY_DIR);
+            Backend::Direct { store, open_writable }
+        }
+    };
+    McpServer::new(backend, env::current_dir()?, OVERLAY_ID).serve(tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+const SESSION_COMMANDS: &[&str] = &["list", "show", "resume", "fork", "export"];
+
+/// `sly session list|show|resume|fork|export`, through the running agent
+/// when there is one. Returns the impulse to boot the agent with when
+/// `resume` finds no agent running.
+async fn run_session_command(args: &[String]) -> Result<Option<sly::io::events::Impulse>> {
+    use sly::core::session::AgentSession;
+    use sly::io::events::Impulse;
+    use sly::io::query_socket::{Endpoint, QueryRequest};
+
+    const USAGE: &str = "Usage: sly session list\n       sly session show <id>\n       sly session resume <id>\n       sly session fork <id> [--at STEP]\n       sly session export <id> [--format md|json]";
+
+    let flag = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str);
+    let id = args.get(1).filter(|a| !a.starts_with("--")).cloned();
+    if args[0] != "list" && id.is_none() {
+        eprintln!("{}", USAGE);
+        return Ok(None);
+    }
+    let id = id.unwrap_or_default();
+
+    let mut endpoint = Endpoint::open(Path::new(SLY_DIR)).await?;
+    if args[0] == "resume" {
+        if !endpoint.is_agent() {
+            println!("{} No agent running; starting one to resume {}", "🔋".green(), id);
+            return Ok(Some(Impulse::ThinkStep(id)));
+        }
+        endpoint.request(&QueryRequest::Resume { id: id.clone() }).await?;
+        println!("{} Queued a think step for {}", "▶️".green(), id);
+        return Ok(None);
+    }
+
+    if args[0] == "fork" {
+        let at = flag("--at").map(str::parse).transpose().context("--at takes a step number")?;
+        let fork: AgentSession = serde_json::from_value(endpoint.request(&QueryRequest::Fork { id: id.clone(), at }).await?)?;
+        println!("{} Forked {} into {} ({} messages, own overlay)", "🌱".green(), id, fork.id, fork.messages.len());
+        println!("   Resume it with: sly session resume {}", fork.id);
+        return Ok(None);
+    }
+
+    let ids = if args[0] == "list" {
+        serde_json::from_value(endpoint.request(&QueryRequest::ListSessions).await?)?
+    } else {
+        vec![id.clone()]
+    };
+    let mut sessions = Vec::new();
+    for id in ids {
+        let found: Option<AgentSession> = serde_json::from_value(endpoint.request(&QueryRequest::Session { id: id.clone() }).await?)?;
+        sessions.push(found.with_context(|| format!("No session with id {}", id))?);
+    }
+
+    match args[0].as_str() {
+        "list" => {
+            if sessions.is_empty() {
+                println!("No sessions yet.");
+            }
+            for s in &sessions {
+                let input: String = s.messages.first().map(|m| m.content.as_str()).unwrap_or_default().chars().take(60).collect();
+                println!("{} {:<20} steps={:<3} messages={:<4} {}", s.id.cyan(), s.status.name(), s.depth, s.messages.len(), input.replace('\n', " "));
+            }
+        }
+        "show" => {
+            let s = &sessions[0];
+            println!("{} Session {} — {} after {} steps", "📜".cyan(), s.id, s.status.name(), s.depth);
+            if let Some(error) = s.status.error() {
+                println!("   {} {}", "Error:".red(), error);
+            }
+            for (i, m) in s.messages.iter().enumerate() {
+                let preview: String = m.content.lines().next().unwrap_or_default().chars().take(100).collect();
+                println!("{:>4} {:<12} {:<9} ~{:<5} {}", i + 1, m.kind.as_str().yellow(), m.role.as_str(), m.tokens, preview);
+            }
+        }
+        _ => match flag("--format").unwrap_or("md") {
+            "md" => print!("{}", sessions[0].to_markdown()),
+            "json" => println!("{}", serde_json::to_string_pretty(&sessions[0])?),
+            other => anyhow::bail!("Unknown export format '{}' (expected md or json)", other),
+        },
+    }
+    Ok(None)
+}
+
+/// `sly events tail|query`, through the running agent when there is one.
+async fn run_events_command(args: &[String]) -> Result<()> {
+    use sly::io::query_socket::Endpoint;
+    use sly::memory::{events, EventQuery};
+    use std::collections::HashSet;
+
+    const USAGE: &str = "Usage: sly events tail [-n N] [-f] [--op OP] [--session ID]\n       sly events query [--op OP]... [--since T] [--until T] [--session ID] [--directive ID] [--limit N] [--json]\n       sly events replay [--into DIR]\n       sly events diff [--json]\n       T is epoch ms, RFC 3339, or an age like 15m, 6h, 2d";
+
+    if matches!(args.first().map(String::as_str), Some("replay" | "diff")) {
+        return run_replay_command(args).await;
+    }
+
+    let flag = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str);
+    let now = chrono::Utc::now().timestamp_millis();
+    let time = |name: &str| -> Result<Option<i64>> {
+        flag(name).map(|t| events::parse_time(t, now).with_context(|| format!("Cannot parse {} '{}'", name, t))).transpose()
+    };
+    let mut query = EventQuery {
+        ops: args.windows(2).filter(|w| w[0] == "--op").map(|w| w[1].clone()).collect(),
+        since: time("--since")?,
+        until: time("--until")?,
+        session_id: flag("--session").map(str::to_string),
+        directive_id: flag("--directive").map(str::to_string),
+        ..Default::default()
+    };
+    let follow = args.iter().any(|a| a == "-f" || a == "--follow");
+    let json = args.iter().any(|a| a == "--json");
+    match args.first().map(String::as_str) {
+        Some("tail") => {
+            query.limit = flag("-n").map(str::parse).transpose()?.unwrap_or(20);
+            query.latest = true;
+        }
+        Some("query") => query.limit = flag("--limit").map(str::parse).transpose()?.unwrap_or(100),
+        _ => {
+            eprintln!("{}", USAGE);
+            return Ok(());
+        }
+    }
+
+    // Following restarts one millisecond before the last event printed, as
+    // replay pages do, and skips the ids already printed at that boundary.
+    let mut endpoint = Endpoint::open(Path::new(SLY_DIR)).await?;
+    let mut boundary: HashSet<String> = HashSet::new();
+    loop {
+        let batch = endpoint.query_events(&query).await?;
+        for event in batch.iter().filter(|e| !boundary.contains(&e.id)) {
+            if json {
+                println!("{}", serde_json::to_string(event)?);
+            } else {
+                let at = chrono::DateTime::from_timestamp_millis(event.timestamp).map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string()).unwrap_or_default();
+                let session = event.session_id.as_deref().map(|s| format!(" [{}]", s)).unwrap_or_default();
+                let data: String = event.data.to_string().chars().take(160).collect();
+                println!("{} {}{} {}", at.bright_black(), event.op.cyan(), session, data);
+            }
+        }
+        if !follow {
+            return Ok(());
+        }
+        if let Some(last) = batch.last().map(|e| e.timestamp) {
+            boundary = batch.iter().filter(|e| e.timestamp == last).map(|e| e.id.clone()).collect();
+            query.since = Some(last - 1);
+        }
+        query.latest = false;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// Rebuilds sessions, memories and overlay writes from the full event log.
+/// `replay --into DIR` writes them to a fresh store at `DIR/cozo` (pending
+/// overlay files under `DIR/overlay`); `diff` compares them with live state.
+async fn run_replay_command(args: &[String]) -> Result<()> {
+    use sly::io::query_socket::{Endpoint, QueryRequest};
+    use sly::memory::{replay, EventQuery};
+    use std::collections::HashSet;
+
+    const PAGE: usize = 10_000;
+
+    let mut endpoint = Endpoint::open(Path::new(SLY_DIR)).await?;
+
+    // `since` is exclusive, so each page restarts one millisecond early and
+    // skips the ids already read at the boundary.
+    let mut events = Vec::new();
+    let mut query = EventQuery { limit: PAGE, ..Default::default() };
+    let mut boundary: HashSet<String> = HashSet::new();
+    loop {
+        let page = endpoint.query_events(&query).await?;
+        let full = page.len() == PAGE;
+        let Some(last) = page.last().map(|e| e.timestamp) else { break };
+        let fresh: Vec<_> = page.into_iter().filter(|e| !boundary.contains(&e.id)).collect();
+        if fresh.is_empty() {
+            break;
+        }
+        boundary = fresh.iter().filter(|e| e.timestamp == last).map(|e| e.id.clone()).collect();
+        events.extend(fresh);
+        if !full {
+            break;
+        }
+        query.since = Some(last - 1);
+    }
+
+    let (rebuilt, report) = replay::ReplayState::rebuild(&events);
+    println!("{} Replayed {} events: {} applied, {} sessions, {} memories, {} files",
+        "🔁".cyan(), events.len(), report.applied, rebuilt.sessions.len(), rebuilt.memories.len(), rebuilt.overlay_files().count());
+    for error in &report.errors {
+        eprintln!("   {} {}", "⚠️".yellow(), error);
+    }
+
+    let flag = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str);
+    if args[0] == "replay" {
+        let Some(dir) = flag("--into") else { return Ok(()) };
+        let dir = Path::new(dir);
+        if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+            anyhow::bail!("{} is not empty; replay needs a fresh directory", dir.display());
+        }
+        let config = SlyConfig::load();
+        let engine = match config.storage.engine {
+            StorageEngine::Memory => StorageEngine::RocksDb,
+            engine => engine,
+        };
+        let target = Memory::open(&dir.join("cozo").to_string_lossy(), false, engine, &config.embedding).await?;
+        replay::restore(&rebuilt, &target).await?;
+        for (overlay_id, path, file) in rebuilt.overlay_files().filter(|(_, _, f)| !f.committed) {
+            let out = dir.join("overlays").join(overlay_id).join(path.trim_start_matches('/'));
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(out, &file.content)?;
+        }
+        println!("{} Rebuilt store written to {}", "✅".green(), dir.display());
+        return Ok(());
+    }
+
+    let live: replay::ReplayState = serde_json::from_value(endpoint.request(&QueryRequest::Snapshot).await?)?;
+    let workspace = env::current_dir()?;
+    let mut differences = rebuilt.diff(&live);
+    differences.extend(rebuilt.diff_overlay(|overlay_id, path, committed| {
+        let root = if committed { workspace.clone() } else { sly::safety::overlay::overlay_path(overlay_id) };
+        fs::read_to_string(root.join(path.trim_start_matches('/'))).ok()
+    }));
+
+    if args.iter().any(|a| a == "--json") {
+        println!("{}", serde_json::to_string_pretty(&differences)?);
+    } else if differences.is_empty() {
+        println!("{} Live state matches the event log.", "✅".green());
+    } else {
+        for difference in &differences {
+            println!("{}", difference);
+        }
+    }
+    Ok(())
+}
+
+/// Read-only Datalog against the store, through the running agent when
+/// there is one (it holds the RocksDB lock) and directly otherwise.
+async fn run_query_command(args: &[String]) -> Result<()> {
+    use sly::io::query_socket::{Endpoint, QueryRequest};
+
+    if args.is_empty() {
+        eprintln!("Usage: sly query <datalog_script>");
+        return Ok(());
+    }
+    let script = args.join(" ");
+    let result = Endpoint::open(Path::new(SLY_DIR)).await?.request(&QueryRequest::Query { script }).await?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn run_db_command(args: &[String]) -> Result<()> {
+    use sly::memory::backend_cozo::CozoBackend;
+    use sly::memory::migrations::{self, SCHEMA_VERSION};
+    use sly::memory::transfer::{self, ConflictPolicy};
+
+    const USAGE: &str = "Usage: sly db migrate [--dry-run]\n       sly db export [--format jsonl|backup] <file>\n       sly db import [--format jsonl|backup] [--on-conflict skip|overwrite|fail] <file>";
+
+    let path = format!("{}/cozo", SLY_DIR);
+    let flag = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).map(String::as_str);
+    let file = args.iter().enumerate().skip(1)
+        .filter(|(i, a)| !a.starts_with("--") && !args[i - 1].starts_with("--"))
+        .map(|(_, a)| Path::new(a))
+        .next_back();
+    let backup = flag("--format") == Some("backup");
+
+    match args.first().map(String::as_str) {
+        Some("migrate") => {
+            let dry_run = args.iter().any(|a| a == "--dry-run");
+            // A writable open applies pending migrations on its own.
+            let backend = CozoBackend::new(&path, dry_run)?;
+            if dry_run {
+                let steps = backend.migrate(true)?;
+                if steps.is_empty() {
+                    println!("{} Nothing to migrate.", "✅".green());
+                }
+                for step in steps {
+                    println!("{} v{}: {}", "🗄️".cyan(), step.version, step.description);
+                    for change in &step.changes {
+                        println!("    {}", migrations::change_script(change, backend.dim).replace('\n', " "));
+                    }
+                }
+            }
+            println!("Store schema v{} (binary supports v{})", backend.schema_version()?, SCHEMA_VERSION);
+            Ok(())
+        }
+        Some("export") => {
+            let Some(file) = file else {
+                eprintln!("{}", USAGE);
+                return Ok(());
+            };
+            let backend = CozoBackend::new(&path, true)?;
+            if backup {
+                transfer::export_backup(&backend, file)?;
+            } else {
+                let mut out = std::io::BufWriter::new(fs::File::create(file)?);
+                let counts = transfer::export_jsonl(&backend, &mut out, transfer::EXPORT_RELATIONS)?;
+                for (relation, count) in counts {
+                    println!("   {:<18} {}", relation, count);
+                }
+            }
+            println!("{} Exported store to {}", "📤".cyan(), file.display());
+            Ok(())
+        }
+        Some("import") => {
+            let Some(file) = file else {
+                eprintln!("{}", USAGE);
+                return Ok(());
+            };
+            let policy: ConflictPolicy = flag("--on-conflict").unwrap_or("skip").parse()?;
+            let mut backend = CozoBackend::new(&path, false)?;
+            if backup {
+                if policy != ConflictPolicy::Overwrite {
+                    anyhow::bail!("Backup imports always overwrite existing keys; pass --on-conflict overwrite");
+                }
+                transfer::import_backup(&backend, file)?;
+            } else {
+                let input = std::io::BufReader::new(fs::File::open(file)?);
+                let report = transfer::import_jsonl(&mut backend, input, policy)?;
+                for (relation, (rows, conflicts)) in report.relations {
+                    println!("   {:<18} {} rows, {} existing keys", relation, rows, conflicts);
+                }
+            }
+            println!("{} Imported {} ({:?} on conflict)", "📥".cyan(), file.display(), policy);
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
 fn init_workspace() -> Result<()> {
     let sly_path = Path::new(SLY_DIR);
     if sly_path.exists() {
@@ -1,35 +1,179 @@
 use anyhow::{anyhow, Context, Result};
+use colored::*;
+use futures::future::BoxFuture;
 use serde_json::{json, Value};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
 
 use crate::mcp::transport::Transport;
 use crate::mcp::types::{
-    ClientCapabilities, ClientInfo, InitializeParams, JsonRpcRequest, JsonRpcResponse, Tool,
+    CallToolResult, ClientCapabilities, ClientInfo, GetPromptResult, InitializeParams, JsonRpcError, JsonRpcMessage, JsonRpcRequest,
+    JsonRpcResponse, Prompt, Resource, ResourceContents, ServerCapabilities, Tool, METHOD_NOT_FOUND, PROTOCOL_VERSION,
 };
 
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Called with the `params` of every notification of the method it was
+/// registered for. Runs on the reader task, so it must not block.
+pub type NotificationHandler = Arc<dyn Fn(Option<Value>) + Send + Sync>;
+
+/// Answers server-to-client requests (`roots/list`, `sampling/createMessage`)
+/// of the method it was registered for. Each call runs on its own task, so
+/// it may take as long as it needs.
+pub type RequestHandler = Arc<dyn Fn(Option<Value>) -> BoxFuture<'static, Result<Value, JsonRpcError>> + Send + Sync>;
+
+/// State shared between the client and its reader task.
+struct Shared {
+    transport: Arc<dyn Transport>,
+    /// Requests awaiting a response, keyed by their serialized id.
+    pending: std::sync::Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>,
+    handlers: RwLock<HashMap<String, Vec<NotificationHandler>>>,
+    request_handlers: RwLock<HashMap<String, RequestHandler>>,
+    /// Flips to true once the server's output has ended.
+    closed: watch::Sender<bool>,
+}
+
+/// JSON-RPC client for one MCP server. Any number of requests can be in
+/// flight; a background task reads the server's output and routes each
+/// response to the request with the same id and each notification to the
+/// handlers registered for its method.
 pub struct McpClient {
-    transport: Arc<Box<dyn Transport>>,
+    shared: Arc<Shared>,
+    next_id: AtomicU64,
+    timeout: Duration,
+    reader: JoinHandle<()>,
     server_info: Arc<Mutex<Option<ClientInfo>>>,
-    server_capabilities: Arc<Mutex<Option<ClientCapabilities>>>,
+    server_capabilities: Arc<Mutex<Option<ServerCapabilities>>>,
 }
 
 impl McpClient {
+    /// Must be called from within the Tokio runtime (spawns the reader).
     pub fn new(transport: Box<dyn Transport>) -> Self {
+        let shared = Arc::new(Shared {
+            transport: Arc::from(transport),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            handlers: RwLock::new(HashMap::new()),
+            request_handlers: RwLock::new(HashMap::new()),
+            closed: watch::Sender::new(false),
+        });
+        let reader = tokio::spawn(read_loop(shared.clone()));
         Self {
-            transport: Arc::new(transport),
+            shared,
+            next_id: AtomicU64::new(1),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            reader,
             server_info: Arc::new(Mutex::new(None)),
             server_capabilities: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Default per-request timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// False once the server closed its output.
+    pub fn is_connected(&self) -> bool {
+        !*self.shared.closed.borrow()
+    }
+
+    /// Resolves once the server has gone away (for stdio: the process exited).
+    pub async fn closed(&self) {
+        let mut closed = self.shared.closed.subscribe();
+        let _ = closed.wait_for(|c| *c).await;
+    }
+
+    /// Recent stderr output of the server, when the transport captures it.
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.shared.transport.stderr_tail()
+    }
+
+    /// Round-trip time of a `ping`. Any answer counts, even an error from a
+    /// server that does not implement it.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration> {
+        let started = std::time::Instant::now();
+        self.call("ping", None, timeout).await?;
+        Ok(started.elapsed())
+    }
+
+    /// Registers `handler` for notifications named `method`
+    /// (e.g. `notifications/tools/list_changed`).
+    pub fn on_notification(&self, method: &str, handler: impl Fn(Option<Value>) + Send + Sync + 'static) {
+        let mut handlers = self.shared.handlers.write().unwrap_or_else(|e| e.into_inner());
+        handlers.entry(method.to_string()).or_default().push(Arc::new(handler));
+    }
+
+    /// Answers the server's `method` requests with `handler`. Register
+    /// before `initialize`: the capabilities Sly advertises follow from
+    /// which of `roots/list` and `sampling/createMessage` have handlers.
+    pub fn on_request<F, Fut>(&self, method: &str, handler: F)
+    where
+        F: Fn(Option<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value, JsonRpcError>> + Send + 'static,
+    {
+        let handler: RequestHandler = Arc::new(move |params| Box::pin(handler(params)));
+        self.shared.request_handlers.write().unwrap_or_else(|e| e.into_inner()).insert(method.to_string(), handler);
+    }
+
+    pub async fn request(&self, method: &str, params: Option<Value>) -> Result<Value> {
+        self.request_with_timeout(method, params, self.timeout).await
+    }
+
+    /// Sends a request and waits for its response. On timeout, or when the
+    /// returned future is dropped first, the server is sent
+    /// `notifications/cancelled` for it.
+    pub async fn request_with_timeout(&self, method: &str, params: Option<Value>, timeout: Duration) -> Result<Value> {
+        let response = self.call(method, params, timeout).await?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("MCP {} error ({}): {}", method, error.code, error.message));
+        }
+        Ok(response.result.unwrap_or(Value::Null))
+    }
+
+    /// Sends a request and returns whatever the server answered.
+    async fn call(&self, method: &str, params: Option<Value>, timeout: Duration) -> Result<JsonRpcResponse> {
+        if !self.is_connected() {
+            return Err(anyhow!("MCP server has disconnected"));
+        }
+        let id = Value::from(self.next_id.fetch_add(1, Ordering::SeqCst));
+        let (tx, rx) = oneshot::channel();
+        self.shared.pending.lock().unwrap_or_else(|e| e.into_inner()).insert(id.to_string(), tx);
+        let mut in_flight = InFlight { shared: self.shared.clone(), id: id.clone(), done: false };
+
+        if let Err(e) = self.shared.transport.send(&JsonRpcRequest::new(method, params, Some(id))).await {
+            in_flight.finish();
+            return Err(e.context(format!("Failed to send MCP request {}", method)));
+        }
+
+        let response = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => {
+                in_flight.finish();
+                return Err(anyhow!("MCP server closed the connection before answering {}", method));
+            }
+            // Dropping `in_flight` cancels the request
+            Err(_) => return Err(anyhow!("MCP request {} timed out after {}s", method, timeout.as_secs_f32())),
+        };
+        in_flight.finish();
+        Ok(response)
+    }
+
+    pub async fn notify(&self, method: &str, params: Option<Value>) -> Result<()> {
+        self.shared.transport.send(&JsonRpcRequest::notification(method, params)).await
+    }
+
     pub async fn initialize(&self) -> Result<()> {
+        let answers = |method: &str| self.shared.request_handlers.read().unwrap_or_else(|e| e.into_inner()).contains_key(method);
         let params = InitializeParams {
-            protocol_version: "2024-11-05".to_string(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
             capabilities: ClientCapabilities {
-                roots: Some(std::collections::HashMap::new()),
-                sampling: None,
+                roots: answers("roots/list").then(|| HashMap::from([("listChanged".to_string(), json!(false))])),
+                sampling: answers("sampling/createMessage").then(HashMap::new),
             },
             client_info: ClientInfo {
                 name: "sly-mcp-client".to_string(),
@@ -37,94 +181,272 @@ impl McpClient {
             },
         };
 
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "initialize".to_string(),
-            params: Some(serde_json::to_value(params)?),
-            id: Some(Value::Number(serde_json::Number::from(1))),
-        };
+        let result = self.request("initialize", Some(serde_json::to_value(params)?)).await?;
+        let info: ClientInfo = serde_json::from_value(result["serverInfo"].clone())
+            .context("Missing serverInfo in initialize response")?;
+        let caps: ServerCapabilities = serde_json::from_value(result["capabilities"].clone())
+            .context("Missing capabilities in initialize response")?;
 
-        self.transport.send(&request).await?;
+        *self.server_info.lock().await = Some(info);
+        *self.server_capabilities.lock().await = Some(caps);
 
-        // Wait for response (Blocking for now during init)
-        // In a real async actor system, we'd have a correlation map.
-        // For simplified "Design First", we assume simple sequential init.
-        if let Some(line) = self.transport.receive_line().await? {
-            let response: JsonRpcResponse = serde_json::from_str(&line)?;
-            
-            if let Some(error) = response.error {
-                 return Err(anyhow!("MCP Initialize Error: {}", error.message));
-            }
+        self.notify("notifications/initialized", None).await
+    }
+
+    /// What the server said it supports; `None` before `initialize`.
+    pub async fn capabilities(&self) -> Option<ServerCapabilities> {
+        self.server_capabilities.lock().await.clone()
+    }
+
+    pub async fn list_tools(&self) -> Result<Vec<Tool>> {
+        self.list_paged("tools/list", "tools").await
+    }
+
+    pub async fn list_resources(&self) -> Result<Vec<Resource>> {
+        self.list_paged("resources/list", "resources").await
+    }
+
+    pub async fn read_resource(&self, uri: &str) -> Result<Vec<ResourceContents>> {
+        let result = self.request("resources/read", Some(json!({ "uri": uri }))).await?;
+        Ok(serde_json::from_value(result["contents"].clone()).unwrap_or_default())
+    }
+
+    /// Asks for `notifications/resources/updated` when `uri` changes.
+    pub async fn subscribe_resource(&self, uri: &str) -> Result<()> {
+        self.request("resources/subscribe", Some(json!({ "uri": uri }))).await.map(|_| ())
+    }
+
+    pub async fn list_prompts(&self) -> Result<Vec<Prompt>> {
+        self.list_paged("prompts/list", "prompts").await
+    }
 
-            if let Some(result) = response.result {
-                 // Store server info from result["serverInfo"] / ["capabilities"]
-                 let info: ClientInfo = serde_json::from_value(result["serverInfo"].clone())
-                     .context("Missing serverInfo in initialize response")?;
-                 
-                 let caps: ClientCapabilities = serde_json::from_value(result["capabilities"].clone())
-                     .context("Missing capabilities in initialize response")?;
-
-                 *self.server_info.lock().await = Some(info);
-                 *self.server_capabilities.lock().await = Some(caps);
-
-                 // Send initialized notification
-                 let notification = JsonRpcRequest {
-                     jsonrpc: "2.0".to_string(),
-                     method: "notifications/initialized".to_string(),
-                     params: None,
-                     id: None,
-                 };
-                 self.transport.send(&notification).await?;
-
-                 return Ok(());
+    pub async fn get_prompt(&self, name: &str, arguments: Value) -> Result<GetPromptResult> {
+        let result = self.request("prompts/get", Some(json!({ "name": name, "arguments": arguments }))).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Runs a list request, following `nextCursor` pages.
+    async fn list_paged<T: serde::de::DeserializeOwned>(&self, method: &str, key: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = cursor.as_ref().map(|c| json!({ "cursor": c }));
+            let result = self.request(method, params).await?;
+            if let Some(page) = result.get(key) {
+                items.extend(serde_json::from_value::<Vec<T>>(page.clone())?);
+            }
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
             }
         }
+    }
 
-        Err(anyhow!("MCP Initialize unexpected response or timeout"))
+    pub async fn call_tool(&self, name: &str, args: Value) -> Result<CallToolResult> {
+        let params = json!({
+            "name": name,
+            "arguments": args
+        });
+        let result = self.request("tools/call", Some(params)).await
+            .map_err(|e| anyhow!("Tool Call Error: {}", e))?;
+        serde_json::from_value(result).context("Malformed tools/call result")
     }
+}
 
-    pub async fn list_tools(&self) -> Result<Vec<Tool>> {
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "tools/list".to_string(),
-            params: None,
-            id: Some(Value::Number(serde_json::Number::from(2))),
-        };
-        self.transport.send(&request).await?;
-
-        if let Some(line) = self.transport.receive_line().await? {
-            let response: JsonRpcResponse = serde_json::from_str(&line)?;
-            if let Some(result) = response.result {
-                if let Some(tools_val) = result.get("tools") {
-                     let tools: Vec<Tool> = serde_json::from_value(tools_val.clone())?;
-                     return Ok(tools);
-                }
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Removes a request from the pending map when it ends without a response,
+/// telling the server to stop working on it.
+struct InFlight {
+    shared: Arc<Shared>,
+    id: Value,
+    done: bool,
+}
+
+impl InFlight {
+    fn finish(&mut self) {
+        self.shared.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id.to_string());
+        self.done = true;
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.finish();
+        if *self.shared.closed.borrow() {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let transport = self.shared.transport.clone();
+            let cancel = JsonRpcRequest::notification(
+                "notifications/cancelled",
+                Some(json!({ "requestId": self.id, "reason": "Request timed out or was abandoned" })),
+            );
+            runtime.spawn(async move {
+                let _ = transport.send(&cancel).await;
+            });
+        }
+    }
+}
+
+// --- IO ---
+
+async fn read_loop(shared: Arc<Shared>) {
+    loop {
+        match shared.transport.receive_line().await {
+            Ok(Some(line)) if line.trim().is_empty() => continue,
+            Ok(Some(line)) => match JsonRpcMessage::parse(&line) {
+                Ok(message) => dispatch(&shared, message).await,
+                Err(e) => eprintln!("     {} Unreadable MCP message ({}): {}", "⚠️".yellow(), e, line.trim()),
+            },
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("     {} MCP transport error: {}", "⚠️".red(), e);
+                break;
             }
         }
-        Ok(vec![])
-    }
-
-    pub async fn call_tool(&self, name: &str, args: Value) -> Result<Value> {
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "tools/call".to_string(),
-            params: Some(json!({
-                "name": name,
-                "arguments": args
-            })),
-            id: Some(Value::String(Uuid::new_v4().to_string())),
-        };
-        self.transport.send(&request).await?;
+    }
+    shared.closed.send_replace(true);
+    // Dropping the senders wakes every waiting request with an error
+    shared.pending.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
 
-        if let Some(line) = self.transport.receive_line().await? {
-            let response: JsonRpcResponse = serde_json::from_str(&line)?;
-            if let Some(error) = response.error {
-                return Err(anyhow!("Tool Call Error: {}", error.message));
+async fn dispatch(shared: &Shared, message: JsonRpcMessage) {
+    match message {
+        JsonRpcMessage::Response(response) => {
+            let key = response.id.as_ref().map(|id| id.to_string()).unwrap_or_default();
+            let waiter = shared.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            match waiter {
+                Some(tx) => {
+                    let _ = tx.send(response);
+                }
+                // Late answer to a request that already timed out
+                None => eprintln!("     {} MCP response for unknown request {}", "⚠️".yellow(), key),
             }
-            if let Some(result) = response.result {
-                return Ok(result);
+        }
+        JsonRpcMessage::Notification(notification) => {
+            let handlers = shared.handlers.read().unwrap_or_else(|e| e.into_inner())
+                .get(&notification.method).cloned().unwrap_or_default();
+            if handlers.is_empty() && notification.method == "notifications/message" {
+                let params = notification.params.unwrap_or_default();
+                println!("     {} [MCP {}] {}", "📜".bright_black(), params["level"].as_str().unwrap_or("info"), params["data"]);
+                return;
             }
+            for handler in handlers {
+                handler(notification.params.clone());
+            }
+        }
+        JsonRpcMessage::Request(request) => {
+            let handler = shared.request_handlers.read().unwrap_or_else(|e| e.into_inner()).get(&request.method).cloned();
+            let transport = shared.transport.clone();
+            let answer = async move {
+                let response = match (request.method.as_str(), handler) {
+                    ("ping", _) => JsonRpcResponse::success(request.id, json!({})),
+                    (_, Some(handler)) => match handler(request.params).await {
+                        Ok(result) => JsonRpcResponse::success(request.id, result),
+                        Err(error) => JsonRpcResponse::failure(request.id, error.code, error.message),
+                    },
+                    _ => JsonRpcResponse::failure(request.id, METHOD_NOT_FOUND, format!("Method not found: {}", request.method)),
+                };
+                if let Err(e) = transport.send_value(&serde_json::to_value(&response).unwrap_or_default()).await {
+                    eprintln!("     {} Failed to answer MCP {}: {}", "⚠️".yellow(), request.method, e);
+                }
+            };
+            // Handlers may wait on the model; keep reading meanwhile
+            tokio::spawn(answer);
         }
-        Err(anyhow!("Tool call returned no result"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::transport::test_support;
+    use crate::mcp::types::ToolContent;
+    use tokio::sync::mpsc;
+
+    fn connect() -> (McpClient, mpsc::UnboundedReceiver<Value>, mpsc::UnboundedSender<String>) {
+        let (transport, sent, server) = test_support::channel();
+        (McpClient::new(Box::new(transport)), sent, server)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_are_matched_by_id() {
+        let (client, mut sent, server) = connect();
+        let changed = Arc::new(AtomicU64::new(0));
+        let seen = changed.clone();
+        client.on_notification("notifications/tools/list_changed", move |_| {
+            seen.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let answer = async {
+            let first = sent.recv().await.unwrap();
+            let second = sent.recv().await.unwrap();
+            // Notification, a ping from the server, then the answers in reverse order
+            server.send(r#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#.to_string()).unwrap();
+            server.send(r#"{"jsonrpc":"2.0","method":"ping","id":"srv-1"}"#.to_string()).unwrap();
+            for request in [&second, &first] {
+                let reply = json!({ "jsonrpc": "2.0", "id": request["id"], "result": { "content": [{ "type": "text", "text": request["params"]["name"] }] } });
+                server.send(reply.to_string()).unwrap();
+            }
+            sent.recv().await.unwrap()
+        };
+        let (a, b, pong) = tokio::join!(
+            client.call_tool("a", json!({})),
+            client.call_tool("b", json!({})),
+            answer
+        );
+        assert!(matches!(&a.unwrap().content[..], [ToolContent::Text { text }] if text == "a"));
+        assert!(matches!(&b.unwrap().content[..], [ToolContent::Text { text }] if text == "b"));
+        assert_eq!((pong["id"].as_str(), pong.get("error")), (Some("srv-1"), None));
+        assert_eq!(changed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_requests_reach_handlers() {
+        let (client, mut sent, server) = connect();
+        client.on_request("roots/list", |_| async { Ok(json!({ "roots": [{ "uri": "file:///work" }] })) });
+
+        let handshake = async {
+            let init = sent.recv().await.unwrap();
+            assert_eq!(init["params"]["capabilities"], json!({ "roots": { "listChanged": false } }));
+            let reply = json!({ "jsonrpc": "2.0", "id": init["id"], "result": { "serverInfo": { "name": "s", "version": "1" }, "capabilities": {} } });
+            server.send(reply.to_string()).unwrap();
+            sent.recv().await.unwrap()
+        };
+        let (init, initialized) = tokio::join!(client.initialize(), handshake);
+        init.unwrap();
+        assert_eq!(initialized["method"], "notifications/initialized");
+
+        server.send(r#"{"jsonrpc":"2.0","method":"roots/list","id":7}"#.to_string()).unwrap();
+        assert_eq!(sent.recv().await.unwrap()["result"]["roots"][0]["uri"], "file:///work");
+        server.send(r#"{"jsonrpc":"2.0","method":"sampling/createMessage","id":8}"#.to_string()).unwrap();
+        assert_eq!(sent.recv().await.unwrap()["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request_is_cancelled() {
+        let (client, mut sent, server) = connect();
+        let err = client.request_with_timeout("tools/list", None, Duration::from_millis(20)).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        let request = sent.recv().await.unwrap();
+        let cancel = sent.recv().await.unwrap();
+        assert_eq!(cancel["method"], "notifications/cancelled");
+        assert_eq!(cancel["params"]["requestId"], request["id"]);
+        assert!(cancel.get("id").is_none());
+
+        // Closing the stream fails later requests instead of hanging them
+        drop(server);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!client.is_connected());
+        assert!(client.list_tools().await.is_err());
     }
 }
@@ -0,0 +1,182 @@
+// src/mcp/host.rs - What Sly offers the MCP servers it connects to
+//
+// Servers may ask their client for the filesystem roots to work in
+// (`roots/list`) and for completions from the client's model
+// (`sampling/createMessage`). The roots are the workspace and the overlay
+// the agent stages its changes in. Sampling is forwarded to Cortex, gated
+// by `[mcp_sampling]`: off by default, optionally limited to some servers,
+// with a cap on tokens and a per-server rate limit.
+
+use colored::*;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::core::cortex::Cortex;
+use crate::core::state::McpSamplingConfig;
+use crate::mcp::client::McpClient;
+use crate::mcp::types::{JsonRpcError, INTERNAL_ERROR, INVALID_PARAMS, SAMPLING_REJECTED};
+use crate::safety::OverlayFS;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+pub struct McpHost {
+    roots: Vec<Value>,
+    policy: McpSamplingConfig,
+    cortex: Arc<Cortex>,
+    /// When each server last sampled, within the rate window.
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+/// A `sampling/createMessage` request reduced to what Cortex takes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingRequest {
+    pub system: Option<String>,
+    /// (role, text), role being "user" or "assistant".
+    pub turns: Vec<(String, String)>,
+    pub max_tokens: u32,
+    pub temperature: Option<f64>,
+}
+
+// --- Pure Functions ---
+
+pub fn roots(workspace: &Path, overlay: &Path) -> Vec<Value> {
+    vec![
+        json!({ "uri": format!("file://{}", workspace.display()), "name": "workspace" }),
+        json!({ "uri": format!("file://{}", overlay.display()), "name": "overlay (changes staged by the agent)" }),
+    ]
+}
+
+pub fn allows_sampling(policy: &McpSamplingConfig, server: &str) -> bool {
+    policy.enabled && (policy.servers.is_empty() || policy.servers.iter().any(|s| s == server))
+}
+
+/// Records a request at `now` unless `per_minute` were already made in the
+/// last minute.
+pub fn within_rate(recent: &mut VecDeque<Instant>, now: Instant, per_minute: usize) -> bool {
+    while recent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+        recent.pop_front();
+    }
+    if recent.len() >= per_minute {
+        return false;
+    }
+    recent.push_back(now);
+    true
+}
+
+/// Reads a request's params, holding `maxTokens` to `max_tokens`. Only
+/// text messages can be forwarded.
+pub fn sampling_request(params: &Value, max_tokens: u32) -> Result<SamplingRequest, String> {
+    let messages = params["messages"].as_array().filter(|m| !m.is_empty()).ok_or("messages must be a non-empty array")?;
+    let turns = messages.iter()
+        .map(|message| match message["content"]["type"].as_str() {
+            Some("text") => Ok((
+                message["role"].as_str().unwrap_or("user").to_string(),
+                message["content"]["text"].as_str().unwrap_or_default().to_string(),
+            )),
+            other => Err(format!("Only text messages can be sampled, got {}", other.unwrap_or("no content"))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SamplingRequest {
+        system: params["systemPrompt"].as_str().map(str::to_string),
+        turns,
+        max_tokens: params["maxTokens"].as_u64().map_or(max_tokens, |t| t.min(max_tokens as u64) as u32),
+        temperature: params["temperature"].as_f64(),
+    })
+}
+
+fn rpc_error(code: i64, message: String) -> JsonRpcError {
+    JsonRpcError { code, message, data: None }
+}
+
+// --- IO ---
+
+impl McpHost {
+    pub fn new(overlay: &OverlayFS, policy: McpSamplingConfig, cortex: Arc<Cortex>) -> Self {
+        Self {
+            roots: roots(overlay.base_dir(), overlay.overlay_dir()),
+            policy,
+            cortex,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers Sly's answers on a fresh client for `server`; call before
+    /// `initialize` so the capabilities are advertised.
+    pub fn attach(self: &Arc<Self>, server: &str, client: &McpClient) {
+        let roots = self.roots.clone();
+        client.on_request("roots/list", move |_| {
+            let roots = roots.clone();
+            async move { Ok(json!({ "roots": roots })) }
+        });
+
+        if !allows_sampling(&self.policy, server) {
+            return;
+        }
+        let host = self.clone();
+        let server = server.to_string();
+        client.on_request("sampling/createMessage", move |params| {
+            let (host, server) = (host.clone(), server.clone());
+            async move { host.sample(&server, params.unwrap_or_default()).await }
+        });
+    }
+
+    async fn sample(&self, server: &str, params: Value) -> Result<Value, JsonRpcError> {
+        let admitted = {
+            let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+            within_rate(recent.entry(server.to_string()).or_default(), Instant::now(), self.policy.per_minute)
+        };
+        if !admitted {
+            return Err(rpc_error(SAMPLING_REJECTED, format!("Sampling limit reached ({} per minute)", self.policy.per_minute)));
+        }
+        let request = sampling_request(&params, self.policy.max_tokens).map_err(|e| rpc_error(INVALID_PARAMS, e))?;
+
+        println!("   {} MCP server {} is sampling the model ({} messages, up to {} tokens)", "🧪".cyan(), server, request.turns.len(), request.max_tokens);
+        let text = self.cortex.complete(request.system.as_deref(), &request.turns, request.max_tokens, request.temperature).await
+            .map_err(|e| rpc_error(INTERNAL_ERROR, format!("{:#}", e)))?;
+        Ok(json!({
+            "role": "assistant",
+            "content": { "type": "text", "text": text },
+            "model": self.cortex.config.primary_model,
+            "stopReason": "endTurn"
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_policy() {
+        let mut policy = McpSamplingConfig { servers: vec!["search".into()], max_tokens: 500, per_minute: 2, ..Default::default() };
+        assert!(!allows_sampling(&policy, "search"), "off by default");
+        policy.enabled = true;
+        assert!(allows_sampling(&policy, "search"));
+        assert!(!allows_sampling(&policy, "files"));
+
+        let params = json!({
+            "systemPrompt": "Summarize.",
+            "maxTokens": 4000,
+            "messages": [
+                { "role": "user", "content": { "type": "text", "text": "hi" } },
+                { "role": "assistant", "content": { "type": "text", "text": "hello" } }
+            ]
+        });
+        let request = sampling_request(&params, policy.max_tokens).unwrap();
+        assert_eq!(request.max_tokens, 500);
+        assert_eq!(request.system.as_deref(), Some("Summarize."));
+        assert_eq!(request.turns[1], ("assistant".to_string(), "hello".to_string()));
+        let image = json!({ "messages": [{ "role": "user", "content": { "type": "image", "data": "", "mimeType": "image/png" } }] });
+        assert!(sampling_request(&image, 500).is_err());
+
+        let mut recent = VecDeque::new();
+        let start = Instant::now();
+        assert!(within_rate(&mut recent, start, policy.per_minute));
+        assert!(within_rate(&mut recent, start, policy.per_minute));
+        assert!(!within_rate(&mut recent, start + Duration::from_secs(30), policy.per_minute));
+        assert!(within_rate(&mut recent, start + RATE_WINDOW, policy.per_minute));
+    }
+}
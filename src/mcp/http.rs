@@ -0,0 +1,458 @@
+// src/mcp/http.rs - MCP over HTTP
+//
+// Two transports for servers running as services:
+// - `HttpTransport`, the streamable HTTP transport: every message is POSTed
+//   to one endpoint, answers come back as a JSON body or an SSE stream, and
+//   a GET stream (when the server offers one) carries server-initiated
+//   messages. The `Mcp-Session-Id` the server assigns is sent on every
+//   request, and a dropped GET stream is resumed with `Last-Event-ID`.
+// - `SseTransport`, the legacy transport: a GET SSE stream whose first
+//   `endpoint` event names the URL to POST messages to.
+// Both hand incoming messages to the client through a channel, one JSON
+// document per `receive_line`.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use futures::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::mcp::transport::Transport;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+const LAST_EVENT_HEADER: &str = "last-event-id";
+const STREAM_ACCEPT: &str = "application/json, text/event-stream";
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// One server-sent event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental `text/event-stream` parser; chunks may split lines anywhere.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    current: SseEvent,
+    has_data: bool,
+}
+
+// --- Pure Functions ---
+
+impl SseParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&raw);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if self.has_data {
+                    events.push(std::mem::take(&mut self.current));
+                }
+                self.current = SseEvent::default();
+                self.has_data = false;
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+            match field {
+                "data" => {
+                    if self.has_data {
+                        self.current.data.push('\n');
+                    }
+                    self.current.data.push_str(value);
+                    self.has_data = true;
+                }
+                "event" => self.current.event = Some(value.to_string()),
+                "id" => self.current.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Splits a JSON body into single messages (it may be a batch array).
+fn json_messages(body: &str) -> Vec<String> {
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Array(batch)) => batch.iter().map(Value::to_string).collect(),
+        Ok(single) => vec![single.to_string()],
+        Err(_) if body.trim().is_empty() => vec![],
+        Err(_) => vec![body.to_string()],
+    }
+}
+
+fn build_headers(headers: &HashMap<String, String>, auth_token: Option<&str>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(
+            HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid header name {}", name))?,
+            HeaderValue::from_str(value).with_context(|| format!("Invalid value for header {}", name))?,
+        );
+    }
+    if let Some(token) = auth_token {
+        map.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).context("Invalid auth token")?);
+    }
+    Ok(map)
+}
+
+fn is_event_stream(response: &reqwest::Response) -> bool {
+    response.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+// --- IO ---
+
+/// Feeds every event of an SSE response to `on_event` until the stream ends.
+async fn read_events(response: reqwest::Response, mut on_event: impl FnMut(SseEvent)) -> Result<()> {
+    let mut parser = SseParser::default();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        for event in parser.feed(&chunk?) {
+            on_event(event);
+        }
+    }
+    Ok(())
+}
+
+async fn error_for_status(response: reqwest::Response, what: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow!("{} failed with HTTP {}: {}", what, status, body.trim()))
+}
+
+/// State the POST handler and the GET listener share.
+struct HttpShared {
+    http: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+    session_id: std::sync::Mutex<Option<String>>,
+    last_event_id: std::sync::Mutex<Option<String>>,
+    incoming: mpsc::UnboundedSender<String>,
+}
+
+impl HttpShared {
+    fn request(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder.headers(self.headers.clone());
+        match self.session_id.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+            Some(id) => builder.header(SESSION_HEADER, id),
+            None => builder,
+        }
+    }
+
+    fn deliver(&self, event: SseEvent) {
+        if let Some(id) = &event.id {
+            *self.last_event_id.lock().unwrap_or_else(|e| e.into_inner()) = Some(id.clone());
+        }
+        if event.event.as_deref().unwrap_or("message") == "message" {
+            let _ = self.incoming.send(event.data);
+        }
+    }
+}
+
+/// Streamable HTTP transport (MCP 2025-03-26).
+pub struct HttpTransport {
+    shared: Arc<HttpShared>,
+    receiver: Mutex<mpsc::UnboundedReceiver<String>>,
+    listening: AtomicBool,
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl HttpTransport {
+    pub fn new(url: &str, headers: &HashMap<String, String>, auth_token: Option<&str>) -> Result<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            shared: Arc::new(HttpShared {
+                http: reqwest::Client::new(),
+                url: url.to_string(),
+                headers: build_headers(headers, auth_token)?,
+                session_id: std::sync::Mutex::new(None),
+                last_event_id: std::sync::Mutex::new(None),
+                incoming: tx,
+            }),
+            receiver: Mutex::new(rx),
+            listening: AtomicBool::new(false),
+            tasks: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+
+    /// The id the server assigned at initialization, if any.
+    pub fn session_id(&self) -> Option<String> {
+        self.shared.session_id.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn spawn(&self, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|t| !t.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
+}
+
+/// Keeps the server's GET stream open, resuming after the last event seen.
+/// Returns when the server does not offer one.
+async fn listen(shared: Arc<HttpShared>) {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let mut request = shared.request(shared.http.get(&shared.url)).header(ACCEPT, "text/event-stream");
+        if let Some(id) = shared.last_event_id.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+            request = request.header(LAST_EVENT_HEADER, id);
+        }
+        match request.send().await {
+            Ok(response) if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED => return,
+            Ok(response) if response.status().is_success() && is_event_stream(&response) => {
+                delay = Duration::from_secs(1);
+                let reader = shared.clone();
+                if let Err(e) = read_events(response, move |event| reader.deliver(event)).await {
+                    eprintln!("     {} MCP event stream dropped: {}", "⚠️".yellow(), e);
+                }
+            }
+            Ok(response) => {
+                eprintln!("     {} MCP event stream refused (HTTP {})", "⚠️".yellow(), response.status());
+                return;
+            }
+            Err(e) => eprintln!("     {} MCP event stream unreachable: {}", "⚠️".yellow(), e),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for HttpTransport {
+    async fn send_value(&self, message: &Value) -> Result<()> {
+        let request = self.shared.request(self.shared.http.post(&self.shared.url))
+            .header(ACCEPT, STREAM_ACCEPT)
+            .json(message);
+        let response = request.send().await.context("MCP HTTP request failed")?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND && self.session_id().is_some() {
+            return Err(anyhow!("MCP session expired; the server must be re-initialized"));
+        }
+        let response = error_for_status(response, "MCP POST").await?;
+
+        if let Some(id) = response.headers().get(SESSION_HEADER).and_then(|v| v.to_str().ok()) {
+            *self.shared.session_id.lock().unwrap_or_else(|e| e.into_inner()) = Some(id.to_string());
+        }
+
+        if is_event_stream(&response) {
+            // Answers arrive on the stream; read it in the background so
+            // other requests can go out meanwhile.
+            let shared = self.shared.clone();
+            self.spawn(async move {
+                let reader = shared.clone();
+                if let Err(e) = read_events(response, move |event| reader.deliver(event)).await {
+                    eprintln!("     {} MCP response stream dropped: {}", "⚠️".yellow(), e);
+                }
+            });
+        } else if response.status() != reqwest::StatusCode::ACCEPTED {
+            for line in json_messages(&response.text().await?) {
+                let _ = self.shared.incoming.send(line);
+            }
+        }
+
+        // The GET stream needs the session the first exchange established
+        if message.get("method").is_some_and(|m| m == "notifications/initialized") && !self.listening.swap(true, Ordering::SeqCst) {
+            self.spawn(listen(self.shared.clone()));
+        }
+        Ok(())
+    }
+
+    async fn receive_line(&self) -> Result<Option<String>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+}
+
+impl Drop for HttpTransport {
+    fn drop(&mut self) {
+        for task in self.tasks.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            task.abort();
+        }
+        // Tell the server the session is over
+        if self.session_id().is_none() {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let request = self.shared.request(self.shared.http.delete(&self.shared.url));
+            runtime.spawn(async move {
+                let _ = request.send().await;
+            });
+        }
+    }
+}
+
+/// Legacy HTTP+SSE transport (MCP 2024-11-05).
+pub struct SseTransport {
+    http: reqwest::Client,
+    headers: HeaderMap,
+    endpoint: String,
+    receiver: Mutex<mpsc::UnboundedReceiver<String>>,
+    stream: JoinHandle<()>,
+}
+
+impl SseTransport {
+    /// Opens the event stream and waits for the server to name its POST
+    /// endpoint.
+    pub async fn connect(url: &str, headers: &HashMap<String, String>, auth_token: Option<&str>) -> Result<Self> {
+        let http = reqwest::Client::new();
+        let headers = build_headers(headers, auth_token)?;
+        let response = http.get(url).headers(headers.clone()).header(ACCEPT, "text/event-stream").send().await
+            .with_context(|| format!("Failed to open MCP event stream at {}", url))?;
+        let response = error_for_status(response, "MCP SSE connect").await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (endpoint_tx, endpoint_rx) = oneshot::channel();
+        let stream = tokio::spawn(async move {
+            let mut endpoint_tx = Some(endpoint_tx);
+            let result = read_events(response, |event| match event.event.as_deref() {
+                Some("endpoint") => {
+                    if let Some(reply) = endpoint_tx.take() {
+                        let _ = reply.send(event.data);
+                    }
+                }
+                None | Some("message") => {
+                    let _ = tx.send(event.data);
+                }
+                Some(_) => {}
+            }).await;
+            if let Err(e) = result {
+                eprintln!("     {} MCP event stream dropped: {}", "⚠️".yellow(), e);
+            }
+        });
+
+        let endpoint = tokio::time::timeout(Duration::from_secs(10), endpoint_rx).await
+            .map_err(|_| anyhow!("MCP server at {} sent no endpoint event", url))?
+            .map_err(|_| anyhow!("MCP event stream at {} closed before naming an endpoint", url))?;
+        let endpoint = url::Url::parse(url)?.join(&endpoint)?.to_string();
+
+        Ok(Self { http, headers, endpoint, receiver: Mutex::new(rx), stream })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for SseTransport {
+    async fn send_value(&self, message: &Value) -> Result<()> {
+        let response = self.http.post(&self.endpoint).headers(self.headers.clone()).json(message).send().await
+            .context("MCP HTTP request failed")?;
+        error_for_status(response, "MCP POST").await.map(|_| ())
+    }
+
+    /// `None` once the event stream has ended.
+    async fn receive_line(&self) -> Result<Option<String>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+}
+
+impl Drop for SseTransport {
+    fn drop(&mut self) {
+        self.stream.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::client::McpClient;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_sse_parser_handles_split_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b": keep-alive\nid: 7\nevent: mess").is_empty());
+        let events = parser.feed(b"age\ndata: {\"a\":\r\ndata: 1}\n\ndata: x\n\n");
+        assert_eq!(events, vec![
+            SseEvent { id: Some("7".into()), event: Some("message".into()), data: "{\"a\":\n1}".into() },
+            SseEvent { id: None, event: None, data: "x".into() },
+        ]);
+    }
+
+    /// Reads one HTTP/1.1 request: method, lower-cased headers and body.
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> (String, HashMap<String, String>, String) {
+        let mut raw = Vec::new();
+        let mut byte = [0u8; 1];
+        while !raw.ends_with(b"\r\n\r\n") {
+            socket.read_exact(&mut byte).await.unwrap();
+            raw.push(byte[0]);
+        }
+        let head = String::from_utf8(raw).unwrap();
+        let mut lines = head.lines();
+        let method = lines.next().unwrap().split(' ').next().unwrap().to_string();
+        let headers: HashMap<String, String> = lines
+            .filter_map(|l| l.split_once(": "))
+            .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+            .collect();
+        let length: usize = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+        let mut body = vec![0u8; length];
+        socket.read_exact(&mut body).await.unwrap();
+        (method, headers, String::from_utf8(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_streamable_http_against_stand_in_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut seen = Vec::new();
+            while seen.len() < 4 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (method, headers, body) = read_request(&mut socket).await;
+                let message: Value = serde_json::from_str(&body).unwrap_or_default();
+                let name = message["method"].as_str().unwrap_or(&method).to_string();
+                if name != "initialize" {
+                    assert_eq!(headers.get(SESSION_HEADER).map(String::as_str), Some("sess-1"), "{}", name);
+                }
+                assert_eq!(headers.get("authorization").map(String::as_str), Some("Bearer t0k"));
+                let reply = match name.as_str() {
+                    // Answered over SSE, with the session id
+                    "initialize" => {
+                        let result = serde_json::json!({ "jsonrpc": "2.0", "id": message["id"], "result": {
+                            "serverInfo": { "name": "stand-in", "version": "1" }, "capabilities": {}
+                        }});
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nMcp-Session-Id: sess-1\r\nConnection: close\r\n\r\nid: 1\ndata: {}\n\n", result)
+                    }
+                    "notifications/initialized" => "HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    // Plain JSON answer
+                    "tools/list" => {
+                        let result = serde_json::json!({ "jsonrpc": "2.0", "id": message["id"], "result": { "tools": [{ "name": "echo", "inputSchema": {} }] } }).to_string();
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", result.len(), result)
+                    }
+                    // No server-initiated stream
+                    _ => "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                socket.write_all(reply.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+                seen.push(name);
+            }
+            seen
+        });
+
+        let transport = HttpTransport::new(&url, &HashMap::new(), Some("t0k")).unwrap();
+        let client = McpClient::new(Box::new(transport));
+        client.initialize().await.unwrap();
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools[0].name, "echo");
+
+        let mut seen = server.await.unwrap();
+        seen.sort();
+        assert_eq!(seen, vec!["GET", "initialize", "notifications/initialized", "tools/list"]);
+    }
+}
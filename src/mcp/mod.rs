@@ -1,4 +1,9 @@
 pub mod types;
 pub mod transport;
+pub mod http;
 pub mod client;
 pub mod registry;
+pub mod schema;
+pub mod supervisor;
+pub mod server;
+pub mod host;
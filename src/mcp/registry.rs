@@ -1,32 +1,404 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use anyhow::{anyhow, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use crate::core::cortex::InlineImage;
+use crate::core::state::McpServerConfig;
 use crate::mcp::client::McpClient;
+use crate::mcp::schema;
+use crate::memory::EventRecord;
+use crate::mcp::types::{CallToolResult, GetPromptResult, Prompt, Resource, ResourceContents, Tool, ToolContent};
 
 #[derive(Clone)]
 pub struct McpToolMetadata {
     pub name: String,
     pub server_name: String,
+    pub tool: Tool,
     pub client: Arc<McpClient>,
+    /// Calls wait for `sly mcp approve` before they run.
+    pub needs_approval: bool,
 }
 
-pub async fn get_all_tool_metadata(clients_mutex: &Mutex<HashMap<String, Arc<McpClient>>>) -> Vec<McpToolMetadata> {
-    let clients = clients_mutex.lock().await;
-    let mut metadata = Vec::new();
-    
-    for (name, client) in clients.iter() {
-        if let Ok(tools) = client.list_tools().await {
-            for tool in tools {
-                metadata.push(McpToolMetadata {
-                    name: tool.name,
-                    server_name: name.clone(),
-                    client: client.clone(),
+impl McpToolMetadata {
+    /// `server.tool`, unique across servers.
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", self.server_name, self.name)
+    }
+}
+
+/// What one server offered at one listing.
+#[derive(Default)]
+struct Catalog {
+    listing: u64,
+    client: Option<Arc<McpClient>>,
+    tools: Vec<McpToolMetadata>,
+    resources: Vec<Resource>,
+    prompts: Vec<Prompt>,
+}
+
+/// Sessions subscribed to each `(server, uri)`, and the updates each
+/// session has not seen yet.
+#[derive(Default)]
+struct Watchers {
+    by_resource: HashMap<(String, String), BTreeSet<String>>,
+    pending: HashMap<String, BTreeSet<(String, String)>>,
+}
+
+/// Which of a server's tools the agent sees, and which run only once
+/// approved. Patterns are tool names; a trailing `*` matches a prefix.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub require_approval: bool,
+    pub auto_approve: Vec<String>,
+}
+
+/// A tool call held for approval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingCall {
+    pub id: String,
+    pub session_id: String,
+    pub tool: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Calls waiting for a decision, and decided calls each session has not
+/// acted on yet (with whether they were approved).
+#[derive(Default)]
+struct Approvals {
+    waiting: HashMap<String, PendingCall>,
+    decided: HashMap<String, Vec<(PendingCall, bool)>>,
+}
+
+/// Event ops for tool calls held for approval, the decisions on them, and
+/// a session step taking up a decision.
+pub const APPROVAL_REQUESTED: &str = "mcp_approval_requested";
+pub const APPROVAL_DECIDED: &str = "mcp_approval_decided";
+pub const APPROVAL_CONSUMED: &str = "mcp_approval_consumed";
+
+/// Held calls the event log leaves open: still waiting for a decision, or
+/// decided (approved or not) with no step having acted on it yet.
+#[derive(Debug, Default, PartialEq)]
+pub struct OpenApprovals {
+    pub waiting: Vec<PendingCall>,
+    pub decided: Vec<(PendingCall, bool)>,
+}
+
+/// A tool result as the agent sees it: text parts joined, images set
+/// aside for the next (multimodal) model request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolOutput {
+    pub text: String,
+    pub images: Vec<InlineImage>,
+    pub is_error: bool,
+}
+
+/// Tools, resources and prompts of every connected server, listed once at
+/// connect time and again only when a server reports a `list_changed`
+/// notification or is re-attached after a reconnect. Reading it never
+/// talks to a server.
+#[derive(Default)]
+pub struct ToolRegistry {
+    servers: RwLock<HashMap<String, Catalog>>,
+    listings: AtomicU64,
+    watchers: std::sync::Mutex<Watchers>,
+    /// Tool images per session, not yet shown to the model.
+    images: std::sync::Mutex<HashMap<String, Vec<InlineImage>>>,
+    approvals: std::sync::Mutex<Approvals>,
+}
+
+const LIST_CHANGED: [&str; 3] = [
+    "notifications/tools/list_changed",
+    "notifications/resources/list_changed",
+    "notifications/prompts/list_changed",
+];
+
+// --- Pure Functions ---
+
+/// Sorts the calls `APPROVAL_REQUESTED` events held, in the order they were
+/// held, by what later approval events say happened to them.
+pub fn open_approvals(events: &[EventRecord]) -> OpenApprovals {
+    let ids = |op: &str| -> HashMap<&str, &serde_json::Value> {
+        events.iter().filter(|e| e.op == op).filter_map(|e| Some((e.data["id"].as_str()?, &e.data))).collect()
+    };
+    let (decided, consumed) = (ids(APPROVAL_DECIDED), ids(APPROVAL_CONSUMED));
+    let mut open = OpenApprovals::default();
+    for call in events.iter()
+        .filter(|e| e.op == APPROVAL_REQUESTED)
+        .filter_map(|e| serde_json::from_value::<PendingCall>(e.data.clone()).ok())
+    {
+        match decided.get(call.id.as_str()) {
+            None => open.waiting.push(call),
+            Some(_) if consumed.contains_key(call.id.as_str()) => {}
+            Some(decision) => {
+                let approved = decision["approved"].as_bool().unwrap_or(false);
+                open.decided.push((call, approved));
+            }
+        }
+    }
+    open
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => p == name,
+    })
+}
+
+impl ToolPolicy {
+    pub fn from_config(config: &McpServerConfig) -> Self {
+        Self {
+            allow: config.allow_tools.clone(),
+            deny: config.deny_tools.clone(),
+            require_approval: config.require_approval,
+            auto_approve: config.auto_approve.clone(),
+        }
+    }
+
+    /// Deny wins over allow; an empty allow list allows every tool.
+    pub fn allows(&self, tool: &str) -> bool {
+        (self.allow.is_empty() || matches_any(&self.allow, tool)) && !matches_any(&self.deny, tool)
+    }
+
+    pub fn needs_approval(&self, tool: &str) -> bool {
+        self.require_approval && !matches_any(&self.auto_approve, tool)
+    }
+}
+
+// --- IO ---
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every known tool, ordered by qualified name.
+    pub fn snapshot(&self) -> Vec<McpToolMetadata> {
+        let servers = self.servers.read().unwrap_or_else(|e| e.into_inner());
+        let mut metadata: Vec<McpToolMetadata> = servers.values().flat_map(|c| &c.tools).cloned().collect();
+        metadata.sort_by_key(|m| m.qualified_name());
+        metadata
+    }
+
+    /// Every known resource with its server, ordered by server then uri.
+    pub fn resources(&self) -> Vec<(String, Resource)> {
+        let servers = self.servers.read().unwrap_or_else(|e| e.into_inner());
+        let mut resources: Vec<(String, Resource)> = servers.iter()
+            .flat_map(|(name, c)| c.resources.iter().map(move |r| (name.clone(), r.clone())))
+            .collect();
+        resources.sort_by(|a, b| (&a.0, &a.1.uri).cmp(&(&b.0, &b.1.uri)));
+        resources
+    }
+
+    /// Every known prompt with its server, ordered by `server.prompt`.
+    pub fn prompts(&self) -> Vec<(String, Prompt)> {
+        let servers = self.servers.read().unwrap_or_else(|e| e.into_inner());
+        let mut prompts: Vec<(String, Prompt)> = servers.iter()
+            .flat_map(|(name, c)| c.prompts.iter().map(move |p| (name.clone(), p.clone())))
+            .collect();
+        prompts.sort_by(|a, b| (&a.0, &a.1.name).cmp(&(&b.0, &b.1.name)));
+        prompts
+    }
+
+    pub fn server_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.servers.read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn client(&self, server_name: &str) -> Option<Arc<McpClient>> {
+        self.servers.read().unwrap_or_else(|e| e.into_inner()).get(server_name).and_then(|c| c.client.clone())
+    }
+
+    /// Forgets a server's tools, resources and prompts (it disconnected).
+    pub fn remove(&self, server_name: &str) {
+        self.servers.write().unwrap_or_else(|e| e.into_inner()).remove(server_name);
+    }
+
+    /// Lists what `client` offers now and keeps it current. Returns how many
+    /// tools the server offers that `policy` lets the agent see.
+    pub async fn attach(self: &Arc<Self>, server_name: &str, client: Arc<McpClient>, policy: ToolPolicy) -> Result<usize> {
+        // Registered first so a change during the initial listing is not
+        // missed. Weak on both sides: the handlers live inside the client.
+        for method in LIST_CHANGED {
+            let registry = Arc::downgrade(self);
+            let weak_client = Arc::downgrade(&client);
+            let server = server_name.to_string();
+            let policy = policy.clone();
+            client.on_notification(method, move |_| {
+                let (Some(registry), Some(client)) = (registry.upgrade(), weak_client.upgrade()) else {
+                    return;
+                };
+                let (server, policy) = (server.clone(), policy.clone());
+                tokio::spawn(async move {
+                    match registry.refresh(&server, &client, &policy).await {
+                        Ok(n) => println!("     {} {} listing changed ({} tools)", "🔄".cyan(), server, n),
+                        Err(e) => eprintln!("     {} Could not re-list {}: {}", "⚠️".yellow(), server, e),
+                    }
                 });
+            });
+        }
+        let registry = Arc::downgrade(self);
+        let server = server_name.to_string();
+        client.on_notification("notifications/resources/updated", move |params| {
+            let uri = params.as_ref().and_then(|p| p["uri"].as_str()).unwrap_or_default();
+            if let Some(registry) = registry.upgrade() {
+                registry.mark_updated(&server, uri);
             }
+        });
+        self.refresh(server_name, &client, &policy).await
+    }
+
+    async fn refresh(&self, server_name: &str, client: &Arc<McpClient>, policy: &ToolPolicy) -> Result<usize> {
+        let listing = self.listings.fetch_add(1, Ordering::SeqCst) + 1;
+        let tools = client.list_tools().await?;
+        let caps = client.capabilities().await.unwrap_or_default();
+        // Resources and prompts are optional; a server failing to list them
+        // still offers its tools.
+        let resources = match caps.resources {
+            Some(_) => client.list_resources().await.unwrap_or_else(|e| {
+                eprintln!("     {} {} resources unavailable: {}", "⚠️".yellow(), server_name, e);
+                vec![]
+            }),
+            None => vec![],
+        };
+        let prompts = match caps.prompts {
+            Some(_) => client.list_prompts().await.unwrap_or_else(|e| {
+                eprintln!("     {} {} prompts unavailable: {}", "⚠️".yellow(), server_name, e);
+                vec![]
+            }),
+            None => vec![],
+        };
+
+        let tools: Vec<McpToolMetadata> = tools.into_iter().filter(|tool| policy.allows(&tool.name)).map(|tool| McpToolMetadata {
+            name: tool.name.clone(),
+            server_name: server_name.to_string(),
+            needs_approval: policy.needs_approval(&tool.name),
+            tool,
+            client: client.clone(),
+        }).collect();
+        let count = tools.len();
+        let mut servers = self.servers.write().unwrap_or_else(|e| e.into_inner());
+        // An older listing that finished late must not replace a newer one
+        if servers.get(server_name).is_none_or(|c| c.listing < listing) {
+            let catalog = Catalog { listing, client: Some(client.clone()), tools, resources, prompts };
+            servers.insert(server_name.to_string(), catalog);
+        }
+        Ok(count)
+    }
+
+    /// Notes that `session_id` wants to hear when `uri` changes.
+    pub fn watch(&self, server_name: &str, uri: &str, session_id: &str) {
+        let mut watchers = self.watchers.lock().unwrap_or_else(|e| e.into_inner());
+        watchers.by_resource.entry((server_name.to_string(), uri.to_string())).or_default().insert(session_id.to_string());
+    }
+
+    pub fn mark_updated(&self, server_name: &str, uri: &str) {
+        let mut watchers = self.watchers.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (server_name.to_string(), uri.to_string());
+        let sessions: Vec<String> = watchers.by_resource.get(&key).into_iter().flatten().cloned().collect();
+        for session in sessions {
+            watchers.pending.entry(session).or_default().insert(key.clone());
+        }
+    }
+
+    /// Resources `session_id` watches that changed since it last asked, as
+    /// `(server, uri)`.
+    pub fn take_updates(&self, session_id: &str) -> Vec<(String, String)> {
+        let mut watchers = self.watchers.lock().unwrap_or_else(|e| e.into_inner());
+        watchers.pending.remove(session_id).map(|s| s.into_iter().collect()).unwrap_or_default()
+    }
+
+    /// Holds `images` for the next model request of `session_id`.
+    pub fn attach_images(&self, session_id: &str, images: Vec<InlineImage>) {
+        if images.is_empty() {
+            return;
         }
+        let mut pending = self.images.lock().unwrap_or_else(|e| e.into_inner());
+        pending.entry(session_id.to_string()).or_default().extend(images);
+    }
+
+    pub fn take_images(&self, session_id: &str) -> Vec<InlineImage> {
+        self.images.lock().unwrap_or_else(|e| e.into_inner()).remove(session_id).unwrap_or_default()
+    }
+
+    /// Holds a call of `tool` until `decide` is called with the returned id.
+    pub fn hold(&self, session_id: &str, tool: &str, arguments: serde_json::Value) -> PendingCall {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        let call = PendingCall { id: id.clone(), session_id: session_id.to_string(), tool: tool.to_string(), arguments };
+        self.approvals.lock().unwrap_or_else(|e| e.into_inner()).waiting.insert(id, call.clone());
+        call
+    }
+
+    /// Puts back the calls that were open when the agent last stopped.
+    pub fn restore_approvals(&self, open: OpenApprovals) {
+        let mut approvals = self.approvals.lock().unwrap_or_else(|e| e.into_inner());
+        approvals.waiting.extend(open.waiting.into_iter().map(|call| (call.id.clone(), call)));
+        for (call, approved) in open.decided {
+            approvals.decided.entry(call.session_id.clone()).or_default().push((call, approved));
+        }
+    }
+
+    /// Approves or rejects a held call; the session acts on it next step.
+    pub fn decide(&self, id: &str, approved: bool) -> Result<PendingCall> {
+        let mut approvals = self.approvals.lock().unwrap_or_else(|e| e.into_inner());
+        let call = approvals.waiting.remove(id).ok_or_else(|| anyhow!("No tool call is waiting for approval as {}", id))?;
+        approvals.decided.entry(call.session_id.clone()).or_default().push((call.clone(), approved));
+        Ok(call)
+    }
+
+    /// Held calls nobody decided on yet, by id.
+    pub fn waiting(&self) -> Vec<PendingCall> {
+        let mut calls: Vec<PendingCall> = self.approvals.lock().unwrap_or_else(|e| e.into_inner()).waiting.values().cloned().collect();
+        calls.sort_by(|a, b| a.id.cmp(&b.id));
+        calls
+    }
+
+    pub fn take_decisions(&self, session_id: &str) -> Vec<(PendingCall, bool)> {
+        self.approvals.lock().unwrap_or_else(|e| e.into_inner()).decided.remove(session_id).unwrap_or_default()
+    }
+
+    /// The server that serves `uri`: `server` when given, else the one that
+    /// listed it, else the only connected server.
+    pub fn locate_resource(&self, uri: &str, server: Option<&str>) -> Result<(String, Arc<McpClient>)> {
+        let name = match server {
+            Some(name) => name.to_string(),
+            None => {
+                let listed: Vec<String> = self.resources().into_iter().filter(|(_, r)| r.uri == uri).map(|(s, _)| s).collect();
+                let servers = self.server_names();
+                match (listed.as_slice(), servers.as_slice()) {
+                    ([one], _) | ([], [one]) => one.clone(),
+                    _ => return Err(anyhow!("Cannot tell which server serves {}; pass `server`", uri)),
+                }
+            }
+        };
+        let client = self.client(&name).ok_or_else(|| anyhow!("No MCP server named {}", name))?;
+        Ok((name, client))
+    }
+
+    /// Finds a prompt by `server.prompt`, or by a bare name only one server
+    /// offers. Returns the server and the prompt's own name.
+    pub fn resolve_prompt(&self, name: &str) -> Result<(String, String, Arc<McpClient>)> {
+        let prompts = self.prompts();
+        let exact = prompts.iter().find(|(server, p)| format!("{}.{}", server, p.name) == name);
+        let found = match exact {
+            Some(found) => found,
+            None => {
+                let matches: Vec<&(String, Prompt)> = prompts.iter().filter(|(_, p)| p.name == name).collect();
+                match matches.as_slice() {
+                    [one] => *one,
+                    [] => return Err(anyhow!("Prompt not found: {}", name)),
+                    _ => return Err(anyhow!("Prompt name {} is ambiguous; qualify it as server.prompt", name)),
+                }
+            }
+        };
+        let client = self.client(&found.0).ok_or_else(|| anyhow!("No MCP server named {}", found.0))?;
+        Ok((found.0.clone(), found.1.name.clone(), client))
     }
-    metadata
 }
 
 pub async fn get_tool_definitions(metadata: &[McpToolMetadata]) -> String {
@@ -34,33 +406,260 @@ pub async fn get_tool_definitions(metadata: &[McpToolMetadata]) -> String {
         return String::new();
     }
 
-    let mut all_tools = Vec::new();
-    for meta in metadata {
-        // We need the full tool object for the definition. 
-        // For now, let's assume we can reconstruct or we should have stored it.
-        // Let's just use a placeholder or simpler JSON if we don't have the full Tool object here.
-        // Actually, let's modify McpToolMetadata to include the full Tool object.
-        all_tools.push(serde_json::json!({
-            "name": meta.name,
-            "server": meta.server_name,
-        }));
-    }
+    let all_tools: Vec<serde_json::Value> = metadata.iter().map(|meta| serde_json::json!({
+        "name": meta.qualified_name(),
+        "description": meta.tool.description,
+        "inputSchema": meta.tool.input_schema,
+    })).collect();
 
     format!(
-        "\n## AVAILABLE MCP TOOLS\n\nYou have access to the following external tools:\n```json\n{}\n```\n",
+        "\n## AVAILABLE MCP TOOLS\n\nYou have access to the following external tools. Call them with the `CallTool` directive, \
+         using the `server.tool` name as `tool_name` and `arguments` matching `inputSchema`:\n```json\n{}\n```\n",
         serde_json::to_string_pretty(&all_tools).unwrap_or_default()
     )
 }
 
+/// Finds `tool_name`, either `server.tool` or a bare tool name that only
+/// one server offers.
+pub fn resolve_tool<'a>(metadata: &'a [McpToolMetadata], tool_name: &str) -> Result<&'a McpToolMetadata> {
+    if let Some(meta) = metadata.iter().find(|m| m.qualified_name() == tool_name) {
+        return Ok(meta);
+    }
+    let matches: Vec<&McpToolMetadata> = metadata.iter().filter(|m| m.name == tool_name).collect();
+    match matches.as_slice() {
+        [meta] => Ok(meta),
+        [] => Err(anyhow!("Tool not found: {}", tool_name)),
+        many => Err(anyhow!(
+            "Tool name {} is ambiguous; use one of: {}",
+            tool_name,
+            many.iter().map(|m| m.qualified_name()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
 pub async fn call_mcp_tool(
     metadata: &[McpToolMetadata],
-    tool_name: &str, 
+    tool_name: &str,
     args: serde_json::Value
-) -> Result<serde_json::Value> {
-    let meta = metadata.iter()
-        .find(|m| m.name == tool_name)
-        .ok_or_else(|| anyhow!("Tool not found: {}", tool_name))?;
+) -> Result<CallToolResult> {
+    let meta = resolve_tool(metadata, tool_name)?;
+
+    let problems = schema::validate(&meta.tool.input_schema, &args);
+    if !problems.is_empty() {
+        return Err(anyhow!("Invalid arguments for {}:\n- {}", meta.qualified_name(), problems.join("\n- ")));
+    }
+
+    println!("    Found tool {} on server {}", meta.name, meta.server_name);
+    meta.client.call_tool(&meta.name, args).await
+}
+
+/// Resources and prompts the agent can pull in, for the prompt preamble.
+pub fn get_context_definitions(resources: &[(String, Resource)], prompts: &[(String, Prompt)]) -> String {
+    let mut out = String::new();
+    if !resources.is_empty() {
+        let listed: Vec<serde_json::Value> = resources.iter().map(|(server, r)| serde_json::json!({
+            "server": server, "uri": r.uri, "name": r.name, "description": r.description,
+        })).collect();
+        out.push_str(&format!(
+            "\n## AVAILABLE MCP RESOURCES\n\nRead one with `{{\"directive\": \"ReadResource\", \"uri\": ..., \"server\": ...}}`; \
+             add `\"subscribe\": true` to be shown its new contents whenever it changes:\n```json\n{}\n```\n",
+            serde_json::to_string_pretty(&listed).unwrap_or_default()
+        ));
+    }
+    if !prompts.is_empty() {
+        let listed: Vec<serde_json::Value> = prompts.iter().map(|(server, p)| serde_json::json!({
+            "name": format!("{}.{}", server, p.name), "description": p.description, "arguments": p.arguments,
+        })).collect();
+        out.push_str(&format!(
+            "\n## AVAILABLE MCP PROMPTS\n\nExpand one into this session with `{{\"directive\": \"GetPrompt\", \"name\": ..., \"arguments\": {{...}}}}`:\n```json\n{}\n```\n",
+            serde_json::to_string_pretty(&listed).unwrap_or_default()
+        ));
+    }
+    out
+}
 
-    println!("    Found tool {} on server {}", tool_name, meta.server_name);
-    meta.client.call_tool(tool_name, args).await
+pub fn render_resource_contents(contents: &[ResourceContents]) -> String {
+    contents.iter().map(|c| match (&c.text, &c.blob) {
+        (Some(text), _) => text.clone(),
+        (None, Some(blob)) => format!("[binary {} content, {} base64 bytes]", c.mime_type.as_deref().unwrap_or("unknown"), blob.len()),
+        (None, None) => "[empty]".to_string(),
+    }).collect::<Vec<_>>().join("\n\n")
+}
+
+pub fn render_tool_result(result: &CallToolResult) -> ToolOutput {
+    let mut parts = Vec::new();
+    let mut images = Vec::new();
+    for content in &result.content {
+        match content {
+            ToolContent::Text { text } => parts.push(text.trim_end().to_string()),
+            ToolContent::Image { data, mime_type } => {
+                images.push(InlineImage { mime_type: mime_type.clone(), data: data.clone() });
+                parts.push(format!("[image {} ({}) attached to the next request]", images.len(), mime_type));
+            }
+            ToolContent::Resource { resource } => parts.push(render_resource_contents(std::slice::from_ref(resource))),
+            ToolContent::Unsupported => parts.push("[content of an unsupported type]".to_string()),
+        }
+    }
+    let text = if parts.is_empty() { "[no content]".to_string() } else { parts.join("\n") };
+    ToolOutput { text, images, is_error: result.is_error.unwrap_or(false) }
+}
+
+pub fn render_prompt(result: &GetPromptResult) -> String {
+    let mut out: Vec<String> = result.description.iter().map(|d| format!("_{}_", d)).collect();
+    for message in &result.messages {
+        let content = match &message.content {
+            ToolContent::Text { text } => text.clone(),
+            ToolContent::Image { mime_type, data } => format!("[image {}, {} base64 bytes]", mime_type, data.len()),
+            ToolContent::Resource { resource } => render_resource_contents(std::slice::from_ref(resource)),
+            ToolContent::Unsupported => "[content of an unsupported type]".to_string(),
+        };
+        out.push(format!("**{}:** {}", message.role, content));
+    }
+    out.join("\n\n")
+}
+
+/// Reads `uri` and, with `subscribe_for`, subscribes that session to its
+/// changes. Returns the server it came from and the rendered contents.
+pub async fn read_resource(registry: &ToolRegistry, uri: &str, server: Option<&str>, subscribe_for: Option<&str>) -> Result<(String, String)> {
+    let (server, client) = registry.locate_resource(uri, server)?;
+    let contents = client.read_resource(uri).await?;
+    if let Some(session_id) = subscribe_for {
+        if !client.capabilities().await.unwrap_or_default().resource_subscriptions() {
+            return Err(anyhow!("{} does not support resource subscriptions", server));
+        }
+        client.subscribe_resource(uri).await?;
+        registry.watch(&server, uri, session_id);
+    }
+    Ok((server, render_resource_contents(&contents)))
+}
+
+pub async fn get_prompt(registry: &ToolRegistry, name: &str, arguments: serde_json::Value) -> Result<String> {
+    let (_, prompt, client) = registry.resolve_prompt(name)?;
+    Ok(render_prompt(&client.get_prompt(&prompt, arguments).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::transport::test_support;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_registry_relists_only_on_list_changed() {
+        let (transport, mut sent, server) = test_support::channel();
+        let client = Arc::new(McpClient::new(Box::new(transport)));
+        let lister = tokio::spawn(async move {
+            let mut listed = 0;
+            while let Some(request) = sent.recv().await {
+                if request["method"] != "tools/list" {
+                    continue;
+                }
+                listed += 1;
+                let tools: Vec<_> = (0..listed).map(|i| json!({ "name": format!("t{}", i), "inputSchema": {} })).collect();
+                server.send(json!({ "jsonrpc": "2.0", "id": request["id"], "result": { "tools": tools } }).to_string()).unwrap();
+                if listed == 1 {
+                    server.send(r#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#.to_string()).unwrap();
+                } else {
+                    return listed;
+                }
+            }
+            listed
+        });
+
+        let registry = Arc::new(ToolRegistry::new());
+        assert_eq!(registry.attach("fs", client.clone(), ToolPolicy::default()).await.unwrap(), 1);
+        assert_eq!(lister.await.unwrap(), 2);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let names: Vec<String> = registry.snapshot().iter().map(|m| m.qualified_name()).collect();
+        assert_eq!(names, vec!["fs.t0", "fs.t1"]);
+        registry.remove("fs");
+        assert!(registry.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_resource_updates_reach_watching_sessions_once() {
+        let registry = ToolRegistry::new();
+        registry.watch("docs", "file:///README.md", "s1");
+        registry.mark_updated("docs", "file:///README.md");
+        registry.mark_updated("docs", "file:///README.md");
+        registry.mark_updated("docs", "file:///other.md");
+
+        assert_eq!(registry.take_updates("s1"), vec![("docs".to_string(), "file:///README.md".to_string())]);
+        assert!(registry.take_updates("s1").is_empty());
+        assert!(registry.take_updates("s2").is_empty());
+
+        let blob = ResourceContents { uri: "x".into(), mime_type: Some("image/png".into()), text: None, blob: Some("AAAA".into()) };
+        assert_eq!(render_resource_contents(&[blob]), "[binary image/png content, 4 base64 bytes]");
+    }
+
+    #[test]
+    fn test_tool_results_are_rendered_for_the_agent() {
+        let result: CallToolResult = serde_json::from_value(serde_json::json!({
+            "content": [
+                { "type": "text", "text": "3 failures\n" },
+                { "type": "image", "data": "iVBOR", "mimeType": "image/png" },
+                { "type": "audio", "data": "", "mimeType": "audio/wav" }
+            ],
+            "isError": true
+        })).unwrap();
+        let output = render_tool_result(&result);
+        assert!(output.is_error);
+        assert_eq!(output.text, "3 failures\n[image 1 (image/png) attached to the next request]\n[content of an unsupported type]");
+        assert_eq!(output.images, vec![InlineImage { mime_type: "image/png".into(), data: "iVBOR".into() }]);
+
+        let registry = ToolRegistry::new();
+        registry.attach_images("s1", output.images);
+        assert_eq!(registry.take_images("s1").len(), 1);
+        assert!(registry.take_images("s1").is_empty());
+    }
+
+    #[test]
+    fn test_tool_policy_and_approvals() {
+        let config = McpServerConfig {
+            allow_tools: vec!["read_*".into(), "search".into()],
+            deny_tools: vec!["read_secrets".into()],
+            require_approval: true,
+            auto_approve: vec!["read_*".into()],
+            ..Default::default()
+        };
+        let policy = ToolPolicy::from_config(&config);
+        let visible: Vec<&str> = ["read_file", "read_secrets", "search", "write_file"].into_iter().filter(|t| policy.allows(t)).collect();
+        assert_eq!(visible, vec!["read_file", "search"]);
+        assert!(!policy.needs_approval("read_file"));
+        assert!(policy.needs_approval("search"));
+        assert!(!ToolPolicy::default().needs_approval("search"));
+
+        let registry = ToolRegistry::new();
+        let call = registry.hold("s1", "fs.search", json!({ "q": "x" }));
+        assert_eq!(registry.waiting(), vec![call.clone()]);
+        assert!(registry.take_decisions("s1").is_empty());
+        registry.decide(&call.id, true).unwrap();
+        assert!(registry.decide(&call.id, false).is_err(), "decided once");
+        assert_eq!(registry.take_decisions("s1"), vec![(call.clone(), true)]);
+        assert!(registry.take_decisions("s1").is_empty());
+
+        // After a restart the undecided call waits again, the approved but
+        // unconsumed one is handed to its session, the consumed one is done.
+        let open = registry.hold("s2", "fs.search", json!({ "q": "y" }));
+        let approved = registry.hold("s3", "fs.search", json!({ "q": "z" }));
+        let event = |op: &str, data: serde_json::Value| EventRecord {
+            id: uuid::Uuid::new_v4().to_string(), op: op.to_string(), data, timestamp: 0, version: 2, session_id: None, directive_id: None,
+        };
+        let log = [
+            event(APPROVAL_REQUESTED, serde_json::to_value(&call).unwrap()),
+            event(APPROVAL_REQUESTED, serde_json::to_value(&open).unwrap()),
+            event(APPROVAL_REQUESTED, serde_json::to_value(&approved).unwrap()),
+            event(APPROVAL_DECIDED, json!({ "id": call.id, "tool": call.tool, "approved": true })),
+            event(APPROVAL_CONSUMED, json!({ "id": call.id })),
+            event(APPROVAL_DECIDED, json!({ "id": approved.id, "tool": approved.tool, "approved": true })),
+        ];
+        let restarted = ToolRegistry::new();
+        restarted.restore_approvals(open_approvals(&log));
+        assert_eq!(restarted.waiting(), vec![open.clone()]);
+        assert_eq!(restarted.take_decisions("s3"), vec![(approved, true)]);
+        assert!(restarted.take_decisions("s1").is_empty());
+        restarted.decide(&open.id, false).unwrap();
+        assert_eq!(restarted.take_decisions("s2"), vec![(open, false)]);
+    }
 }
@@ -0,0 +1,148 @@
+// src/mcp/schema.rs - Argument checks against a tool's `inputSchema`
+//
+// Covers the JSON Schema subset MCP servers use for tool inputs: `type`
+// (single or list), `properties`, `required`, `additionalProperties: false`,
+// `enum`, `const`, `items` and numeric/string bounds. Anything else is
+// accepted and left for the server to reject.
+
+use serde_json::Value;
+
+// --- Pure Functions ---
+
+/// Every way `value` violates `schema`, as "path: problem". Empty when valid.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(schema, value, "arguments", &mut errors);
+    errors
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: must be one of {}", path, Value::Array(allowed.clone())));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            errors.push(format!("{}: must be {}", path, constant));
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+                if !map.contains_key(name) {
+                    errors.push(format!("{}: missing required property '{}'", path, name));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, field) in map {
+                match properties.and_then(|p| p.get(name)) {
+                    Some(field_schema) => check(field_schema, field, &format!("{}.{}", path, name), errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!("{}: unknown property '{}'", path, name)),
+                        Some(extra @ Value::Object(_)) => check(extra, field, &format!("{}.{}", path, name), errors),
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}[{}]", path, i), errors);
+                }
+            }
+            bound(schema, "minItems", "maxItems", items.len() as f64, "items", path, errors);
+        }
+        Value::String(s) => bound(schema, "minLength", "maxLength", s.chars().count() as f64, "characters", path, errors),
+        Value::Number(n) => bound(schema, "minimum", "maximum", n.as_f64().unwrap_or_default(), "", path, errors),
+        _ => {}
+    }
+}
+
+fn bound(schema: &serde_json::Map<String, Value>, min: &str, max: &str, actual: f64, unit: &str, path: &str, errors: &mut Vec<String>) {
+    let unit = if unit.is_empty() { String::new() } else { format!(" {}", unit) };
+    if let Some(limit) = schema.get(min).and_then(Value::as_f64) {
+        if actual < limit {
+            errors.push(format!("{}: must be at least {}{}", path, limit, unit));
+        }
+    }
+    if let Some(limit) = schema.get(max).and_then(Value::as_f64) {
+        if actual > limit {
+            errors.push(format!("{}: must be at most {}{}", path, limit, unit));
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_reports_each_problem() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "minLength": 1 },
+                "depth": { "type": "integer", "maximum": 5 },
+                "mode": { "enum": ["fast", "full"] },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["path"],
+            "additionalProperties": false
+        });
+        assert!(validate(&schema, &json!({ "path": "src", "depth": 2, "tags": ["a"] })).is_empty());
+
+        let errors = validate(&schema, &json!({ "depth": 2.5, "mode": "slow", "tags": ["a", 1], "extra": true }));
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        for expected in [
+            "arguments: missing required property 'path'",
+            "arguments.depth: expected integer, got number",
+            "arguments.mode: must be one of [\"fast\",\"full\"]",
+            "arguments.tags[1]: expected string, got number",
+            "arguments: unknown property 'extra'",
+        ] {
+            assert!(errors.iter().any(|e| e == expected), "missing {}", expected);
+        }
+
+        // Schemas the validator does not understand accept anything
+        assert!(validate(&json!(true), &json!(42)).is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+// src/mcp/server.rs - Sly as an MCP server (`sly mcp-serve`)
+//
+// Editors and other agents reach the knowledge graph, library docs, the
+// overlay and sessions over JSON-RPC on stdin/stdout. Requests go through
+// the running agent's query socket when there is one, otherwise to a store
+// this process opened read-only, so an agent can still start meanwhile.
+// Indexed files are offered as `file://` resources.
+// stdout carries protocol messages only; logs go to stderr.
+
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::io::query_socket::{self, QueryClient, QueryRequest};
+use crate::mcp::schema;
+use crate::mcp::types::{
+    CallToolParams, CallToolResult, ClientInfo, JsonRpcError, JsonRpcMessage, JsonRpcResponse, Resource, ResourceContents,
+    ServerCapabilities, Tool, ToolContent, INTERNAL_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR, PROTOCOL_VERSION,
+    RESOURCE_NOT_FOUND,
+};
+use crate::memory::{MemoryStore, SearchHit};
+use crate::safety::overlay;
+
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+const DEFAULT_LIBRARY_LIMIT: usize = 5;
+
+/// Opens the store writable for one write; the handle is dropped (and
+/// the lock released) right after.
+pub type OpenWritable = Box<dyn Fn() -> BoxFuture<'static, Result<Arc<dyn MemoryStore>>> + Send + Sync>;
+
+/// Where requests are answered.
+pub enum Backend {
+    /// The running agent, over its query socket.
+    Agent(Mutex<QueryClient>),
+    /// No agent was running: reads go to `store`, opened read-only, and
+    /// sessions are created through `open_writable`.
+    Direct { store: Arc<dyn MemoryStore>, open_writable: OpenWritable },
+}
+
+pub struct McpServer {
+    backend: Backend,
+    root: PathBuf,
+    overlay_id: String,
+}
+
+// --- Pure Functions ---
+
+pub fn tool_definitions() -> Vec<Tool> {
+    let tool = |name: &str, description: &str, input_schema: Value| Tool {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        input_schema,
+    };
+    let search = json!({
+        "type": "object",
+        "properties": {
+            "query": { "type": "string", "minLength": 1 },
+            "limit": { "type": "integer", "minimum": 1, "maximum": 50 }
+        },
+        "required": ["query"],
+        "additionalProperties": false
+    });
+    vec![
+        tool("search_code", "Hybrid (vector, keyword and graph) search over the indexed code. Returns the best matching symbols with their source.", search),
+        tool("get_neighborhood", "Symbols indexed for a file and the nodes linked to them.", json!({
+            "type": "object",
+            "properties": { "path": { "type": "string", "minLength": 1 } },
+            "required": ["path"],
+            "additionalProperties": false
+        })),
+        tool("query_datalog", "Runs a read-only CozoScript (Datalog) query against the knowledge graph, e.g. `?[path] := *nodes{path}`. Rows come back as JSON.", json!({
+            "type": "object",
+            "properties": { "script": { "type": "string", "minLength": 1 } },
+            "required": ["script"],
+            "additionalProperties": false
+        })),
+        tool("search_library", "Searches the indexed documentation of the project's dependencies.", json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "minLength": 1 },
+                "limit": { "type": "integer", "minimum": 1, "maximum": 50 },
+                "rerank": { "type": "boolean", "description": "Re-rank the matches with the cross-encoder; defaults to the [rerank] config" }
+            },
+            "required": ["query"],
+            "additionalProperties": false
+        })),
+        tool("overlay_diff", "Unified diff of the changes staged in the agent's overlay and not yet committed to the workspace.", json!({
+            "type": "object",
+            "properties": { "session": { "type": "string", "description": "A forked session with an overlay of its own" } },
+            "additionalProperties": false
+        })),
+        tool("start_session", "Starts an agent session working on the given task.", json!({
+            "type": "object",
+            "properties": { "prompt": { "type": "string", "minLength": 1 } },
+            "required": ["prompt"],
+            "additionalProperties": false
+        })),
+    ]
+}
+
+fn text_result(text: String, is_error: bool) -> Value {
+    let result = CallToolResult { content: vec![ToolContent::Text { text }], is_error: Some(is_error) };
+    serde_json::to_value(result).unwrap_or_default()
+}
+
+fn rpc_error(code: i64, message: impl ToString) -> JsonRpcError {
+    JsonRpcError { code, message: message.to_string(), data: None }
+}
+
+pub fn format_hits(hits: &[SearchHit]) -> String {
+    if hits.is_empty() {
+        return "No matches.".to_string();
+    }
+    hits.iter()
+        .enumerate()
+        .map(|(i, hit)| format!("{}. {} ({}, score {:.3})\n```\n{}\n```", i + 1, hit.path, hit.node_type, hit.score, hit.content.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// `file://` URI of an ingested path, which may be relative to `root`.
+pub fn file_uri(root: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    let absolute = if path.is_absolute() { path.to_path_buf() } else { root.join(path.strip_prefix(".").unwrap_or(path)) };
+    format!("file://{}", absolute.display())
+}
+
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+// --- IO ---
+
+impl McpServer {
+    pub fn new(backend: Backend, root: PathBuf, overlay_id: &str) -> Self {
+        Self { backend, root, overlay_id: overlay_id.to_string() }
+    }
+
+    /// Answers requests from `input` on `output`, one JSON message per line,
+    /// until `input` closes.
+    pub async fn serve(&self, input: impl AsyncRead + Unpin, mut output: impl AsyncWrite + Unpin) -> Result<()> {
+        let mut lines = BufReader::new(input).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match JsonRpcMessage::parse(&line) {
+                Ok(message) => self.handle(message).await,
+                Err(e) => Some(JsonRpcResponse::failure(None, PARSE_ERROR, format!("Parse error: {}", e))),
+            };
+            if let Some(response) = response {
+                let mut out = serde_json::to_vec(&response)?;
+                out.push(b'\n');
+                output.write_all(&out).await?;
+                output.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The answer to a request; notifications and responses get none.
+    pub async fn handle(&self, message: JsonRpcMessage) -> Option<JsonRpcResponse> {
+        let JsonRpcMessage::Request(request) = message else {
+            return None;
+        };
+        Some(match self.answer(&request.method, request.params.unwrap_or(Value::Null)).await {
+            Ok(result) => JsonRpcResponse::success(request.id, result),
+            Err(e) => JsonRpcResponse::failure(request.id, e.code, e.message),
+        })
+    }
+
+    async fn answer(&self, method: &str, params: Value) -> Result<Value, JsonRpcError> {
+        let internal = |e: anyhow::Error| rpc_error(INTERNAL_ERROR, format!("{:#}", e));
+        match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": ServerCapabilities { tools: Some(json!({})), resources: Some(json!({})), ..Default::default() },
+                "serverInfo": ClientInfo { name: "sly".to_string(), version: env!("CARGO_PKG_VERSION").to_string() },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => {
+                let call: CallToolParams = serde_json::from_value(params).map_err(|e| rpc_error(INVALID_PARAMS, e))?;
+                let tool = tool_definitions().into_iter().find(|t| t.name == call.name)
+                    .ok_or_else(|| rpc_error(INVALID_PARAMS, format!("Unknown tool: {}", call.name)))?;
+                let arguments = if call.arguments.is_null() { json!({}) } else { call.arguments };
+                let problems = schema::validate(&tool.input_schema, &arguments);
+                if !problems.is_empty() {
+                    return Ok(text_result(format!("Invalid arguments: {}", problems.join("; ")), true));
+                }
+                Ok(match self.call_tool(&tool.name, &arguments).await {
+                    Ok(text) => text_result(text, false),
+                    Err(e) => text_result(format!("{:#}", e), true),
+                })
+            }
+            "resources/list" => Ok(json!({ "resources": self.resources().await.map_err(internal)? })),
+            "resources/read" => {
+                let uri = params["uri"].as_str().ok_or_else(|| rpc_error(INVALID_PARAMS, "Missing uri"))?;
+                match self.read_resource(uri).await.map_err(internal)? {
+                    Some(contents) => Ok(json!({ "contents": [contents] })),
+                    None => Err(rpc_error(RESOURCE_NOT_FOUND, format!("Not an indexed file: {}", uri))),
+                }
+            }
+            other => Err(rpc_error(METHOD_NOT_FOUND, format!("Method not found: {}", other))),
+        }
+    }
+
+    async fn request(&self, request: QueryRequest) -> Result<Value> {
+        match &self.backend {
+            Backend::Agent(client) => client.lock().await.request(&request).await,
+            Backend::Direct { open_writable, .. } if matches!(request, QueryRequest::StartSession { .. }) => {
+                let memory = open_writable().await.context("Could not open the store to create the session; if an agent started since, restart `sly mcp-serve`")?;
+                query_socket::dispatch(request, memory.as_ref(), None, None).await
+            }
+            Backend::Direct { store, .. } => query_socket::dispatch(request, store.as_ref(), None, None).await,
+        }
+    }
+
+    async fn call_tool(&self, name: &str, args: &Value) -> Result<String> {
+        let text = |key: &str| args[key].as_str().unwrap_or_default().to_string();
+        let limit = |default: usize| args["limit"].as_u64().map_or(default, |l| l as usize);
+        match name {
+            "search_code" => {
+                let hits = self.request(QueryRequest::Search { query: text("query"), limit: limit(DEFAULT_SEARCH_LIMIT) }).await?;
+                Ok(format_hits(&serde_json::from_value::<Vec<SearchHit>>(hits)?))
+            }
+            "get_neighborhood" => {
+                let nodes: Vec<String> = serde_json::from_value(self.request(QueryRequest::Neighborhood { path: text("path") }).await?)?;
+                Ok(if nodes.is_empty() { format!("Nothing indexed for {}", text("path")) } else { nodes.join("\n\n---\n\n") })
+            }
+            "query_datalog" => Ok(serde_json::to_string_pretty(&self.request(QueryRequest::Query { script: text("script") }).await?)?),
+            "search_library" => {
+                let docs: Vec<String> = serde_json::from_value(self.request(QueryRequest::SearchLibrary {
+                    query: text("query"),
+                    limit: limit(DEFAULT_LIBRARY_LIMIT),
+                    rerank: args["rerank"].as_bool(),
+                }).await?)?;
+                Ok(if docs.is_empty() { "No matches.".to_string() } else { docs.join("\n\n---\n\n") })
+            }
+            "overlay_diff" => self.overlay_diff(args["session"].as_str()),
+            "start_session" => {
+                let session = self.request(QueryRequest::StartSession { input: text("prompt") }).await?;
+                let id = session["id"].as_str().unwrap_or_default();
+                Ok(if session["started"] == true {
+                    format!("Started session {}. Follow it with `sly session show {}`.", id, id)
+                } else {
+                    format!("Created session {}, but no agent is running to work on it. Start one with `sly session resume {}`.", id, id)
+                })
+            }
+            other => Err(anyhow!("Unknown tool: {}", other)),
+        }
+    }
+
+    /// Pending overlay changes against the workspace. A session with an
+    /// overlay of its own (a fork) is diffed from that one.
+    fn overlay_diff(&self, session: Option<&str>) -> Result<String> {
+        let dir = match session.map(overlay::session_overlay_id).map(|id| overlay::overlay_path(&id)) {
+            Some(dir) if dir.exists() => dir,
+            _ => overlay::overlay_path(&self.overlay_id),
+        };
+        let mut diffs = Vec::new();
+        for entry in walkdir::WalkDir::new(&dir).sort_by_file_name().into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+            let rel = entry.path().strip_prefix(&dir)?.to_string_lossy().to_string();
+            let new = fs::read_to_string(entry.path())?;
+            let old = fs::read_to_string(self.root.join(&rel)).unwrap_or_default();
+            if old != new {
+                diffs.push(unified_diff(&rel, &old, &new));
+            }
+        }
+        Ok(if diffs.is_empty() { "No pending changes in the overlay.".to_string() } else { diffs.concat() })
+    }
+
+    async fn resources(&self) -> Result<Vec<Resource>> {
+        let paths: Vec<String> = serde_json::from_value(self.request(QueryRequest::IndexedFiles).await?)?;
+        let mut resources: Vec<Resource> = paths.iter()
+            .map(|path| Resource {
+                uri: file_uri(&self.root, path),
+                name: path.trim_start_matches("./").to_string(),
+                description: None,
+                mime_type: Some("text/plain".to_string()),
+            })
+            .collect();
+        resources.dedup_by(|a, b| a.uri == b.uri);
+        Ok(resources)
+    }
+
+    /// Only indexed files are served, so clients cannot read arbitrary
+    /// paths (say `.env`) through a crafted URI.
+    async fn read_resource(&self, uri: &str) -> Result<Option<ResourceContents>> {
+        if !self.resources().await?.iter().any(|r| r.uri == uri) {
+            return Ok(None);
+        }
+        let path = uri.trim_start_matches("file://");
+        Ok(Some(ResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some("text/plain".to_string()),
+            text: Some(fs::read_to_string(path)?),
+            blob: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+
+    async fn call(server: &McpServer, line: &str) -> Value {
+        let response = server.handle(JsonRpcMessage::parse(line).unwrap()).await.unwrap();
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tools_and_resources() {
+        let root = std::env::temp_dir().join(format!("sly_mcp_serve_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn answer() -> u32 { 42 }\n").unwrap();
+        let memory: Arc<dyn MemoryStore> = Arc::new(InMemoryStore::new());
+        memory.update_sync_status("./src/lib.rs", "abc").await.unwrap();
+        let writable = memory.clone();
+        let open_writable: OpenWritable = Box::new(move || {
+            let memory = writable.clone();
+            Box::pin(async move { Ok(memory) })
+        });
+        let backend = Backend::Direct { store: memory.clone(), open_writable };
+        let server = McpServer::new(backend, root.clone(), &format!("mcp_serve_{}", uuid::Uuid::new_v4()));
+
+        let init = call(&server, r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#).await;
+        assert_eq!(init["result"]["serverInfo"]["name"], "sly");
+        assert!(server.handle(JsonRpcMessage::parse(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).unwrap()).await.is_none());
+
+        let tools = call(&server, r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#).await;
+        assert_eq!(tools["result"]["tools"].as_array().unwrap().len(), 6);
+
+        let started = call(&server, r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"start_session","arguments":{"prompt":"fix the tests"}}}"#).await;
+        assert_eq!(started["result"]["isError"], false);
+        assert_eq!(memory.list_sessions().await.unwrap().len(), 1);
+
+        let invalid = call(&server, r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"search_code","arguments":{}}}"#).await;
+        assert_eq!(invalid["result"]["isError"], true);
+        let unknown = call(&server, r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"rm_rf"}}"#).await;
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+
+        let uri = format!("file://{}", root.join("src/lib.rs").display());
+        let listed = call(&server, r#"{"jsonrpc":"2.0","id":6,"method":"resources/list"}"#).await;
+        assert_eq!(listed["result"]["resources"][0]["uri"], uri.as_str());
+        let read = call(&server, &json!({ "jsonrpc": "2.0", "id": 7, "method": "resources/read", "params": { "uri": uri } }).to_string()).await;
+        assert_eq!(read["result"]["contents"][0]["text"], "pub fn answer() -> u32 { 42 }\n");
+        let outside = format!("file://{}", root.join(".env").display());
+        let refused = call(&server, &json!({ "jsonrpc": "2.0", "id": 8, "method": "resources/read", "params": { "uri": outside } }).to_string()).await;
+        assert_eq!(refused["error"]["code"], RESOURCE_NOT_FOUND);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("src/lib.rs", "a\nb\n", "a\nc\n");
+        assert_eq!(diff, "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+    }
+}
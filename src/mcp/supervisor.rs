@@ -0,0 +1,262 @@
+// src/mcp/supervisor.rs - Keeps configured MCP servers connected
+//
+// Each server gets a task that watches its client: a closed connection
+// (for stdio servers, the process exiting) or unanswered pings take the
+// server down, after which it is reconnected with exponential backoff,
+// re-initialized and its tools re-listed. Health, restarts and the last
+// stderr lines are kept for `/status`.
+
+use anyhow::{anyhow, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::core::state::McpServerConfig;
+use crate::mcp::client::McpClient;
+use crate::mcp::host::McpHost;
+use crate::mcp::registry::{ToolPolicy, ToolRegistry};
+use crate::mcp::transport;
+
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_MISSED_PINGS: u32 = 2;
+/// A server that stayed up this long starts its backoff over.
+const HEALTHY_AFTER: Duration = Duration::from_secs(120);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// Consecutive failed restarts before giving up on a server.
+const MAX_RESTART_ATTEMPTS: u32 = 10;
+/// Stderr lines shown per server in a status report.
+const STATUS_STDERR_LINES: usize = 5;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ServerState {
+    #[default]
+    Starting,
+    Running,
+    Restarting { attempt: u32, retry_in_secs: u64 },
+    /// Gave up after `MAX_RESTART_ATTEMPTS`.
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerHealth {
+    pub name: String,
+    pub endpoint: String,
+    #[serde(flatten)]
+    pub state: ServerState,
+    pub tools: usize,
+    pub restarts: u32,
+    pub last_ping_ms: Option<u64>,
+    pub last_error: Option<String>,
+    /// Latest stderr lines (of the last run, if the server is down).
+    pub stderr: Vec<String>,
+}
+
+pub struct McpSupervisor {
+    clients: Arc<Mutex<HashMap<String, Arc<McpClient>>>>,
+    registry: Arc<ToolRegistry>,
+    host: Arc<McpHost>,
+    health: std::sync::Mutex<HashMap<String, ServerHealth>>,
+}
+
+// --- Pure Functions ---
+
+/// Wait before restart attempt `attempt` (1-based): 1s, 2s, 4s, ... capped.
+pub fn backoff(attempt: u32) -> Duration {
+    let secs = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_secs(secs).min(MAX_RESTART_DELAY)
+}
+
+impl ServerHealth {
+    /// One line for status reports.
+    pub fn summary(&self) -> String {
+        let state = match &self.state {
+            ServerState::Starting => "🟡 starting".to_string(),
+            ServerState::Running => match self.last_ping_ms {
+                Some(ms) => format!("🟢 running, {} tools, ping {}ms", self.tools, ms),
+                None => format!("🟢 running, {} tools", self.tools),
+            },
+            ServerState::Restarting { attempt, retry_in_secs } => format!("🟠 restarting (attempt {}, in {}s)", attempt, retry_in_secs),
+            ServerState::Failed => "🔴 failed".to_string(),
+        };
+        let mut line = format!("{}: {}", self.name, state);
+        if self.restarts > 0 {
+            line.push_str(&format!(", {} restart(s)", self.restarts));
+        }
+        if let (Some(error), false) = (&self.last_error, self.state == ServerState::Running) {
+            line.push_str(&format!("\n    last error: {}", error));
+        }
+        line
+    }
+}
+
+// --- IO ---
+
+/// The handshake, within the server's startup timeout.
+pub async fn initialize(client: &McpClient, config: &McpServerConfig) -> Result<()> {
+    match tokio::time::timeout(config.startup_timeout(), client.initialize()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.context("Handshake failed")),
+        Err(_) => Err(anyhow!("Connection timed out after {}s", config.startup_timeout().as_secs())),
+    }
+}
+
+impl McpSupervisor {
+    pub fn new(clients: Arc<Mutex<HashMap<String, Arc<McpClient>>>>, registry: Arc<ToolRegistry>, host: Arc<McpHost>) -> Self {
+        Self { clients, registry, host, health: std::sync::Mutex::new(HashMap::new()) }
+    }
+
+    /// Connects `name` now (so its tools are there before the first step)
+    /// and keeps it connected from a background task.
+    pub async fn start(self: &Arc<Self>, name: &str, config: McpServerConfig) {
+        self.update(name, |h| {
+            h.endpoint = config.endpoint();
+            h.state = ServerState::Starting;
+        });
+        let first = self.connect(name, &config).await;
+        match &first {
+            Ok(_) => println!("     {} Connected to {} ({} tools)", "✅".green(), name, self.registry.snapshot().iter().filter(|m| m.server_name == name).count()),
+            Err(e) => eprintln!("     {} Could not start {}: {}", "⚠️".red(), name, e),
+        }
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move { supervisor.run(name, config, first).await });
+    }
+
+    pub fn registry(&self) -> &Arc<ToolRegistry> {
+        &self.registry
+    }
+
+    /// Health of every configured server, by name.
+    pub async fn status(&self) -> Vec<ServerHealth> {
+        let clients = self.clients.lock().await;
+        let mut health: Vec<ServerHealth> = self.health.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect();
+        for server in &mut health {
+            if let Some(client) = clients.get(&server.name) {
+                server.stderr = client.stderr_tail();
+            }
+            let skip = server.stderr.len().saturating_sub(STATUS_STDERR_LINES);
+            server.stderr.drain(..skip);
+        }
+        health.sort_by(|a, b| a.name.cmp(&b.name));
+        health
+    }
+
+    fn update(&self, name: &str, change: impl FnOnce(&mut ServerHealth)) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = health.entry(name.to_string()).or_insert_with(|| ServerHealth { name: name.to_string(), ..Default::default() });
+        change(entry);
+    }
+
+    async fn connect(&self, name: &str, config: &McpServerConfig) -> Result<Arc<McpClient>> {
+        let client = Arc::new(McpClient::new(transport::open(config).await?).with_timeout(config.call_timeout()));
+        self.host.attach(name, &client);
+        initialize(&client, config).await?;
+        let tools = match self.registry.attach(name, client.clone(), ToolPolicy::from_config(config)).await {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!("     {} Connected to {} but could not list tools: {}", "⚠️".yellow(), name, e);
+                0
+            }
+        };
+        self.clients.lock().await.insert(name.to_string(), client.clone());
+        self.update(name, |h| {
+            h.state = ServerState::Running;
+            h.tools = tools;
+            h.last_ping_ms = None;
+        });
+        Ok(client)
+    }
+
+    async fn run(self: Arc<Self>, name: String, config: McpServerConfig, mut current: Result<Arc<McpClient>>) {
+        let mut failures = 0;
+        loop {
+            match current {
+                Ok(client) => {
+                    let started = Instant::now();
+                    let reason = self.watch(&name, &client).await;
+                    let stderr = client.stderr_tail();
+                    self.clients.lock().await.remove(&name);
+                    self.registry.remove(&name);
+                    drop(client);
+                    if started.elapsed() >= HEALTHY_AFTER {
+                        failures = 0;
+                    }
+                    eprintln!("   {} MCP server {} went down: {}", "🔌".red(), name, reason);
+                    self.update(&name, |h| {
+                        h.last_error = Some(reason);
+                        h.stderr = stderr;
+                    });
+                }
+                Err(e) => self.update(&name, |h| h.last_error = Some(format!("{:#}", e))),
+            }
+
+            failures += 1;
+            if failures > MAX_RESTART_ATTEMPTS {
+                eprintln!("   {} Giving up on MCP server {} after {} attempts", "🔌".red(), name, MAX_RESTART_ATTEMPTS);
+                self.update(&name, |h| h.state = ServerState::Failed);
+                return;
+            }
+            let delay = backoff(failures);
+            self.update(&name, |h| h.state = ServerState::Restarting { attempt: failures, retry_in_secs: delay.as_secs() });
+            tokio::time::sleep(delay).await;
+
+            current = self.connect(&name, &config).await;
+            match &current {
+                Ok(_) => {
+                    println!("   {} MCP server {} restarted", "🔌".green(), name);
+                    self.update(&name, |h| h.restarts += 1);
+                }
+                Err(e) => eprintln!("   {} Restart of MCP server {} failed: {:#}", "🔌".yellow(), name, e),
+            }
+        }
+    }
+
+    /// Returns why the server is considered down.
+    async fn watch(&self, name: &str, client: &McpClient) -> String {
+        let mut missed = 0;
+        loop {
+            tokio::select! {
+                _ = client.closed() => return "connection closed (process exited?)".to_string(),
+                _ = tokio::time::sleep(PING_INTERVAL) => match client.ping(PING_TIMEOUT).await {
+                    Ok(rtt) => {
+                        missed = 0;
+                        self.update(name, |h| h.last_ping_ms = Some(rtt.as_millis() as u64));
+                    }
+                    Err(e) => {
+                        missed += 1;
+                        if missed >= MAX_MISSED_PINGS {
+                            return format!("{} pings unanswered: {}", missed, e);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_and_summary() {
+        let delays: Vec<u64> = [1, 2, 3, 7, 40].iter().map(|a| backoff(*a).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 60, 60]);
+
+        let mut health = ServerHealth { name: "files".into(), state: ServerState::Running, tools: 3, last_ping_ms: Some(4), ..Default::default() };
+        assert_eq!(health.summary(), "files: 🟢 running, 3 tools, ping 4ms");
+        health.state = ServerState::Restarting { attempt: 2, retry_in_secs: 2 };
+        health.restarts = 1;
+        health.last_error = Some("connection closed".into());
+        assert_eq!(health.summary(), "files: 🟠 restarting (attempt 2, in 2s), 1 restart(s)\n    last error: connection closed");
+
+        let json = serde_json::to_value(&health).unwrap();
+        assert_eq!(json["state"], "restarting");
+        assert_eq!(serde_json::from_value::<ServerHealth>(json).unwrap(), health);
+    }
+}
@@ -1,53 +1,112 @@
 use anyhow::{Context, Result};
+use std::collections::VecDeque;
 use std::process::Stdio;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tokio::sync::Mutex;
 use std::sync::Arc;
 
+use crate::core::state::McpServerConfig;
 use crate::mcp::types::JsonRpcRequest;
 
-/// Abstraction for sending/receiving JSON-RPC messages
+/// Abstraction for sending/receiving JSON-RPC messages.
+/// `send_value` and `receive_line` may run concurrently: the client keeps a
+/// single reader task on `receive_line` while requests are sent.
 #[async_trait::async_trait]
 pub trait Transport: Send + Sync {
-    async fn send(&self, message: &JsonRpcRequest) -> Result<()>;
-    // Receive one message. Note: This assumes a request-response lockstep or a dedicated reading loop.
-    // Real MCP is async, so client will likely have a "listen loop" separate from "send".
+    /// Writes one message (request, notification or response).
+    async fn send_value(&self, message: &serde_json::Value) -> Result<()>;
+    /// Next message from the server; `None` once it has gone away.
     async fn receive_line(&self) -> Result<Option<String>>;
+
+    async fn send(&self, message: &JsonRpcRequest) -> Result<()> {
+        self.send_value(&serde_json::to_value(message)?).await
+    }
+
+    /// Last lines the server wrote to stderr, for transports that see it.
+    fn stderr_tail(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Lines of server stderr kept per stdio server.
+pub const STDERR_LINES: usize = 200;
+
+/// The transport `config` asks for: stdio for a `command`, streamable HTTP
+/// or legacy SSE for a `url`, with `${VAR}`s expanded.
+pub async fn open(config: &McpServerConfig) -> Result<Box<dyn Transport>> {
+    let config = &config.expanded()?;
+    let Some(url) = &config.url else {
+        return Ok(Box::new(StdioTransport::spawn(config)?));
+    };
+    let token = config.auth_token.as_deref();
+    match config.transport.as_deref().unwrap_or("http") {
+        "http" => Ok(Box::new(crate::mcp::http::HttpTransport::new(url, &config.headers, token)?)),
+        "sse" => Ok(Box::new(crate::mcp::http::SseTransport::connect(url, &config.headers, token).await?)),
+        other => anyhow::bail!("Unknown MCP transport '{}' (expected http or sse)", other),
+    }
 }
 
-/// Transport over Stdio of a spawned process
+/// Transport over Stdio of a spawned process. The process is killed when
+/// the transport is dropped; its stderr is kept in a ring buffer.
 pub struct StdioTransport {
     stdin: Arc<Mutex<ChildStdin>>,
     reader: Arc<Mutex<BufReader<ChildStdout>>>,
     _process: Arc<Mutex<Child>>, // Shared handle
+    stderr: Arc<std::sync::Mutex<VecDeque<String>>>,
 }
 
 impl StdioTransport {
     pub fn new(command: &str, args: &[String]) -> Result<Self> {
-        let mut child = Command::new(command)
-            .args(args)
+        Self::start(Command::new(command).args(args), command, args)
+    }
+
+    /// Spawns a configured server with its `env` and `cwd`.
+    pub fn spawn(config: &McpServerConfig) -> Result<Self> {
+        let mut command = Command::new(&config.command);
+        command.args(&config.args).envs(&config.env);
+        if let Some(cwd) = &config.cwd {
+            command.current_dir(cwd);
+        }
+        Self::start(&mut command, &config.command, &config.args)
+    }
+
+    fn start(command: &mut Command, program: &str, args: &[String]) -> Result<Self> {
+        let mut child = command
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
-            .with_context(|| format!("Failed to spawn MCP server: {} {:?}", command, args))?;
+            .with_context(|| format!("Failed to spawn MCP server: {} {:?}", program, args))?;
 
         let stdin = child.stdin.take().context("Failed to open stdin")?;
         let stdout = child.stdout.take().context("Failed to open stdout")?;
         let reader = BufReader::new(stdout);
 
+        let stderr = Arc::new(std::sync::Mutex::new(VecDeque::new()));
+        if let Some(pipe) = child.stderr.take() {
+            let buffer = stderr.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(pipe).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    push_line(&mut buffer.lock().unwrap_or_else(|e| e.into_inner()), line, STDERR_LINES);
+                }
+            });
+        }
+
         Ok(Self {
             stdin: Arc::new(Mutex::new(stdin)),
             reader: Arc::new(Mutex::new(reader)),
             _process: Arc::new(Mutex::new(child)),
+            stderr,
         })
     }
 }
 
 #[async_trait::async_trait]
 impl Transport for StdioTransport {
-    async fn send(&self, message: &JsonRpcRequest) -> Result<()> {
+    async fn send_value(&self, message: &serde_json::Value) -> Result<()> {
         let mut json = serde_json::to_string(message)?;
         json.push('\n');
         
@@ -65,4 +124,49 @@ impl Transport for StdioTransport {
         }
         Ok(Some(line))
     }
+
+    fn stderr_tail(&self) -> Vec<String> {
+        self.stderr.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
+/// Appends to a ring buffer of at most `capacity` lines.
+pub fn push_line(buffer: &mut VecDeque<String>, line: String, capacity: usize) {
+    if buffer.len() == capacity {
+        buffer.pop_front();
+    }
+    buffer.push_back(line);
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    /// Hands every sent message to the test and reads lines the test writes.
+    pub struct ChannelTransport {
+        sent: mpsc::UnboundedSender<Value>,
+        incoming: Mutex<mpsc::UnboundedReceiver<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for ChannelTransport {
+        async fn send_value(&self, message: &Value) -> Result<()> {
+            self.sent.send(message.clone())?;
+            Ok(())
+        }
+
+        async fn receive_line(&self) -> Result<Option<String>> {
+            Ok(self.incoming.lock().await.recv().await)
+        }
+    }
+
+    /// A transport plus the test's ends: what the client sent, and a sender
+    /// for lines the "server" writes back.
+    pub fn channel() -> (ChannelTransport, mpsc::UnboundedReceiver<Value>, mpsc::UnboundedSender<String>) {
+        let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+        let (line_tx, line_rx) = mpsc::unbounded_channel();
+        (ChannelTransport { sent: sent_tx, incoming: Mutex::new(line_rx) }, sent_rx, line_tx)
+    }
 }
@@ -7,7 +7,9 @@ use std::collections::HashMap;
 pub struct JsonRpcRequest {
     pub jsonrpc: String,
     pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub id: Option<Value>, // None = notification
 }
 
@@ -15,7 +17,9 @@ pub struct JsonRpcRequest {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JsonRpcResponse {
     pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub error: Option<JsonRpcError>,
     pub id: Option<Value>,
 }
@@ -24,12 +28,70 @@ pub struct JsonRpcResponse {
 pub struct JsonRpcError {
     pub code: i64,
     pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub data: Option<Value>,
 }
 
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// MCP's code for a `resources/read` of an unknown URI.
+pub const RESOURCE_NOT_FOUND: i64 = -32002;
+/// MCP's code for a sampling request the client declined.
+pub const SAMPLING_REJECTED: i64 = -1;
+
+/// Anything a peer can send on the wire.
+#[derive(Debug, Clone)]
+pub enum JsonRpcMessage {
+    Request(JsonRpcRequest),
+    Notification(JsonRpcRequest),
+    Response(JsonRpcResponse),
+}
+
+impl JsonRpcMessage {
+    /// Messages with a `method` are requests (with an id) or notifications;
+    /// everything else is a response.
+    pub fn parse(line: &str) -> serde_json::Result<Self> {
+        let value: Value = serde_json::from_str(line)?;
+        if value.get("method").is_some() {
+            let request: JsonRpcRequest = serde_json::from_value(value)?;
+            Ok(match request.id {
+                Some(_) => JsonRpcMessage::Request(request),
+                None => JsonRpcMessage::Notification(request),
+            })
+        } else {
+            Ok(JsonRpcMessage::Response(serde_json::from_value(value)?))
+        }
+    }
+}
+
+impl JsonRpcRequest {
+    pub fn new(method: &str, params: Option<Value>, id: Option<Value>) -> Self {
+        Self { jsonrpc: "2.0".to_string(), method: method.to_string(), params, id }
+    }
+
+    pub fn notification(method: &str, params: Option<Value>) -> Self {
+        Self::new(method, params, None)
+    }
+}
+
+impl JsonRpcResponse {
+    pub fn success(id: Option<Value>, result: Value) -> Self {
+        Self { jsonrpc: "2.0".to_string(), result: Some(result), error: None, id }
+    }
+
+    pub fn failure(id: Option<Value>, code: i64, message: String) -> Self {
+        Self { jsonrpc: "2.0".to_string(), result: None, error: Some(JsonRpcError { code, message, data: None }), id }
+    }
+}
+
 // --- MCP Protocol Types ---
 
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct InitializeParams {
     pub protocol_version: String,
     pub capabilities: ClientCapabilities,
@@ -38,48 +100,128 @@ pub struct InitializeParams {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClientCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub roots: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sampling: Option<HashMap<String, Value>>,
 }
 
+/// What a server offers, from its `initialize` result. Each entry is
+/// present (usually as an object of sub-flags) when supported.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ServerCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompts: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<Value>,
+}
+
+impl ServerCapabilities {
+    pub fn resource_subscriptions(&self) -> bool {
+        self.resources.as_ref().and_then(|r| r["subscribe"].as_bool()).unwrap_or(false)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClientInfo {
     pub name: String,
     pub version: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct Tool {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default)]
     pub input_schema: Value, // JSON Schema
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CallToolParams {
     pub name: String,
+    #[serde(default)]
     pub arguments: Value,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct CallToolResult {
     pub content: Vec<ToolContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub is_error: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
 pub enum ToolContent {
     Text { text: String },
     Image { data: String, mime_type: String },
-    Resource { resource: Resource },
+    Resource { resource: ResourceContents },
+    /// Kinds Sly does not handle (audio, resource links, ...).
+    #[serde(other)]
+    Unsupported,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// An entry of `resources/list`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct Resource {
     pub uri: String,
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mime_type: Option<String>,
+}
+
+/// One part of a `resources/read` answer: `text`, or base64 `blob`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceContents {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub blob: Option<String>,
 }
+
+/// An entry of `prompts/list`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: ToolContent,
+}
+
+/// Answer to `prompts/get`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetPromptResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+}
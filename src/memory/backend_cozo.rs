@@ -1,18 +1,41 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use cozo::{DataValue, DbInstance, ScriptMutability, Vector};
 use ndarray::Array1;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
 use uuid::Uuid;
 use chrono::Utc;
 
+use super::engine_candle::DEFAULT_EMBEDDING_DIM;
+use crate::core::state::StorageEngine;
+use super::events::{Correlation, EventQuery, EventRecord, EVENT_VERSION};
+use super::migrations::{self, Change, PlannedStep, StoreShape, SCHEMA_VERSION};
+
+/// File name of SQLite-backed stores; its presence selects the engine.
+pub const SQLITE_FILE: &str = "cozo.sqlite";
+
+/// Relations carrying an embedding column; rebuilt when the model changes.
+pub const VECTOR_RELATIONS: [&str; 5] = ["cache", "nodes", "library", "chunks", "embedding_cache"];
+
 pub struct CozoBackend {
     pub db: DbInstance,
+    /// Width of every vector column in this store.
+    pub dim: usize,
 }
 
 impl CozoBackend {
+    /// Opens whichever engine the store at `path` was created with.
     pub fn new(path: &str, read_only: bool) -> Result<Self> {
-        let db_path = Path::new(path).join("cozo.db");
+        Self::open(path, read_only, detect_engine(path))
+    }
+
+    pub fn open(path: &str, read_only: bool, engine: StorageEngine) -> Result<Self> {
+        let (kind, file) = match engine {
+            StorageEngine::RocksDb => ("rocksdb", "cozo.db"),
+            StorageEngine::Sqlite => ("sqlite", SQLITE_FILE),
+            StorageEngine::Memory => ("mem", ""),
+        };
+        let db_path = Path::new(path).join(file);
         let db_path_str = db_path.to_str().context("Invalid UTF-8 in database path")?;
         
         let mut retries = 0;
@@ -24,7 +47,7 @@ impl CozoBackend {
                 "{}"
             };
 
-            match DbInstance::new("rocksdb", db_path_str, options) {
+            match DbInstance::new(kind, db_path_str, options) {
                 Ok(db) => break db,
                 Err(e) if !read_only && retries < max_retries && e.to_string().contains("Resource temporarily unavailable") => {
                     retries += 1;
@@ -39,245 +62,163 @@ impl CozoBackend {
             }
         };
 
-        let backend = Self { db };
-        if !read_only {
-            backend.initialize_schema()?;
+        let mut backend = Self { db, dim: DEFAULT_EMBEDDING_DIM };
+        if let Ok(Some((_, dim))) = backend.embedding_meta() {
+            backend.dim = dim;
+        }
+        if read_only {
+            let version = backend.schema_version()?;
+            if version > SCHEMA_VERSION {
+                bail!("Store is at schema v{} but this binary only understands up to v{}. Upgrade sly before opening it.", version, SCHEMA_VERSION);
+            }
+        } else {
+            backend.migrate(false)?;
         }
         Ok(backend)
     }
 
-    fn initialize_schema(&self) -> Result<()> {
-        // Initialize Schema
-        let create_cache = "
-            :create cache {
-                id: String
-                =>
-                query: String,
-                response: String,
-                embedding: <F32; 384>
-            }
-        ";
-        self.run_schema_script(create_cache, "cache")?;
-
-        let create_index = "
-            ::hnsw create cache:idx {
-                dim: 384,
-                dtype: F32,
-                fields: [embedding],
-                distance: Cosine,
-                m: 50,
-                ef_construction: 200
-            }
-        ";
-        self.run_schema_script(create_index, "cache:idx")?;
-
-        // Updated Schema for Active RAG: includes embedding in nodes
-        let create_nodes = "
-            :create nodes {
-                id: String
-                =>
-                content: String,
-                type: String,
-                path: String,
-                embedding: <F32; 384>
-            }
-        ";
-        self.run_schema_script(create_nodes, "nodes")?;
-
-        // Schema Migration: Check if `nodes` has `embedding` column
-        let check_schema = "?[id, embedding] := *nodes{id, embedding} :limit 1";
-        if let Err(_) = self.db.run_script(check_schema, Default::default(), ScriptMutability::Immutable) {
-            println!("Migrating `nodes` schema: Adding `embedding` column...");
-            
-            // 0. Cleanup partial state
-            let _ = self.db.run_script("::remove nodes_old", Default::default(), ScriptMutability::Mutable);
-
-            // 1. Rename old table (backup)
-            if let Err(e) = self.db.run_script("::rename nodes nodes_old", Default::default(), ScriptMutability::Mutable) {
-                eprintln!("Failed to rename nodes->nodes_old: {}", e);
-            }
-            
-            // 1.5 Explicit remove
-            let _ = self.db.run_script("::remove nodes", Default::default(), ScriptMutability::Mutable);
+    /// Brings the store up to `SCHEMA_VERSION`. Each step's relation
+    /// changes run as one chained (atomic) script, then its indices, then a
+    /// verification pass; only a verified step is stamped in `schema_version`.
+    /// With `dry_run` nothing is written and the pending plan is returned.
+    pub fn migrate(&self, dry_run: bool) -> Result<Vec<PlannedStep>> {
+        let shape = self.inspect_shape()?;
+        if shape.version > SCHEMA_VERSION {
+            bail!(
+                "Store is at schema v{} but this binary only understands up to v{}. Upgrade sly before opening it.",
+                shape.version, SCHEMA_VERSION
+            );
+        }
 
-            // 2. Create new table
-            if let Err(e) = self.db.run_script(create_nodes, Default::default(), ScriptMutability::Mutable) {
-                eprintln!("Failed to recreate nodes table: {}", e);
-            }
-            
-            // 3. Migrate data
-            let migrate_data = "
-                ?[id, content, type, path, embedding] := *nodes_old{id, content, type, path}, embedding = $empty_vec
-                :put nodes { id => content, type, path, embedding }
-            ";
-            let mut params = BTreeMap::new();
-            params.insert("empty_vec".to_string(), vec_to_datavalue(vec![0.0; 384]));
+        let steps = migrations::plan(&shape);
+        if dry_run || steps.is_empty() {
+            return Ok(steps);
+        }
 
-            if let Err(e) = self.db.run_script(migrate_data, params, ScriptMutability::Mutable) {
-                eprintln!("Failed to migrate nodes data: {}", e);
-            }
-            
-            // 4. Drop old table
-            let _ = self.db.run_script("::remove nodes_old", Default::default(), ScriptMutability::Mutable);
-            println!("`nodes` verification/migration complete.");
+        if !shape.relations.contains("schema_version") {
+            self.run_script(&migrations::relation_ddl("schema_version", self.dim), Default::default(), ScriptMutability::Mutable)
+                .context("Failed to create `schema_version`")?;
         }
 
-        let create_nodes_idx = "
-            ::hnsw create nodes:idx {
-                dim: 384,
-                dtype: F32,
-                fields: [embedding],
-                distance: Cosine,
-                m: 50,
-                ef_construction: 200
-            }
-        ";
-        self.run_schema_script(create_nodes_idx, "nodes:idx")?;
-
-        let create_edges = "
-            :create edges {
-                from: String,
-                to: String
-                =>
-                rel_type: String
-            }
-        ";
-        self.run_schema_script(create_edges, "edges")?;
-
-        // Initialize Library Table for Docs
-        let create_library = "
-            :create library {
-                id: String
-                =>
-                name: String,
-                version: String,
-                content: String,
-                language: String,
-                chunk_type: String,
-                embedding: <F32; 384>
+        for step in &steps {
+            eprintln!("🗄️ Applying schema migration v{}: {}", step.version, step.description);
+
+            let tx: Vec<String> = step.changes.iter()
+                .filter(|c| !matches!(c, Change::Index(_)))
+                .map(|c| format!("{{\n{}\n}}", migrations::change_script(c, self.dim)))
+                .collect();
+            if !tx.is_empty() {
+                let mut params = BTreeMap::new();
+                params.insert("zero_vec".to_string(), vec_to_datavalue(vec![0.0; self.dim]));
+                self.run_script(&tx.join("\n"), params, ScriptMutability::Mutable)
+                    .with_context(|| format!("Schema migration v{} failed", step.version))?;
             }
-        ";
-        self.run_schema_script(create_library, "library")?;
-
-        // Schema Migration: Check if `library` has `embedding` column
-        let check_lib_schema = "?[id, embedding] := *library{id, embedding} :limit 1";
-        if let Err(_) = self.db.run_script(check_lib_schema, Default::default(), ScriptMutability::Immutable) {
-            println!("Migrating `library` schema: Adding `embedding` column...");
-            
-            let _ = self.db.run_script("::remove library_old", Default::default(), ScriptMutability::Mutable);
-            
-            if let Err(e) = self.db.run_script("::rename library library_old", Default::default(), ScriptMutability::Mutable) {
-                eprintln!("Failed to rename library->library_old: {}", e);
+
+            for change in step.changes.iter().filter(|c| matches!(c, Change::Index(_))) {
+                self.run_script(&migrations::change_script(change, self.dim), Default::default(), ScriptMutability::Mutable)
+                    .with_context(|| format!("Schema migration v{} failed on {:?}", step.version, change))?;
             }
 
-            let _ = self.db.run_script("::remove library", Default::default(), ScriptMutability::Mutable);
-            
-            if let Err(e) = self.db.run_script(create_library, Default::default(), ScriptMutability::Mutable) {
-                eprintln!("Failed to recreate library table: {}", e);
+            let after = self.inspect_shape()?;
+            let migration = migrations::MIGRATIONS.iter().find(|m| m.version == step.version)
+                .ok_or_else(|| anyhow!("Unknown migration v{}", step.version))?;
+            if let Some(missing) = migration.changes.iter().find(|c| !after.satisfies(c)) {
+                bail!("Schema migration v{} did not apply {:?}", step.version, missing);
             }
-            
-            // Migrate data
-            let migrate_lib_data = "
-                ?[id, name, version, content, language, chunk_type, embedding] := *library_old{id, name, version, content, language, chunk_type}, embedding = $empty_vec
-                :put library { id => name, version, content, language, chunk_type, embedding }
+
+            let stamp = "
+                ?[version, description, applied_at] <- [[$version, $description, $now]]
+                :put schema_version { version => description, applied_at }
             ";
             let mut params = BTreeMap::new();
-            params.insert("empty_vec".to_string(), vec_to_datavalue(vec![0.0; 384]));
+            params.insert("version".to_string(), DataValue::from(step.version));
+            params.insert("description".to_string(), DataValue::from(step.description));
+            params.insert("now".to_string(), DataValue::from(Utc::now().timestamp_millis()));
+            self.run_script(stamp, params, ScriptMutability::Mutable)?;
+        }
+        Ok(steps)
+    }
 
-            if let Err(e) = self.db.run_script(migrate_lib_data, params, ScriptMutability::Mutable) {
-                eprintln!("Failed to migrate library data: {}", e);
-            }
-            
-            let _ = self.db.run_script("::remove library_old", Default::default(), ScriptMutability::Mutable);
-            println!("`library` verification/migration complete.");
+    /// Highest applied migration; 0 for stores that predate versioning.
+    pub fn schema_version(&self) -> Result<i64> {
+        let relations = self.list_names("::relations")?;
+        if !relations.contains("schema_version") {
+            return Ok(0);
         }
+        let result = self.run_script("?[max(version)] := *schema_version{version}", Default::default(), ScriptMutability::Immutable)?;
+        Ok(result.rows.first().and_then(|r| r.first()).and_then(|v| v.get_int()).unwrap_or(0))
+    }
 
-        let create_kv = "
-            :create kv_cache {
-                hash: String
-                =>
-                cache_id: String,
-                created_at: Int
-            }
-        ";
-        self.run_schema_script(create_kv, "kv_cache")?;
-
-        let create_sync = "
-            :create sync_log {
-                path: String
-                =>
-                last_ingested: Float,
-                content_hash: String
-            }
-        ";
-        // Initialize sync_log table
-        self.run_schema_script(create_sync, "sync_log")?;
-
-        let create_event_log = "
-            :create event_log {
-                id: String
-                =>
-                op: String,
-                data: Json,
-                timestamp: Int,
-                version: Int
-            }
-        ";
-        self.run_schema_script(create_event_log, "event_log")?;
-
-        let create_skills = "
-            :create skills {
-                name: String
-                =>
-                code: String,
-                description: String,
-                signature: String
-            }
-        ";
-        self.run_schema_script(create_skills, "skills")?;
-
-        let create_lib_index = "
-            ::hnsw create library:idx {
-                dim: 384,
-                dtype: F32,
-                fields: [embedding],
-                distance: Cosine,
-                m: 50,
-                ef_construction: 200
-            }
-        ";
-        self.run_schema_script(create_lib_index, "library:idx")?;
-
-        let create_sessions = "
-            :create sessions {
-                id: String
-                =>
-                status: String,
-                depth: Int,
-                input: String,
-                created_at: Int
+    fn inspect_shape(&self) -> Result<StoreShape> {
+        let relations = self.list_names("::relations")?;
+        let mut shape = StoreShape { version: self.schema_version()?, ..Default::default() };
+
+        for relation in relations.iter().filter(|r| !r.contains(':')) {
+            for index in self.list_names(&format!("::indices {}", relation))? {
+                // Depending on the Cozo version the name may or may not carry the relation prefix.
+                let full = if index.contains(':') { index } else { format!("{}:{}", relation, index) };
+                shape.indices.insert(full);
             }
-        ";
-        self.run_schema_script(create_sessions, "sessions")?;
-
-        let create_messages = "
-            :create session_messages {
-                session_id: String,
-                msg_index: Int
-                =>
-                content: String
+            if matches!(relation.as_str(), "nodes" | "library" | "sessions" | "session_messages") {
+                let columns = self.list_names(&format!("::columns {}", relation))?;
+                shape.columns.insert(relation.clone(), columns.into_iter().collect());
             }
-        ";
-        self.run_schema_script(create_messages, "session_messages")?;
+        }
+        // Index relations are listed alongside regular ones as `rel:name`.
+        shape.indices.extend(relations.iter().filter(|r| r.contains(':')).cloned());
+        shape.relations = relations.into_iter().filter(|r| !r.contains(':')).collect();
+        Ok(shape)
+    }
+
+    /// First column of a system op's output as strings.
+    fn list_names(&self, op: &str) -> Result<HashSet<String>> {
+        let result = self.run_script(op, Default::default(), ScriptMutability::Immutable)?;
+        Ok(result.rows.iter()
+            .filter_map(|row| row.first().and_then(|v| v.get_str()).map(|s| s.to_string()))
+            .collect())
+    }
+
+    /// Model id and vector width recorded for this store, if any.
+    pub fn embedding_meta(&self) -> Result<Option<(String, usize)>> {
+        let script = "?[model, dim] := *embedding_meta{key: 'active', model, dim}";
+        let result = self.run_script(script, Default::default(), ScriptMutability::Immutable)?;
+        Ok(result.rows.first().and_then(|row| {
+            let model = row.first()?.get_str()?.to_string();
+            let dim = row.get(1)?.get_int()?;
+            Some((model, dim as usize))
+        }))
+    }
 
+    pub fn set_embedding_meta(&self, model: &str, dim: usize) -> Result<()> {
+        let script = "
+            ?[key, model, dim] <- [['active', $model, $dim]]
+            :put embedding_meta { key => model, dim }
+        ";
+        let mut params = BTreeMap::new();
+        params.insert("model".to_string(), DataValue::from(model));
+        params.insert("dim".to_string(), DataValue::from(dim as i64));
+        self.run_script(script, params, ScriptMutability::Mutable)?;
         Ok(())
     }
 
-    fn run_schema_script(&self, script: &str, name: &str) -> Result<()> {
-        if let Err(e) = self.db.run_script(script, Default::default(), ScriptMutability::Mutable) {
-            let msg = e.to_string();
-            if !msg.contains("conflicts with an existing one") && !msg.contains("already exists") && !msg.contains("non-existent field") {
-                eprintln!("Database initialization error ({}): {}", name, e);
+    /// Drops each of `relations` (vector relations) and recreates it empty
+    /// at `dim`. Callers snapshot the rows first and re-embed them afterwards.
+    pub fn recreate_vector_relations(&mut self, relations: &[&str], dim: usize) -> Result<()> {
+        for relation in relations {
+            for (name, kind, _) in migrations::index_ddl(relation, self.dim) {
+                let _ = self.db.run_script(&format!("::{} drop {}", kind, name), Default::default(), ScriptMutability::Mutable);
+            }
+            self.run_script(&format!("::remove {}", relation), Default::default(), ScriptMutability::Mutable)
+                .with_context(|| format!("Failed to drop `{}`", relation))?;
+        }
+
+        self.dim = dim;
+        for relation in relations {
+            self.run_script(&migrations::relation_ddl(relation, dim), Default::default(), ScriptMutability::Mutable)
+                .with_context(|| format!("Failed to recreate `{}`", relation))?;
+            for (name, _, script) in migrations::index_ddl(relation, dim) {
+                self.run_script(&script, Default::default(), ScriptMutability::Mutable)
+                    .with_context(|| format!("Failed to recreate `{}`", name))?;
             }
         }
         Ok(())
@@ -291,26 +232,138 @@ impl CozoBackend {
     /// Records an atomic fact (Event) in the immutable log.
     /// This is the 'Hickey Solution' to decoupled state.
     pub fn record_event(&self, op: &str, data: serde_json::Value) -> Result<()> {
-        let script = "
-            ?[id, op, data, timestamp, version] <- [[$id, $op, $data, $timestamp, $version]]
-            :put event_log { id => op, data, timestamp, version }
-        ";
-        let mut params = BTreeMap::new();
-        params.insert("id".to_string(), DataValue::from(Uuid::new_v4().to_string()));
-        params.insert("op".to_string(), DataValue::from(op));
-        
-        // Correct construction for Cozo JSON
-        params.insert("data".to_string(), DataValue::from(data));
-        
-        params.insert("timestamp".to_string(), DataValue::from(Utc::now().timestamp_millis()));
-        params.insert("version".to_string(), DataValue::from(1));
+        self.record_correlated(op, data, &Correlation::default())
+    }
 
-        self.run_script(script, params, ScriptMutability::Mutable)?;
+    pub fn record_correlated(&self, op: &str, data: serde_json::Value, correlation: &Correlation) -> Result<()> {
+        let event = EventRecord {
+            id: Uuid::new_v4().to_string(),
+            op: op.to_string(),
+            data,
+            timestamp: Utc::now().timestamp_millis(),
+            version: EVENT_VERSION,
+            session_id: correlation.session_id.clone(),
+            directive_id: correlation.directive_id.clone(),
+        };
+        self.replace_events(&[], &[event])
+    }
+
+    /// Removes `remove` and writes `put` (to both the log and its time
+    /// index) in one transaction.
+    pub fn replace_events(&self, remove: &[EventRecord], put: &[EventRecord]) -> Result<()> {
+        let mut blocks = Vec::new();
+        let mut params = BTreeMap::new();
+        if !remove.is_empty() {
+            blocks.push("{ ?[id] <- $remove_ids :rm event_log { id } }");
+            blocks.push("{ ?[timestamp, id] <- $remove_keys :rm event_by_time { timestamp, id } }");
+            params.insert("remove_ids".to_string(), DataValue::List(
+                remove.iter().map(|e| DataValue::List(vec![DataValue::from(e.id.clone())])).collect(),
+            ));
+            params.insert("remove_keys".to_string(), DataValue::List(
+                remove.iter().map(|e| DataValue::List(vec![DataValue::from(e.timestamp), DataValue::from(e.id.clone())])).collect(),
+            ));
+        }
+        if !put.is_empty() {
+            blocks.push("{ ?[id, op, data, timestamp, version] <- $log_rows :put event_log { id => op, data, timestamp, version } }");
+            blocks.push("{ ?[timestamp, id, op, session_id, directive_id] <- $time_rows :put event_by_time { timestamp, id => op, session_id, directive_id } }");
+            let optional = |v: &Option<String>| v.clone().map(DataValue::from).unwrap_or(DataValue::Null);
+            params.insert("log_rows".to_string(), DataValue::List(put.iter().map(|e| DataValue::List(vec![
+                DataValue::from(e.id.clone()),
+                DataValue::from(e.op.clone()),
+                DataValue::from(e.data.clone()),
+                DataValue::from(e.timestamp),
+                DataValue::from(e.version),
+            ])).collect()));
+            params.insert("time_rows".to_string(), DataValue::List(put.iter().map(|e| DataValue::List(vec![
+                DataValue::from(e.timestamp),
+                DataValue::from(e.id.clone()),
+                DataValue::from(e.op.clone()),
+                optional(&e.session_id),
+                optional(&e.directive_id),
+            ])).collect()));
+        }
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        self.run_script(&blocks.join("\n"), params, ScriptMutability::Mutable)?;
         Ok(())
     }
+
+    /// Scans `event_by_time`, so time-bounded queries never touch older rows.
+    pub fn query_events(&self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        let mut filters = String::new();
+        let mut params = BTreeMap::new();
+        if let Some(since) = query.since {
+            filters.push_str(", ts > $since");
+            params.insert("since".to_string(), DataValue::from(since));
+        }
+        if let Some(until) = query.until {
+            filters.push_str(", ts <= $until");
+            params.insert("until".to_string(), DataValue::from(until));
+        }
+        if !query.ops.is_empty() {
+            filters.push_str(", is_in(op, $ops)");
+            params.insert("ops".to_string(), DataValue::List(query.ops.iter().map(|o| DataValue::from(o.clone())).collect()));
+        }
+        if let Some(session_id) = &query.session_id {
+            filters.push_str(", session_id == $session_id");
+            params.insert("session_id".to_string(), DataValue::from(session_id.clone()));
+        }
+        if let Some(directive_id) = &query.directive_id {
+            filters.push_str(", directive_id == $directive_id");
+            params.insert("directive_id".to_string(), DataValue::from(directive_id.clone()));
+        }
+
+        let script = format!(
+            "?[ts, id, op, data, version, session_id, directive_id] := *event_by_time{{timestamp: ts, id, op, session_id, directive_id}}{filters}, *event_log{{id, data, version}}
+            :sort {order}ts
+            :limit {limit}",
+            filters = filters,
+            order = if query.latest { "-" } else { "" },
+            limit = query.limit
+        );
+        let result = self.run_script(&script, params, ScriptMutability::Immutable)?;
+
+        let text = |v: Option<&DataValue>| v.and_then(|v| v.get_str()).map(|s| s.to_string());
+        let mut events: Vec<EventRecord> = result.rows.iter()
+            .filter_map(|row| Some(EventRecord {
+                timestamp: row.first()?.get_int()?,
+                id: text(row.get(1))?,
+                op: text(row.get(2))?,
+                data: match row.get(3) {
+                    Some(DataValue::Json(j)) => j.0.clone(),
+                    _ => serde_json::Value::Null,
+                },
+                version: row.get(4).and_then(|v| v.get_int()).unwrap_or(1),
+                session_id: text(row.get(5)),
+                directive_id: text(row.get(6)),
+            }))
+            .collect();
+        if query.latest {
+            events.reverse();
+        }
+        Ok(events)
+    }
+}
+
+/// SQLite when a `cozo.sqlite` file exists under `path`, RocksDB otherwise.
+pub fn detect_engine(path: &str) -> StorageEngine {
+    if Path::new(path).join(SQLITE_FILE).exists() {
+        StorageEngine::Sqlite
+    } else {
+        StorageEngine::RocksDb
+    }
 }
 
 // Convert Rust Vec<f32> to Cozo DataValue::Vec
 pub fn vec_to_datavalue(v: Vec<f32>) -> DataValue {
     DataValue::Vec(Vector::F32(Array1::from_vec(v)))
 }
+
+pub fn datavalue_to_vec(v: &DataValue) -> Option<Vec<f32>> {
+    match v {
+        DataValue::Vec(Vector::F32(a)) => Some(a.to_vec()),
+        DataValue::Vec(Vector::F64(a)) => Some(a.iter().map(|x| *x as f32).collect()),
+        _ => None,
+    }
+}
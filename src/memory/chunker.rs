@@ -0,0 +1,350 @@
+use quote::ToTokens;
+
+/// BGE-small (and most BERT encoders) see at most 512 positions, two of which
+/// are taken by [CLS]/[SEP].
+pub const MODEL_MAX_TOKENS: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkKind {
+    Code,
+    Markdown,
+    Text,
+}
+
+impl ChunkKind {
+    /// Maps a `GraphNode::node_type` to the splitting strategy for its content.
+    pub fn for_node_type(node_type: &str) -> Self {
+        match node_type {
+            "markdown_heading" => ChunkKind::Markdown,
+            "file" | "lesson" | "heuristic" => ChunkKind::Text,
+            _ => ChunkKind::Code,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkerConfig {
+    pub max_tokens: usize,
+    pub overlap_tokens: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: MODEL_MAX_TOKENS - 32,
+            overlap_tokens: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub index: usize,
+    pub content: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddedChunk {
+    pub index: usize,
+    pub content: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Embedding of a whole parent (node or library entry) plus the per-chunk rows.
+/// `chunks` is empty when the content fits in a single window.
+#[derive(Debug, Clone)]
+pub struct ChunkedEmbedding {
+    pub embedding: Vec<f32>,
+    pub chunks: Vec<EmbeddedChunk>,
+}
+
+/// A unit the packer will not split unless it alone exceeds the budget.
+/// `context` is the breadcrumb (signature, heading path) repeated on every
+/// chunk so each window stays self-describing.
+#[derive(Debug, Clone)]
+struct Segment {
+    text: String,
+    context: String,
+    boundary: bool,
+}
+
+// --- Pure Functions ---
+
+/// Splits `content` into token-bounded, overlapping windows.
+/// `count_tokens` is injected so callers can use the real tokenizer when the
+/// embedding engine is loaded and a cheap estimate otherwise.
+pub fn chunk(content: &str, kind: ChunkKind, config: &ChunkerConfig, count_tokens: &dyn Fn(&str) -> usize) -> Vec<Chunk> {
+    if content.trim().is_empty() {
+        return vec![];
+    }
+    if count_tokens(content) <= config.max_tokens {
+        return vec![Chunk { index: 0, content: content.to_string() }];
+    }
+
+    let segments = match kind {
+        ChunkKind::Code => code_segments(content),
+        ChunkKind::Markdown => markdown_segments(content),
+        ChunkKind::Text => text_segments(content),
+    };
+    let separator = if kind == ChunkKind::Code { "\n" } else { "\n\n" };
+
+    pack(segments, separator, config, count_tokens)
+        .into_iter()
+        .enumerate()
+        .map(|(index, content)| Chunk { index, content })
+        .collect()
+}
+
+/// Cheap stand-in for a tokenizer (~4 bytes per token for English and code).
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Mean of the chunk vectors, re-normalized so it lives on the same unit
+/// sphere as single-shot embeddings.
+pub fn mean_pool(vectors: &[Vec<f32>]) -> Vec<f32> {
+    let Some(first) = vectors.first() else { return vec![] };
+    let mut sum = vec![0.0f32; first.len()];
+    for v in vectors {
+        for (acc, x) in sum.iter_mut().zip(v) {
+            *acc += x;
+        }
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        sum.iter_mut().for_each(|x| *x /= norm);
+    }
+    sum
+}
+
+fn pack(segments: Vec<Segment>, separator: &str, config: &ChunkerConfig, count_tokens: &dyn Fn(&str) -> usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut window: Vec<(Segment, usize)> = Vec::new();
+    let mut window_tokens = 0;
+
+    let budget_for = |context: &str| config.max_tokens.saturating_sub(count_tokens(context)).max(1);
+
+    for seg in segments.into_iter().flat_map(|s| split_oversized(s, &budget_for, count_tokens)) {
+        let seg_tokens = count_tokens(&seg.text);
+        let budget = window.first().map(|(s, _)| budget_for(&s.context)).unwrap_or_else(|| budget_for(&seg.context));
+
+        // Prefer structural boundaries once the window is reasonably full,
+        // and never straddle two different contexts.
+        let context_changed = window.first().map(|(s, _)| s.context != seg.context).unwrap_or(false);
+        let at_boundary = seg.boundary && window_tokens >= budget / 2;
+        let overflow = window_tokens + seg_tokens > budget;
+
+        if !window.is_empty() && (overflow || at_boundary || context_changed) {
+            chunks.push(render(&window, separator));
+            if overflow && !seg.boundary && !context_changed {
+                window = overlap_tail(&window, config.overlap_tokens.min(budget.saturating_sub(seg_tokens)));
+            } else {
+                window.clear();
+            }
+            window_tokens = window.iter().map(|(_, t)| t).sum();
+        }
+
+        window_tokens += seg_tokens;
+        window.push((seg, seg_tokens));
+    }
+
+    if !window.is_empty() {
+        chunks.push(render(&window, separator));
+    }
+    chunks
+}
+
+fn render(window: &[(Segment, usize)], separator: &str) -> String {
+    let body = window.iter().map(|(s, _)| s.text.as_str()).collect::<Vec<_>>().join(separator);
+    match window.first() {
+        Some((s, _)) if !s.context.is_empty() && !body.starts_with(&s.context) => format!("{}\n{}", s.context, body),
+        _ => body,
+    }
+}
+
+fn overlap_tail(window: &[(Segment, usize)], overlap_tokens: usize) -> Vec<(Segment, usize)> {
+    let mut tail = Vec::new();
+    let mut total = 0;
+    for (seg, tokens) in window.iter().rev() {
+        if total + tokens > overlap_tokens {
+            break;
+        }
+        total += tokens;
+        tail.push((seg.clone(), *tokens));
+    }
+    tail.reverse();
+    tail
+}
+
+/// Breaks a single segment that alone exceeds the budget into word windows.
+fn split_oversized(seg: Segment, budget_for: &dyn Fn(&str) -> usize, count_tokens: &dyn Fn(&str) -> usize) -> Vec<Segment> {
+    let budget = budget_for(&seg.context);
+    if count_tokens(&seg.text) <= budget {
+        return vec![seg];
+    }
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for word in seg.text.split_inclusive(char::is_whitespace) {
+        if !current.is_empty() && count_tokens(&current) + count_tokens(word) > budget {
+            pieces.push(std::mem::take(&mut current));
+        }
+        current.push_str(word);
+    }
+    if !current.trim().is_empty() {
+        pieces.push(current);
+    }
+
+    pieces.into_iter().enumerate().map(|(i, text)| Segment {
+        text: text.trim().to_string(),
+        context: seg.context.clone(),
+        boundary: seg.boundary && i == 0,
+    }).collect()
+}
+
+/// Statement/member boundaries from the AST. Node content produced by
+/// `knowledge::parser` is a token stream on one line, so line splitting
+/// alone would see a single giant segment.
+fn code_segments(content: &str) -> Vec<Segment> {
+    let seg = |text: String, context: &str| Segment { text, context: context.to_string(), boundary: false };
+
+    if let Ok(item) = syn::parse_str::<syn::Item>(content) {
+        let (context, parts): (String, Vec<String>) = match &item {
+            syn::Item::Fn(f) => (
+                f.sig.to_token_stream().to_string(),
+                f.block.stmts.iter().map(|s| s.to_token_stream().to_string()).collect(),
+            ),
+            syn::Item::Impl(i) => (
+                format!("impl {}", i.self_ty.to_token_stream()),
+                i.items.iter().map(|it| it.to_token_stream().to_string()).collect(),
+            ),
+            syn::Item::Trait(t) => (
+                format!("trait {}", t.ident),
+                t.items.iter().map(|it| it.to_token_stream().to_string()).collect(),
+            ),
+            syn::Item::Struct(s) => (
+                format!("struct {}", s.ident),
+                s.fields.iter().map(|f| f.to_token_stream().to_string()).collect(),
+            ),
+            syn::Item::Enum(e) => (
+                format!("enum {}", e.ident),
+                e.variants.iter().map(|v| v.to_token_stream().to_string()).collect(),
+            ),
+            _ => (String::new(), vec![]),
+        };
+        if !parts.is_empty() {
+            return parts.into_iter().map(|p| seg(p, &context)).collect();
+        }
+    }
+
+    // Non-Rust or unparsable: blank lines separate logical blocks.
+    content.split("\n\n")
+        .filter(|b| !b.trim().is_empty())
+        .map(|b| Segment { text: b.to_string(), context: String::new(), boundary: true })
+        .collect()
+}
+
+/// Paragraphs tagged with their heading path ("# Guide > ## Install").
+fn markdown_segments(content: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut paragraph = String::new();
+    let mut starts_section = false;
+    let mut in_fence = false;
+
+    let flush = |paragraph: &mut String, headings: &[(usize, String)], boundary: &mut bool, out: &mut Vec<Segment>| {
+        if !paragraph.trim().is_empty() {
+            out.push(Segment {
+                text: paragraph.trim_end().to_string(),
+                context: headings.iter().map(|(_, h)| h.as_str()).collect::<Vec<_>>().join(" > "),
+                boundary: *boundary,
+            });
+            *boundary = false;
+        }
+        paragraph.clear();
+    };
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_fence = !in_fence;
+        }
+
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        let is_heading = !in_fence && level > 0 && level <= 6 && trimmed[level..].starts_with(' ');
+
+        if is_heading {
+            flush(&mut paragraph, &headings, &mut starts_section, &mut segments);
+            headings.retain(|(l, _)| *l < level);
+            headings.push((level, trimmed.to_string()));
+            starts_section = true;
+        } else if !in_fence && trimmed.is_empty() {
+            flush(&mut paragraph, &headings, &mut starts_section, &mut segments);
+        } else {
+            paragraph.push_str(line);
+            paragraph.push('\n');
+        }
+    }
+    flush(&mut paragraph, &headings, &mut starts_section, &mut segments);
+    segments
+}
+
+fn text_segments(content: &str) -> Vec<Segment> {
+    content.split("\n\n")
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| Segment { text: p.trim().to_string(), context: String::new(), boundary: false })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    #[test]
+    fn test_small_content_is_single_chunk() {
+        let config = ChunkerConfig { max_tokens: 50, overlap_tokens: 5 };
+        let chunks = chunk("fn main() {}", ChunkKind::Code, &config, &words);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].content, "fn main() {}");
+    }
+
+    #[test]
+    fn test_code_splits_on_statements_with_signature() {
+        let body: String = (0..40).map(|i| format!("let v{} = compute({}) ;", i, i)).collect::<Vec<_>>().join(" ");
+        let code = format!("fn big(x: u32) -> u32 {{ {} x }}", body);
+        let config = ChunkerConfig { max_tokens: 60, overlap_tokens: 10 };
+        let chunks = chunk(&code, ChunkKind::Code, &config, &words);
+
+        assert!(chunks.len() > 1);
+        for c in &chunks {
+            assert!(c.content.starts_with("fn big"), "missing signature: {}", c.content);
+            assert!(words(&c.content) <= 60);
+        }
+        // Overlap: the last statement of one chunk reappears in the next.
+        let last_stmt = chunks[0].content.lines().last().unwrap();
+        assert!(chunks[1].content.contains(last_stmt));
+    }
+
+    #[test]
+    fn test_markdown_chunks_carry_heading_path() {
+        let para = "word ".repeat(30);
+        let md = format!("# Guide\n\n## Install\n\n{p}\n\n{p}\n\n## Usage\n\n{p}\n", p = para);
+        let config = ChunkerConfig { max_tokens: 45, overlap_tokens: 0 };
+        let chunks = chunk(&md, ChunkKind::Markdown, &config, &words);
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[0].content.starts_with("# Guide > ## Install"));
+        assert!(chunks[2].content.starts_with("# Guide > ## Usage"));
+    }
+
+    #[test]
+    fn test_mean_pool_is_normalized() {
+        let pooled = mean_pool(&[vec![1.0, 0.0], vec![0.0, 1.0]]);
+        let norm: f32 = pooled.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+}
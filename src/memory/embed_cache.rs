@@ -0,0 +1,86 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Cache key for a text: hex sha256 of its UTF-8 bytes. Paired with the
+/// model id, so switching models never serves stale vectors.
+pub fn content_hash(text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(text.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Process-lifetime hit/miss counters for the embedding cache.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq)]
+pub struct CacheStatsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn record(&self, hits: usize, misses: usize) {
+        self.hits.fetch_add(hits as u64, Ordering::Relaxed);
+        self.misses.fetch_add(misses as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CacheStatsSnapshot {
+    /// Counts accumulated since `earlier`.
+    pub fn since(&self, earlier: &CacheStatsSnapshot) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+        }
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl std::fmt::Display for CacheStatsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} hits / {} misses ({:.0}% hit rate)", self.hits, self.misses, self.hit_rate() * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_stable_and_content_sensitive() {
+        assert_eq!(content_hash("fn a() {}"), content_hash("fn a() {}"));
+        assert_ne!(content_hash("fn a() {}"), content_hash("fn a() { }"));
+        assert_eq!(content_hash("").len(), 64);
+    }
+
+    #[test]
+    fn test_stats_delta_and_rate() {
+        let stats = CacheStats::default();
+        stats.record(1, 1);
+        let before = stats.snapshot();
+        stats.record(9, 1);
+        let delta = stats.snapshot().since(&before);
+        assert_eq!(delta, CacheStatsSnapshot { hits: 9, misses: 1 });
+        assert!((delta.hit_rate() - 0.9).abs() < 1e-9);
+    }
+}
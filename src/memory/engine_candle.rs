@@ -1,32 +1,82 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use candle_core::{DType, Device, Tensor, IndexOp};
 use candle_transformers::models::bert::{BertModel, Config};
-use hf_hub::{api::sync::Api, Repo, RepoType};
+use hf_hub::{api::sync::Api, Cache, Repo, RepoType};
+use std::path::{Path, PathBuf};
 use tokenizers::Tokenizer;
 
+use crate::core::state::EmbeddingConfig;
+
+/// Vector width of stores created before the model became configurable.
+pub const DEFAULT_EMBEDDING_DIM: usize = 384;
+
+/// The three files every BERT-style checkpoint we load needs.
+pub struct ModelFiles {
+    pub config: PathBuf,
+    pub tokenizer: PathBuf,
+    pub weights: PathBuf,
+}
+
+impl ModelFiles {
+    /// `model` is either a local directory or a Hub id. Offline mode (or
+    /// `HF_HUB_OFFLINE=1`) resolves Hub ids from the local HF cache only.
+    pub fn resolve(model: &str, offline: bool) -> Result<Self> {
+        let dir = Path::new(model);
+        if dir.is_dir() {
+            let files = Self {
+                config: dir.join("config.json"),
+                tokenizer: dir.join("tokenizer.json"),
+                weights: dir.join("model.safetensors"),
+            };
+            for f in [&files.config, &files.tokenizer, &files.weights] {
+                if !f.exists() {
+                    return Err(anyhow!("Model directory {} is missing {}", model, f.display()));
+                }
+            }
+            return Ok(files);
+        }
+
+        let offline = offline || std::env::var("HF_HUB_OFFLINE").is_ok_and(|v| v == "1");
+        if offline {
+            let repo = Cache::from_env().model(model.to_string());
+            let get = |name: &str| repo.get(name)
+                .with_context(|| format!("{} not in the local HF cache for {} (offline mode)", name, model));
+            return Ok(Self {
+                config: get("config.json")?,
+                tokenizer: get("tokenizer.json")?,
+                weights: get("model.safetensors")?,
+            });
+        }
+
+        let api = Api::new()?;
+        let repo = api.repo(Repo::new(model.to_string(), RepoType::Model));
+        Ok(Self {
+            config: repo.get("config.json")?,
+            tokenizer: repo.get("tokenizer.json")?,
+            weights: repo.get("model.safetensors")?,
+        })
+    }
+}
+
 pub struct EmbeddingEngine {
     model: BertModel,
     tokenizer: Tokenizer,
     device: Device,
+    model_id: String,
+    dim: usize,
 }
 
 impl EmbeddingEngine {
-    pub fn new() -> Result<Self> {
+    pub fn new(settings: &EmbeddingConfig) -> Result<Self> {
         // Initialize Candle with Metal support (MacOS GPU)
         let device = Device::new_metal(0).unwrap_or(Device::Cpu);
-        println!("🧠 Initializing Embedding Engine on device: {:?}", device);
+        eprintln!("🧠 Initializing Embedding Engine ({}) on device: {:?}", settings.model, device);
 
-        // Load model from HF Hub (BGE-Small-en-v1.5)
-        let model_id = "BAAI/bge-small-en-v1.5".to_string();
-        let api = Api::new()?;
-        let repo = api.repo(Repo::new(model_id, RepoType::Model));
-
-        let config_filename = repo.get("config.json")?;
-        let tokenizer_filename = repo.get("tokenizer.json")?;
-        let weights_filename = repo.get("model.safetensors")?;
+        let files = ModelFiles::resolve(&settings.model, settings.offline)?;
+        let weights_filename = files.weights;
 
-        let config: Config = serde_json::from_str(&std::fs::read_to_string(config_filename)?)?;
-        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(|e| anyhow!(e))?;
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(files.config)?)?;
+        let mut tokenizer = Tokenizer::from_file(files.tokenizer).map_err(|e| anyhow!(e))?;
 
         if let Some(pp) = tokenizer.get_padding_mut() {
             pp.strategy = tokenizers::PaddingStrategy::BatchLongest;
@@ -48,7 +98,7 @@ impl EmbeddingEngine {
             Ok(res) => res,
             Err(e) => {
                 if device.is_metal() {
-                    println!("Metal implementation incomplete for this model ({}). Falling back to CPU.", e);
+                    eprintln!("Metal implementation incomplete for this model ({}). Falling back to CPU.", e);
                     let cpu_device = Device::Cpu;
                     let tensors = candle_core::safetensors::load(&weights_filename, &cpu_device)?;
                     let vb = candle_nn::VarBuilder::from_tensors(tensors, DType::F32, &cpu_device);
@@ -64,9 +114,20 @@ impl EmbeddingEngine {
             model,
             tokenizer,
             device: final_device,
+            model_id: settings.model.clone(),
+            dim: config.hidden_size,
         })
     }
 
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Embedding width, taken from the model's `hidden_size`.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
     pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
         let tokens = self.tokenizer.encode(text, true).map_err(|e| anyhow!(e))?;
         let token_ids = Tensor::new(tokens.get_ids(), &self.device)?.unsqueeze(0)?;
@@ -82,6 +143,13 @@ impl EmbeddingEngine {
         Ok(vector)
     }
 
+    /// Token count as seen by the model, excluding [CLS]/[SEP].
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.encode(text, false)
+            .map(|e| e.len())
+            .unwrap_or_else(|_| crate::memory::chunker::estimate_tokens(text))
+    }
+
     pub fn batch_embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
         if texts.is_empty() {
             return Ok(vec![]);
@@ -124,6 +192,45 @@ impl EmbeddingEngine {
     }
 }
 
+#[cfg(test)]
+impl EmbeddingEngine {
+    /// A one-layer BERT with random weights and a word-level vocabulary,
+    /// for tests that need vectors of some width but not a real model.
+    pub(crate) fn tiny(model_id: &str, dim: usize) -> Result<Self> {
+        let varmap = candle_nn::VarMap::new();
+        let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let model = BertModel::load(vb, &tiny_config(dim)?)?;
+        Ok(Self { model, tokenizer: tiny_tokenizer()?, device: Device::Cpu, model_id: model_id.to_string(), dim })
+    }
+}
+
+/// Config for a one-layer BERT `dim` wide over the `tiny_tokenizer` vocabulary.
+#[cfg(test)]
+pub(crate) fn tiny_config(dim: usize) -> Result<Config> {
+    Ok(serde_json::from_value(serde_json::json!({
+        "vocab_size": 8, "hidden_size": dim, "num_hidden_layers": 1, "num_attention_heads": 2,
+        "intermediate_size": 16, "hidden_act": "gelu", "hidden_dropout_prob": 0.0,
+        "max_position_embeddings": 4096, "type_vocab_size": 2, "initializer_range": 0.02,
+        "layer_norm_eps": 1e-12, "pad_token_id": 0, "classifier_dropout": null, "model_type": "bert"
+    }))?)
+}
+
+/// Word-level tokenizer over a handful of code words, with BERT's
+/// `[CLS] a [SEP] b [SEP]` framing, to pair with the random test models.
+#[cfg(test)]
+pub(crate) fn tiny_tokenizer() -> Result<Tokenizer> {
+    let tokenizer = serde_json::json!({
+        "version": "1.0", "truncation": null, "added_tokens": [], "decoder": null,
+        "padding": { "strategy": "BatchLongest", "direction": "Right", "pad_to_multiple_of": null, "pad_id": 0, "pad_type_id": 0, "pad_token": "[PAD]" },
+        "normalizer": { "type": "Lowercase" },
+        "pre_tokenizer": { "type": "Whitespace" },
+        "post_processor": { "type": "BertProcessing", "sep": ["[SEP]", 3], "cls": ["[CLS]", 2] },
+        "model": { "type": "WordLevel", "unk_token": "[UNK]",
+                   "vocab": { "[PAD]": 0, "[UNK]": 1, "[CLS]": 2, "[SEP]": 3, "fn": 4, "struct": 5, "docs": 6, "cache": 7 } }
+    });
+    Tokenizer::from_bytes(serde_json::to_vec(&tokenizer)?).map_err(|e| anyhow!(e))
+}
+
 // Helper for L2 Normalization (pure calculation)
 fn normalize_tensor_l2(v: &Tensor) -> Result<Tensor> {
     // v is shape [hidden_size]
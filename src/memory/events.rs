@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::replay;
+use crate::mcp::registry::{APPROVAL_CONSUMED, APPROVAL_DECIDED, APPROVAL_REQUESTED};
+
+/// Format of newly written events. v1 events carry no correlation ids.
+pub const EVENT_VERSION: i64 = 2;
+
+pub const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Ops `retention_days` never deletes: replay rebuilds sessions, memories and
+/// overlays from them, and held tool calls are restored from the approvals.
+pub const RETAINED_OPS: &[&str] = &[
+    replay::SESSION_CREATED,
+    replay::SESSION_UPDATED,
+    replay::MEMORY_STORED,
+    replay::FORGET,
+    replay::OVERLAY_WRITE,
+    replay::OVERLAY_COMMIT,
+    replay::SESSION_FORKED,
+    APPROVAL_REQUESTED,
+    APPROVAL_DECIDED,
+    APPROVAL_CONSUMED,
+];
+
+/// One row of the event log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub id: String,
+    pub op: String,
+    pub data: Value,
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+    #[serde(default = "legacy_version")]
+    pub version: i64,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub directive_id: Option<String>,
+}
+
+fn legacy_version() -> i64 {
+    1
+}
+
+/// What caused an event, so related events can be pulled together.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Correlation {
+    pub session_id: Option<String>,
+    pub directive_id: Option<String>,
+}
+
+/// Filter for `EventLog::query_events`. All set fields must match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventQuery {
+    /// Exact op names; empty means every op.
+    pub ops: Vec<String>,
+    /// Exclusive lower bound (ms).
+    pub since: Option<i64>,
+    /// Inclusive upper bound (ms).
+    pub until: Option<i64>,
+    pub session_id: Option<String>,
+    pub directive_id: Option<String>,
+    pub limit: usize,
+    /// Take the most recent `limit` events instead of the oldest.
+    /// Results are oldest first either way.
+    pub latest: bool,
+}
+
+impl Default for EventQuery {
+    fn default() -> Self {
+        Self {
+            ops: vec![],
+            since: None,
+            until: None,
+            session_id: None,
+            directive_id: None,
+            limit: 100,
+            latest: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct RetentionReport {
+    /// Events deleted for being older than the retention window.
+    pub expired: usize,
+    /// Events folded into summaries.
+    pub compacted: usize,
+    pub summaries: usize,
+}
+
+/// Events folded into one summary: `summary` replaces every id in `replaces`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compaction {
+    pub summary: EventRecord,
+    pub replaces: Vec<EventRecord>,
+}
+
+// --- Pure Functions ---
+
+/// Whether `retention_days` may delete `event` once it is old enough.
+pub fn expires(event: &EventRecord) -> bool {
+    !RETAINED_OPS.contains(&event.op.as_str())
+}
+
+impl EventQuery {
+    pub fn matches(&self, event: &EventRecord) -> bool {
+        (self.ops.is_empty() || self.ops.contains(&event.op))
+            && self.since.is_none_or(|since| event.timestamp > since)
+            && self.until.is_none_or(|until| event.timestamp <= until)
+            && self.session_id.as_ref().is_none_or(|s| event.session_id.as_ref() == Some(s))
+            && self.directive_id.as_ref().is_none_or(|d| event.directive_id.as_ref() == Some(d))
+    }
+
+    /// Applies the filter and limit to events sorted oldest first.
+    pub fn select<'a>(&self, events: impl Iterator<Item = &'a EventRecord>) -> Vec<EventRecord> {
+        let matching: Vec<&EventRecord> = events.filter(|e| self.matches(e)).collect();
+        let skip = if self.latest { matching.len().saturating_sub(self.limit) } else { 0 };
+        matching.into_iter().skip(skip).take(self.limit).cloned().collect()
+    }
+}
+
+/// Groups `events` by (op, session, UTC day) and folds each group of two or
+/// more into a summary that keeps the first event's id and timestamp, the
+/// count and time span, and the first payload as a sample. Summaries fold
+/// into later summaries without losing their counts. `RETAINED_OPS` events
+/// are never folded, whatever `compact_ops` lists.
+pub fn plan_compaction(events: &[EventRecord]) -> Vec<Compaction> {
+    let mut groups: BTreeMap<(String, Option<String>, i64), Vec<&EventRecord>> = BTreeMap::new();
+    for event in events.iter().filter(|e| expires(e)) {
+        let key = (event.op.clone(), event.session_id.clone(), event.timestamp.div_euclid(DAY_MS));
+        groups.entry(key).or_default().push(event);
+    }
+
+    groups.into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|e| e.timestamp);
+            let first = group[0];
+            let count: u64 = group.iter().map(|e| compacted_count(e)).sum();
+            let last = group.iter().map(|e| e.data.get("last").and_then(Value::as_i64).unwrap_or(e.timestamp)).max().unwrap_or(first.timestamp);
+            let sample = if compacted_count(first) > 1 { first.data["sample"].clone() } else { first.data.clone() };
+            Compaction {
+                summary: EventRecord {
+                    id: first.id.clone(),
+                    op: first.op.clone(),
+                    data: serde_json::json!({
+                        "compacted": count,
+                        "first": first.timestamp,
+                        "last": last,
+                        "sample": sample
+                    }),
+                    timestamp: first.timestamp,
+                    version: EVENT_VERSION,
+                    session_id: first.session_id.clone(),
+                    directive_id: None,
+                },
+                replaces: group.into_iter().cloned().collect(),
+            }
+        })
+        .collect()
+}
+
+/// A point in time for CLI filters: epoch milliseconds, RFC 3339, or an
+/// age such as `90s`, `15m`, `6h`, `2d` counted back from `now`.
+pub fn parse_time(text: &str, now: i64) -> Option<i64> {
+    if let Ok(ms) = text.parse::<i64>() {
+        return Some(ms);
+    }
+    if let Ok(at) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(at.timestamp_millis());
+    }
+    let unit = match text.chars().last()? {
+        's' => 1_000,
+        'm' => 60_000,
+        'h' => 3_600_000,
+        'd' => DAY_MS,
+        _ => return None,
+    };
+    let amount: i64 = text[..text.len() - 1].parse().ok()?;
+    Some(now - amount * unit)
+}
+
+/// How many original events `event` stands for.
+fn compacted_count(event: &EventRecord) -> u64 {
+    event.data.get("compacted").and_then(Value::as_u64).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, op: &str, timestamp: i64, session: Option<&str>) -> EventRecord {
+        EventRecord {
+            id: id.to_string(),
+            op: op.to_string(),
+            data: serde_json::json!({ "n": id }),
+            timestamp,
+            version: EVENT_VERSION,
+            session_id: session.map(str::to_string),
+            directive_id: None,
+        }
+    }
+
+    #[test]
+    fn test_query_selection() {
+        let events = [
+            event("a", "EXEC:think", 10, Some("s1")),
+            event("b", "store_cache", 20, None),
+            event("c", "EXEC:think", 30, Some("s2")),
+            event("d", "EXEC:think", 40, Some("s1")),
+        ];
+        let by_session = EventQuery { session_id: Some("s1".to_string()), ..Default::default() };
+        assert_eq!(by_session.select(events.iter()).iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), vec!["a", "d"]);
+
+        let tail = EventQuery { ops: vec!["EXEC:think".to_string()], limit: 2, latest: true, ..Default::default() };
+        assert_eq!(tail.select(events.iter()).iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), vec!["c", "d"]);
+
+        let window = EventQuery { since: Some(10), until: Some(30), ..Default::default() };
+        assert_eq!(window.select(events.iter()).len(), 2);
+    }
+
+    #[test]
+    fn test_compaction_groups_by_op_session_and_day() {
+        let events = vec![
+            event("a", "store_cache", 1_000, None),
+            event("b", "store_cache", 2_000, None),
+            event("c", "store_cache", DAY_MS + 1, None),
+            event("d", "batch_add_nodes", 3_000, None),
+            event("e", replay::SESSION_CREATED, 4_000, None),
+            event("f", replay::SESSION_CREATED, 5_000, None),
+        ];
+        let plan = plan_compaction(&events);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].summary.id, "a");
+        assert_eq!(plan[0].summary.data["compacted"], 2);
+        assert_eq!(plan[0].summary.data["last"], 2_000);
+
+        // A summary folds into later events without losing its count.
+        let again = plan_compaction(&[plan[0].summary.clone(), event("e", "store_cache", 5_000, None)]);
+        assert_eq!(again[0].summary.data["compacted"], 3);
+        assert_eq!(again[0].summary.data["sample"], serde_json::json!({ "n": "a" }));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000000", 0), Some(1_700_000_000_000));
+        assert_eq!(parse_time("2h", 10_000_000), Some(10_000_000 - 7_200_000));
+        assert_eq!(parse_time("1970-01-01T00:00:01Z", 0), Some(1_000));
+        assert_eq!(parse_time("yesterday", 0), None);
+    }
+}
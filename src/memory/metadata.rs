@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+/// Metadata accepted by `MemoryStore::store`. Every field is optional in
+/// the JSON form; unknown fields are rejected so typos do not vanish.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeMetadata {
+    /// Becomes the node type, e.g. "heuristic", "lesson", "fact".
+    pub kind: String,
+    pub source_session: Option<String>,
+    pub tags: Vec<String>,
+    /// Seconds until the memory stops being recalled and is purged.
+    pub ttl_secs: Option<i64>,
+    /// 0.0..=1.0, how much the writer trusts this memory.
+    pub confidence: f64,
+}
+
+impl Default for NodeMetadata {
+    fn default() -> Self {
+        Self {
+            kind: "heuristic".to_string(),
+            source_session: None,
+            tags: vec![],
+            ttl_secs: None,
+            confidence: 1.0,
+        }
+    }
+}
+
+/// Metadata as persisted in `node_meta`: the TTL resolved to a deadline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredMetadata {
+    pub kind: String,
+    pub source_session: Option<String>,
+    pub tags: Vec<String>,
+    pub confidence: f64,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl StoredMetadata {
+    pub fn from_input(meta: NodeMetadata, now: i64) -> Self {
+        Self {
+            kind: meta.kind,
+            source_session: meta.source_session,
+            tags: meta.tags,
+            confidence: meta.confidence.clamp(0.0, 1.0),
+            expires_at: meta.ttl_secs.map(|ttl| now.saturating_add(ttl.saturating_mul(1000))),
+            created_at: now,
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// Restricts recall to memories whose metadata matches. Nodes without
+/// metadata (ingested code) only pass the default, unconstrained filter.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataFilter {
+    /// Any of these kinds; empty means any kind.
+    pub kinds: Vec<String>,
+    /// All of these tags.
+    pub tags: Vec<String>,
+    pub source_session: Option<String>,
+    pub min_confidence: Option<f64>,
+    pub include_expired: bool,
+}
+
+impl MetadataFilter {
+    /// True when the filter needs more than the expiry check.
+    pub fn is_constrained(&self) -> bool {
+        !self.kinds.is_empty() || !self.tags.is_empty() || self.source_session.is_some() || self.min_confidence.is_some()
+    }
+
+    // --- Pure Functions ---
+
+    pub fn matches(&self, meta: Option<&StoredMetadata>, now: i64) -> bool {
+        let Some(meta) = meta else { return !self.is_constrained() };
+        if !self.include_expired && meta.is_expired(now) {
+            return false;
+        }
+        (self.kinds.is_empty() || self.kinds.contains(&meta.kind))
+            && self.tags.iter().all(|t| meta.tags.contains(t))
+            && self.source_session.as_ref().is_none_or(|s| meta.source_session.as_ref() == Some(s))
+            && self.min_confidence.is_none_or(|min| meta.confidence >= min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(kind: &str, tags: &[&str], expires_at: Option<i64>) -> StoredMetadata {
+        StoredMetadata {
+            kind: kind.to_string(),
+            source_session: Some("s1".to_string()),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            confidence: 0.6,
+            expires_at,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_input_parsing_defaults_and_rejects_typos() {
+        let meta: NodeMetadata = serde_json::from_value(serde_json::json!({ "kind": "lesson", "ttl_secs": 60 })).unwrap();
+        assert_eq!(meta.confidence, 1.0);
+        assert_eq!(StoredMetadata::from_input(meta, 1_000).expires_at, Some(61_000));
+        assert!(serde_json::from_value::<NodeMetadata>(serde_json::json!({ "tag": ["x"] })).is_err());
+    }
+
+    #[test]
+    fn test_filter_matching() {
+        let meta = stored("lesson", &["auth", "db"], Some(100));
+        let by_tag = MetadataFilter { tags: vec!["auth".to_string()], ..Default::default() };
+        assert!(by_tag.matches(Some(&meta), 50));
+        assert!(!by_tag.matches(Some(&meta), 100), "expired memories are hidden by default");
+        assert!(!by_tag.matches(None, 50), "code nodes carry no tags");
+        assert!(MetadataFilter::default().matches(None, 50));
+
+        let strict = MetadataFilter { min_confidence: Some(0.8), ..Default::default() };
+        assert!(!strict.matches(Some(&meta), 50));
+        let session = MetadataFilter { source_session: Some("s2".to_string()), ..Default::default() };
+        assert!(!session.matches(Some(&meta), 50));
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::{HashMap, HashSet};
+
+/// Newest schema this binary can read and write. Stores stamped with a
+/// higher version are refused rather than silently misread.
+pub const SCHEMA_VERSION: i64 = 7;
+
+/// One idempotent schema change. Each is skipped when the store already
+/// has it, so stores that predate versioning converge on the same shape.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// `:create` the relation when it is missing.
+    Relation(&'static str),
+    /// Rebuild a pre-embedding relation through `:replace`, filling the new
+    /// `embedding` column with a zero vector.
+    AddEmbedding(&'static str),
+    /// Fill a new derived relation from existing rows. Runs in the same
+    /// transaction as the `Relation` change that creates it.
+    Backfill(&'static str),
+    /// Rebuild a relation through `:replace` into its current spec,
+    /// deriving the new columns from the old ones.
+    Retype(&'static str),
+    /// HNSW or FTS index, created when missing. Cozo cannot run index
+    /// operations inside a transaction, so these run after the relation step.
+    Index(&'static str),
+}
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub changes: &'static [Change],
+}
+
+/// Ordered, append-only. Never edit a released step; add a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline relations and vector indices",
+        changes: &[
+            Change::Relation("cache"),
+            Change::Relation("nodes"),
+            Change::AddEmbedding("nodes"),
+            Change::Relation("edges"),
+            Change::Relation("library"),
+            Change::AddEmbedding("library"),
+            Change::Relation("kv_cache"),
+            Change::Relation("sync_log"),
+            Change::Relation("event_log"),
+            Change::Relation("skills"),
+            Change::Relation("sessions"),
+            Change::Relation("session_messages"),
+            Change::Index("cache:idx"),
+            Change::Index("nodes:idx"),
+            Change::Index("library:idx"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "chunk rows for oversized content",
+        changes: &[Change::Relation("chunks"), Change::Index("chunks:idx")],
+    },
+    Migration {
+        version: 3,
+        description: "full-text index on nodes",
+        changes: &[Change::Index("nodes:fts")],
+    },
+    Migration {
+        version: 4,
+        description: "embedding model record and embedding cache",
+        changes: &[Change::Relation("embedding_meta"), Change::Relation("embedding_cache")],
+    },
+    Migration {
+        version: 5,
+        description: "typed metadata for stored memories",
+        changes: &[Change::Relation("node_meta")],
+    },
+    Migration {
+        version: 6,
+        description: "timestamp-ordered event index with correlation ids",
+        changes: &[Change::Relation("event_by_time"), Change::Backfill("event_by_time")],
+    },
+    Migration {
+        version: 7,
+        description: "typed session messages and structured session status",
+        changes: &[Change::Retype("sessions"), Change::Retype("session_messages")],
+    },
+];
+
+/// What a store currently contains, as far as migrations care.
+#[derive(Debug, Default, Clone)]
+pub struct StoreShape {
+    pub version: i64,
+    pub relations: HashSet<String>,
+    /// Full index names, e.g. `nodes:idx`.
+    pub indices: HashSet<String>,
+    pub columns: HashMap<String, Vec<String>>,
+}
+
+impl StoreShape {
+    pub fn satisfies(&self, change: &Change) -> bool {
+        match change {
+            Change::Relation(name) => self.relations.contains(*name),
+            // A missing relation is created with the column by `Relation`.
+            Change::AddEmbedding(name) => !self.relations.contains(*name)
+                || self.columns.get(*name).is_some_and(|cols| cols.iter().any(|c| c == "embedding")),
+            Change::Backfill(name) => self.relations.contains(*name),
+            // A missing relation is created in the new shape by `Relation`.
+            Change::Retype(name) => !self.relations.contains(*name)
+                || self.columns.get(*name).is_some_and(|cols| cols.iter().any(|c| c == retype_marker(name))),
+            Change::Index(name) => self.indices.contains(*name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedStep {
+    pub version: i64,
+    pub description: &'static str,
+    /// Only the changes the store is actually missing.
+    pub changes: Vec<Change>,
+}
+
+// --- Pure Functions ---
+
+/// Steps above the store's version, each trimmed to what is missing.
+/// Steps with nothing to do are kept so the version still advances.
+pub fn plan(shape: &StoreShape) -> Vec<PlannedStep> {
+    MIGRATIONS.iter()
+        .filter(|m| m.version > shape.version)
+        .map(|m| PlannedStep {
+            version: m.version,
+            description: m.description,
+            changes: m.changes.iter().filter(|c| !shape.satisfies(c)).cloned().collect(),
+        })
+        .collect()
+}
+
+/// Script for a change. `AddEmbedding` expects a `$zero_vec` parameter.
+pub fn change_script(change: &Change, dim: usize) -> String {
+    match change {
+        Change::Relation(name) => relation_ddl(name, dim),
+        Change::AddEmbedding(name) => {
+            let columns = legacy_columns(name);
+            format!(
+                "?[{cols}, embedding] := *{rel}{{{cols}}}, embedding = $zero_vec\n:replace {rel} {{ {spec} }}",
+                cols = columns,
+                rel = name,
+                spec = relation_spec(name, dim)
+            )
+        }
+        Change::Backfill(name) => backfill_script(name),
+        Change::Retype(name) => format!("{}\n:replace {} {{ {} }}", retype_rules(name), name, relation_spec(name, dim)),
+        Change::Index(name) => index_ddl_by_name(name, dim),
+    }
+}
+
+fn backfill_script(relation: &str) -> String {
+    match relation {
+        // Events written before v6 have no correlation ids.
+        "event_by_time" => "?[timestamp, id, op, session_id, directive_id] := *event_log{id, op, timestamp}, session_id = null, directive_id = null\n:put event_by_time { timestamp, id => op, session_id, directive_id }".to_string(),
+        other => unreachable!("nothing to backfill into {}", other),
+    }
+}
+
+/// A column that only the retyped shape of `relation` has.
+pub fn retype_marker(relation: &str) -> &'static str {
+    match relation {
+        "sessions" => "error",
+        "session_messages" => "kind",
+        other => unreachable!("{} is never retyped", other),
+    }
+}
+
+fn retype_rules(relation: &str) -> &'static str {
+    match relation {
+        // Status used to be the `Debug` string, e.g. `Error("boom")`.
+        "sessions" => "?[id, status, error, depth, input, created_at] := *sessions{id, status: old, depth, input, created_at},
+    starts_with(old, 'Error'), status = 'Error', error = replace(replace(old, 'Error(\"', ''), '\")', '')
+?[id, status, error, depth, input, created_at] := *sessions{id, status, depth, input, created_at},
+    !starts_with(status, 'Error'), error = null",
+        // Same prefixes as `MessageKind::infer`; the original times are unknown.
+        "session_messages" => "?[session_id, msg_index, role, kind, content, timestamp, tokens] := *session_messages{session_id, msg_index, content},
+    kind = if(starts_with(content, '**Sly'), 'response',
+        if(starts_with(content, '**Observation (Tool'), 'tool',
+        if(starts_with(content, '**Observation'), 'observation', 'prompt'))),
+    role = if(kind == 'response', 'assistant', if(kind == 'tool', 'tool', 'user')),
+    timestamp = 0,
+    tokens = to_int(ceil(length(content) / 4))",
+        other => unreachable!("{} is never retyped", other),
+    }
+}
+
+/// Column list of the relations that existed before embeddings were added.
+fn legacy_columns(relation: &str) -> &'static str {
+    match relation {
+        "nodes" => "id, content, type, path",
+        "library" => "id, name, version, content, language, chunk_type",
+        other => unreachable!("{} never existed without embeddings", other),
+    }
+}
+
+/// Key and value columns of every relation the store defines.
+pub fn relation_spec(relation: &str, dim: usize) -> String {
+    let vector = |columns: &str| format!("{}, embedding: <F32; {}>", columns, dim);
+    match relation {
+        "cache" => vector("id: String => query: String, response: String"),
+        "nodes" => vector("id: String => content: String, type: String, path: String"),
+        "library" => vector("id: String => name: String, version: String, content: String, language: String, chunk_type: String"),
+        // Chunk rows for content larger than the embedding window.
+        // parent_kind is "node" or "library"; parent_id points into that relation.
+        "chunks" => vector("parent_kind: String, parent_id: String, idx: Int => content: String"),
+        // Previously computed vectors, keyed by (model id, sha256 of text).
+        "embedding_cache" => vector("model: String, hash: String => created_at: Int"),
+        "edges" => "from: String, to: String => rel_type: String".to_string(),
+        "kv_cache" => "hash: String => cache_id: String, created_at: Int".to_string(),
+        "sync_log" => "path: String => last_ingested: Float, content_hash: String".to_string(),
+        "event_log" => "id: String => op: String, data: Json, timestamp: Int, version: Int".to_string(),
+        // Secondary index over event_log keyed by time, for range scans.
+        "event_by_time" => "timestamp: Int, id: String => op: String, session_id: String?, directive_id: String?".to_string(),
+        "skills" => "name: String => code: String, description: String, signature: String".to_string(),
+        // `status` is the `SessionStatus` variant name; `error` its message.
+        "sessions" => "id: String => status: String, error: String?, depth: Int, input: String, created_at: Int".to_string(),
+        // role and kind are the snake_case `Role` and `MessageKind` names.
+        "session_messages" => "session_id: String, msg_index: Int => role: String, kind: String, content: String, timestamp: Int, tokens: Int".to_string(),
+        // Which model produced the stored vectors (single row, key "active")
+        "embedding_meta" => "key: String => model: String, dim: Int".to_string(),
+        // Optional metadata for nodes written through `MemoryStore::store`.
+        "node_meta" => "id: String => kind: String, source_session: String?, tags: [String], confidence: Float, expires_at: Int?, created_at: Int".to_string(),
+        "schema_version" => "version: Int => description: String, applied_at: Int".to_string(),
+        other => unreachable!("unknown relation {}", other),
+    }
+}
+
+pub fn relation_ddl(relation: &str, dim: usize) -> String {
+    format!(":create {} {{ {} }}", relation, relation_spec(relation, dim))
+}
+
+/// (index name, index kind, create script) for each index on `relation`.
+pub fn index_ddl(relation: &str, dim: usize) -> Vec<(String, &'static str, String)> {
+    let hnsw = |rel: &str| {
+        format!(
+            "::hnsw create {}:idx {{ dim: {}, dtype: F32, fields: [embedding], distance: Cosine, m: 50, ef_construction: 200 }}",
+            rel, dim
+        )
+    };
+    match relation {
+        "cache" | "library" | "chunks" => vec![(format!("{}:idx", relation), "hnsw", hnsw(relation))],
+        "nodes" => vec![
+            ("nodes:idx".to_string(), "hnsw", hnsw("nodes")),
+            // Lexical index for the keyword half of hybrid search
+            (
+                "nodes:fts".to_string(),
+                "fts",
+                "::fts create nodes:fts { extractor: content, tokenizer: Simple, filters: [Lowercase, AlphaNumOnly] }".to_string(),
+            ),
+        ],
+        _ => vec![],
+    }
+}
+
+fn index_ddl_by_name(name: &str, dim: usize) -> String {
+    let relation = name.split(':').next().unwrap_or(name);
+    index_ddl(relation, dim)
+        .into_iter()
+        .find(|(n, _, _)| n == name)
+        .map(|(_, _, script)| script)
+        .unwrap_or_else(|| unreachable!("unknown index {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(version: i64, relations: &[&str], indices: &[&str]) -> StoreShape {
+        StoreShape {
+            version,
+            relations: relations.iter().map(|r| r.to_string()).collect(),
+            indices: indices.iter().map(|i| i.to_string()).collect(),
+            columns: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_versions_are_ordered_and_current() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert_eq!(MIGRATIONS.last().map(|m| m.version), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_fresh_store_plans_everything_but_column_rebuilds() {
+        let steps = plan(&StoreShape::default());
+        assert_eq!(steps.len(), MIGRATIONS.len());
+        assert!(!steps[0].changes.iter().any(|c| matches!(c, Change::AddEmbedding(_))));
+        assert!(steps[0].changes.contains(&Change::Relation("nodes")));
+    }
+
+    #[test]
+    fn test_legacy_store_only_gets_missing_pieces() {
+        let mut legacy = shape(0, &["nodes", "library", "cache"], &["cache:idx"]);
+        legacy.columns.insert("nodes".to_string(), vec!["id".to_string(), "content".to_string()]);
+        legacy.columns.insert("library".to_string(), vec!["id".to_string(), "embedding".to_string()]);
+
+        let first = &plan(&legacy)[0];
+        assert!(first.changes.contains(&Change::AddEmbedding("nodes")));
+        assert!(!first.changes.contains(&Change::AddEmbedding("library")));
+        assert!(!first.changes.contains(&Change::Relation("nodes")));
+        assert!(!first.changes.contains(&Change::Index("cache:idx")));
+
+        legacy.relations.insert("sessions".to_string());
+        legacy.columns.insert("sessions".to_string(), vec!["id".to_string(), "status".to_string()]);
+        let last = plan(&legacy).pop().unwrap();
+        assert_eq!(last.changes, vec![Change::Retype("sessions")]);
+    }
+
+    #[test]
+    fn test_current_store_plans_nothing() {
+        assert!(plan(&shape(SCHEMA_VERSION, &[], &[])).is_empty());
+        let steps = plan(&shape(2, &[], &["nodes:fts"]));
+        assert_eq!(steps.iter().map(|s| s.version).collect::<Vec<_>>(), vec![3, 4, 5, 6, 7]);
+        assert!(steps[0].changes.is_empty());
+    }
+}
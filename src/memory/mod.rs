@@ -1,24 +1,134 @@
 pub mod backend_cozo;
+pub mod chunker;
+pub mod embed_cache;
 pub mod engine_candle;
+pub mod events;
+pub mod metadata;
+pub mod migrations;
+pub mod reranker;
+pub mod replay;
+pub mod search;
 pub mod store_graph;
+pub mod store_mem;
+pub mod transfer;
 
-pub use store_graph::{Memory, GraphNode, LibraryEntry};
+pub use store_graph::{Memory, GraphNode, LibraryDoc, LibraryEntry};
+pub use store_mem::InMemoryStore;
+pub use search::{SearchHit, SearchOptions};
+pub use metadata::{MetadataFilter, NodeMetadata};
+pub use events::{Correlation, EventQuery, EventRecord, RetentionReport};
 
 use async_trait::async_trait;
 use anyhow::Result;
 use serde_json::Value;
+use crate::core::session::{AgentSession, SessionMessage};
+use crate::core::state::{EmbeddingConfig, EventsConfig};
+use embed_cache::CacheStatsSnapshot;
+use metadata::StoredMetadata;
 
+/// Code graph plus free-standing memories (lessons, heuristics, facts).
 #[async_trait]
-pub trait MemoryStore: Send + Sync {
+pub trait GraphStore: Send + Sync {
     async fn recall(&self, query: &str, limit: usize) -> Result<Vec<String>>;
+    async fn recall_with(&self, query: &str, limit: usize, filter: &MetadataFilter) -> Result<Vec<String>>;
     async fn recall_facts(&self, query: &str) -> Result<Vec<String>>;
-    async fn search_library(&self, query: &str, limit: usize) -> Result<Vec<String>>;
+    /// Ranked hits with the reason each one matched.
+    async fn search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<SearchHit>>;
+    /// `metadata` is a `NodeMetadata` object; returns the new node id.
     async fn store(&self, content: &str, metadata: Option<Value>) -> Result<String>;
+    /// Removes the node with its edges, chunks and metadata.
     async fn forget(&self, id: &str) -> Result<()>;
+    /// Drops memories whose TTL has passed; returns how many.
+    async fn purge_expired(&self) -> Result<usize>;
     async fn count_nodes(&self) -> Result<usize>;
-    
-    // Skills (WASM)
+    async fn add_nodes(&self, nodes: Vec<GraphNode>) -> Result<()>;
+    /// Nodes at `path` and their direct neighbours.
+    async fn neighborhood(&self, path: &str) -> Result<Vec<String>>;
+    /// Every stored memory with its metadata (graph nodes excluded).
+    async fn stored_memories(&self) -> Result<Vec<(String, String, StoredMetadata)>>;
+    /// Writes a memory under a known id, as event replay does.
+    async fn restore_memory(&self, id: &str, content: &str, meta: &StoredMetadata) -> Result<()>;
+    /// Full content of the node with `id`.
+    async fn get_memory(&self, id: &str) -> Result<Option<String>>;
+    /// `None` for stores that do not embed.
+    fn embedding_cache_stats(&self) -> Option<CacheStatsSnapshot> {
+        None
+    }
+    /// Evicts embedding cache rows past the configured age or row cap
+    /// (`now` in epoch seconds); returns how many.
+    fn prune_embedding_cache(&self, _config: &EmbeddingConfig, _now: i64) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Third-party documentation.
+#[async_trait]
+pub trait LibraryStore: Send + Sync {
+    /// `rerank` overrides the `[rerank]` config default for this query.
+    async fn search_library(&self, query: &str, limit: usize, rerank: Option<bool>) -> Result<Vec<String>>;
+    /// Stores documents, replacing entries with the same id. The store does
+    /// its own embedding and chunking.
+    async fn add_library_docs(&self, docs: Vec<LibraryDoc>) -> Result<()>;
+    /// (name, version) pairs, sorted.
+    async fn known_libraries(&self) -> Result<Vec<(String, String)>>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn create_session(&self, session: &AgentSession) -> Result<()>;
+    async fn update_session(&self, session: &AgentSession) -> Result<()>;
+    async fn get_session(&self, id: &str) -> Result<Option<AgentSession>>;
+    async fn list_sessions(&self) -> Result<Vec<String>>;
+    /// Messages `offset..offset + limit` of a session, in order.
+    async fn session_messages(&self, id: &str, offset: usize, limit: usize) -> Result<Vec<SessionMessage>>;
+}
+
+// Skills (WASM)
+#[async_trait]
+pub trait SkillStore: Send + Sync {
     async fn register_skill(&self, name: &str, code: &str, desc: &str, signature: &str) -> Result<()>;
     async fn get_skill(&self, name: &str) -> Result<Option<String>>;
 }
 
+/// Append-only log of facts about what happened.
+#[async_trait]
+pub trait EventLog: Send + Sync {
+    fn record_correlated(&self, op: &str, data: Value, correlation: &Correlation) -> Result<()>;
+
+    fn record_event(&self, op: &str, data: Value) -> Result<()> {
+        self.record_correlated(op, data, &Correlation::default())
+    }
+
+    async fn query_events(&self, query: &EventQuery) -> Result<Vec<EventRecord>>;
+
+    /// Events strictly after `since` (ms), oldest first.
+    async fn events_since(&self, since: i64, limit: usize) -> Result<Vec<EventRecord>> {
+        self.query_events(&EventQuery { since: Some(since), limit, ..Default::default() }).await
+    }
+
+    /// Deletes events past the retention window and folds old, noisy ops
+    /// into per-day summaries.
+    async fn apply_retention(&self, config: &EventsConfig, now: i64) -> Result<RetentionReport>;
+}
+
+/// Ad-hoc read-only queries in the store's native language.
+pub trait QueryStore: Send + Sync {
+    /// Runs `script` without write access; rows come back as JSON.
+    fn query(&self, script: &str) -> Result<Value>;
+}
+
+/// Content hashes of ingested files, so unchanged files are skipped.
+#[async_trait]
+pub trait SyncLog: Send + Sync {
+    /// (last ingested, content hash) for `path`.
+    async fn check_sync_status(&self, path: &str) -> Result<Option<(i64, String)>>;
+    async fn update_sync_status(&self, path: &str, hash: &str) -> Result<()>;
+    /// Every ingested path, sorted.
+    async fn indexed_paths(&self) -> Result<Vec<String>>;
+}
+
+/// Everything the runtime needs from persistence. Implemented for any
+/// type that provides all the parts.
+pub trait MemoryStore: GraphStore + LibraryStore + SessionStore + SkillStore + EventLog + SyncLog + QueryStore {}
+
+impl<T: GraphStore + LibraryStore + SessionStore + SkillStore + EventLog + SyncLog + QueryStore> MemoryStore for T {}
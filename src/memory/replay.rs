@@ -0,0 +1,353 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::events::EventRecord;
+use super::metadata::StoredMetadata;
+use super::MemoryStore;
+use crate::core::session::{AgentSession, SessionMessage, SessionStatus};
+use crate::safety::overlay::{session_overlay_id, SHARED_OVERLAY_ID};
+
+// Ops that carry state. Everything else in the log is telemetry.
+pub const SESSION_CREATED: &str = "session_created";
+pub const SESSION_UPDATED: &str = "session_updated";
+pub const MEMORY_STORED: &str = "memory_stored";
+pub const FORGET: &str = "forget";
+pub const OVERLAY_WRITE: &str = "overlay_write";
+pub const OVERLAY_COMMIT: &str = "overlay_commit";
+/// From then on the (correlated) session stages into an overlay of its own.
+pub const SESSION_FORKED: &str = "session_forked";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayedMemory {
+    pub content: String,
+    pub meta: StoredMetadata,
+}
+
+/// A file the agent wrote. Pending writes live in the overlay; committed
+/// ones were copied into the workspace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverlayFile {
+    pub content: String,
+    pub committed: bool,
+}
+
+/// Everything that can be re-derived from the event log.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayState {
+    pub sessions: BTreeMap<String, AgentSession>,
+    pub memories: BTreeMap<String, ReplayedMemory>,
+    /// Files by overlay id, then path.
+    pub overlays: BTreeMap<String, BTreeMap<String, OverlayFile>>,
+    /// Sessions with an overlay of their own (forks).
+    pub forks: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub applied: usize,
+    /// Telemetry ops, by name.
+    pub skipped: BTreeMap<String, usize>,
+    /// State ops whose payload could not be applied, as "id: reason".
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Difference {
+    /// In the rebuilt state only.
+    Missing { kind: &'static str, id: String },
+    /// In the live state only.
+    Unexpected { kind: &'static str, id: String },
+    Changed { kind: &'static str, id: String, field: &'static str },
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Missing { kind, id } => write!(f, "- {} {} is missing from the live state", kind, id),
+            Difference::Unexpected { kind, id } => write!(f, "+ {} {} exists live but not in the log", kind, id),
+            Difference::Changed { kind, id, field } => write!(f, "~ {} {} differs in {}", kind, id, field),
+        }
+    }
+}
+
+// --- Pure Functions ---
+
+pub fn session_created_event(session: &AgentSession) -> Value {
+    serde_json::json!({ "session": session })
+}
+
+/// Only the messages past `previous_len` are recorded.
+pub fn session_updated_event(session: &AgentSession, previous_len: usize) -> Value {
+    serde_json::json!({
+        "id": session.id,
+        "status": session.status,
+        "depth": session.depth,
+        "from": previous_len,
+        "appended": session.messages.get(previous_len..).unwrap_or_default()
+    })
+}
+
+pub fn memory_stored_event(id: &str, content: &str, meta: &StoredMetadata) -> Value {
+    serde_json::json!({ "id": id, "content": content, "meta": meta })
+}
+
+impl ReplayState {
+    /// Folds `events` (oldest first) into a fresh state.
+    pub fn rebuild<'a>(events: impl IntoIterator<Item = &'a EventRecord>) -> (Self, ReplayReport) {
+        let mut state = Self::default();
+        let mut report = ReplayReport::default();
+        for event in events {
+            match state.apply(event) {
+                Ok(true) => report.applied += 1,
+                Ok(false) => *report.skipped.entry(event.op.clone()).or_default() += 1,
+                Err(e) => report.errors.push(format!("{}: {}", event.id, e)),
+            }
+        }
+        (state, report)
+    }
+
+    /// Applies one event; `Ok(false)` for ops that carry no state.
+    pub fn apply(&mut self, event: &EventRecord) -> Result<bool> {
+        let data = &event.data;
+        match event.op.as_str() {
+            SESSION_CREATED => {
+                let session: AgentSession = serde_json::from_value(data["session"].clone())?;
+                self.sessions.insert(session.id.clone(), session);
+            }
+            SESSION_UPDATED => {
+                let id = data["id"].as_str().unwrap_or_default();
+                let session = self.sessions.get_mut(id)
+                    .ok_or_else(|| anyhow::anyhow!("update for unknown session {}", id))?;
+                let from = data["from"].as_u64().unwrap_or(0) as usize;
+                let appended: Vec<SessionMessage> = serde_json::from_value(data["appended"].clone())?;
+                session.messages.truncate(from);
+                session.messages.extend(appended);
+                session.status = serde_json::from_value::<SessionStatus>(data["status"].clone())?;
+                session.depth = data["depth"].as_u64().unwrap_or(0) as usize;
+            }
+            MEMORY_STORED => {
+                let id = data["id"].as_str().unwrap_or_default().to_string();
+                self.memories.insert(id, ReplayedMemory {
+                    content: data["content"].as_str().unwrap_or_default().to_string(),
+                    meta: serde_json::from_value(data["meta"].clone())?,
+                });
+            }
+            FORGET => {
+                for id in data["ids"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    self.memories.remove(id);
+                }
+            }
+            SESSION_FORKED => {
+                let id = event.session_id.clone().ok_or_else(|| anyhow::anyhow!("fork without a session"))?;
+                self.forks.insert(id);
+            }
+            OVERLAY_WRITE => {
+                let path = data["path"].as_str().unwrap_or_default().to_string();
+                let content = data["content"].as_str().unwrap_or_default().to_string();
+                let overlay = self.overlay_id(event.session_id.as_deref());
+                self.overlays.entry(overlay).or_default().insert(path, OverlayFile { content, committed: false });
+            }
+            OVERLAY_COMMIT => {
+                let overlay = self.overlay_id(event.session_id.as_deref());
+                for file in self.overlays.entry(overlay).or_default().values_mut() {
+                    file.committed = true;
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The overlay `session` stages into.
+    pub fn overlay_id(&self, session: Option<&str>) -> String {
+        match session {
+            Some(id) if self.forks.contains(id) => session_overlay_id(id),
+            _ => SHARED_OVERLAY_ID.to_string(),
+        }
+    }
+
+    /// Every replayed file as (overlay id, path, file).
+    pub fn overlay_files(&self) -> impl Iterator<Item = (&String, &String, &OverlayFile)> {
+        self.overlays.iter().flat_map(|(overlay, files)| files.iter().map(move |(path, file)| (overlay, path, file)))
+    }
+
+    /// Sessions and memories that differ between `self` (rebuilt) and `live`.
+    pub fn diff(&self, live: &ReplayState) -> Vec<Difference> {
+        let mut out = diff_maps("session", &self.sessions, &live.sessions, |a, b| {
+            if a.messages != b.messages {
+                Some("messages")
+            } else if a.status != b.status {
+                Some("status")
+            } else if a.depth != b.depth {
+                Some("depth")
+            } else {
+                None
+            }
+        });
+        out.extend(diff_maps("memory", &self.memories, &live.memories, |a, b| {
+            if a.content != b.content {
+                Some("content")
+            } else if a.meta != b.meta {
+                Some("metadata")
+            } else {
+                None
+            }
+        }));
+        out
+    }
+
+    /// Files whose on-disk content no longer matches the last replayed
+    /// write. `read(overlay_id, path, committed)` returns the live content,
+    /// if any. Files are reported as `overlay_id/path`.
+    pub fn diff_overlay(&self, read: impl Fn(&str, &str, bool) -> Option<String>) -> Vec<Difference> {
+        self.overlay_files()
+            .filter_map(|(overlay, path, file)| {
+                let id = format!("{}/{}", overlay, path.trim_start_matches('/'));
+                match read(overlay, path, file.committed) {
+                    None => Some(Difference::Missing { kind: "file", id }),
+                    Some(live) if live != file.content => Some(Difference::Changed { kind: "file", id, field: "content" }),
+                    Some(_) => None,
+                }
+            })
+            .collect()
+    }
+}
+
+fn diff_maps<T>(
+    kind: &'static str,
+    rebuilt: &BTreeMap<String, T>,
+    live: &BTreeMap<String, T>,
+    changed: impl Fn(&T, &T) -> Option<&'static str>,
+) -> Vec<Difference> {
+    let mut out = Vec::new();
+    for (id, value) in rebuilt {
+        match live.get(id) {
+            None => out.push(Difference::Missing { kind, id: id.clone() }),
+            Some(other) => {
+                if let Some(field) = changed(value, other) {
+                    out.push(Difference::Changed { kind, id: id.clone(), field });
+                }
+            }
+        }
+    }
+    out.extend(live.keys().filter(|id| !rebuilt.contains_key(*id)).map(|id| Difference::Unexpected { kind, id: id.clone() }));
+    out
+}
+
+// --- IO ---
+
+/// The live counterpart of a rebuilt state (overlay files excluded; they
+/// live on disk, see `diff_overlay`).
+pub async fn snapshot(store: &dyn MemoryStore) -> Result<ReplayState> {
+    let mut state = ReplayState::default();
+    for id in store.list_sessions().await? {
+        if let Some(session) = store.get_session(&id).await? {
+            state.sessions.insert(id, session);
+        }
+    }
+    for (id, content, meta) in store.stored_memories().await? {
+        state.memories.insert(id, ReplayedMemory { content, meta });
+    }
+    Ok(state)
+}
+
+/// Writes sessions and memories into `target`, keeping their ids.
+pub async fn restore(state: &ReplayState, target: &dyn MemoryStore) -> Result<()> {
+    for session in state.sessions.values() {
+        target.create_session(session).await?;
+    }
+    for (id, memory) in &state.memories {
+        target.restore_memory(id, &memory.content, &memory.meta).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{EventQuery, InMemoryStore};
+
+    #[tokio::test]
+    async fn test_rebuilt_state_matches_live_store() {
+        let live: Box<dyn MemoryStore> = Box::new(InMemoryStore::new());
+        let session = AgentSession::new("fix the build".to_string());
+        live.create_session(&session).await.unwrap();
+        let session = session.with_message(SessionMessage::response("thinking".to_string())).with_depth_increment();
+        live.update_session(&session).await.unwrap();
+        let kept = live.store("prefer small commits", Some(serde_json::json!({ "tags": ["git"] }))).await.unwrap();
+        let dropped = live.store("temporary note", None).await.unwrap();
+        live.forget(&dropped).await.unwrap();
+        live.record_event("store_cache", serde_json::json!({})).unwrap();
+
+        let events = live.query_events(&EventQuery { limit: 1000, ..Default::default() }).await.unwrap();
+        let (rebuilt, report) = ReplayState::rebuild(&events);
+        assert_eq!(report.skipped.get("store_cache"), Some(&1));
+        assert!(report.errors.is_empty());
+        assert_eq!(rebuilt.sessions[&session.id].messages.len(), 2);
+        assert!(rebuilt.memories.contains_key(&kept));
+
+        let snapshot_live = snapshot(live.as_ref()).await.unwrap();
+        assert!(rebuilt.diff(&snapshot_live).is_empty());
+
+        let fresh = InMemoryStore::new();
+        restore(&rebuilt, &fresh).await.unwrap();
+        assert!(rebuilt.diff(&snapshot(&fresh).await.unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_and_overlay() {
+        let mut state = ReplayState::default();
+        let write = |path: &str, content: &str| EventRecord {
+            id: path.to_string(),
+            op: OVERLAY_WRITE.to_string(),
+            data: serde_json::json!({ "path": path, "content": content }),
+            timestamp: 0,
+            version: 2,
+            session_id: None,
+            directive_id: None,
+        };
+        state.apply(&write("a.rs", "fn a() {}")).unwrap();
+        state.apply(&write("b.rs", "fn b() {}")).unwrap();
+
+        let diffs = state.diff_overlay(|_, path, _| (path == "a.rs").then(|| "fn a() { }".to_string()));
+        assert_eq!(diffs, vec![
+            Difference::Changed { kind: "file", id: "godmode_session/a.rs".to_string(), field: "content" },
+            Difference::Missing { kind: "file", id: "godmode_session/b.rs".to_string() },
+        ]);
+
+        let mut live = state.clone();
+        live.memories.insert("x".to_string(), ReplayedMemory {
+            content: "extra".to_string(),
+            meta: StoredMetadata::from_input(Default::default(), 0),
+        });
+        assert_eq!(state.diff(&live), vec![Difference::Unexpected { kind: "memory", id: "x".to_string() }]);
+    }
+
+    #[test]
+    fn test_forks_stage_and_commit_in_their_own_overlay() {
+        let event = |op: &str, session: &str, data: Value| EventRecord {
+            id: format!("{}-{}", op, session),
+            op: op.to_string(),
+            data,
+            timestamp: 0,
+            version: 2,
+            session_id: Some(session.to_string()),
+            directive_id: None,
+        };
+        let (state, report) = ReplayState::rebuild(&[
+            event(OVERLAY_WRITE, "main", serde_json::json!({ "path": "a.rs", "content": "main" })),
+            event(SESSION_FORKED, "fork", serde_json::json!({ "from": "main" })),
+            event(OVERLAY_WRITE, "fork", serde_json::json!({ "path": "a.rs", "content": "fork" })),
+            event(OVERLAY_COMMIT, "fork", serde_json::json!({})),
+        ]);
+        assert!(report.errors.is_empty());
+
+        let shared = &state.overlays[SHARED_OVERLAY_ID]["a.rs"];
+        assert_eq!((shared.content.as_str(), shared.committed), ("main", false));
+        let forked = &state.overlays[&session_overlay_id("fork")]["a.rs"];
+        assert_eq!((forked.content.as_str(), forked.committed), ("fork", true));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Result};
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::{Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config};
+use super::engine_candle::ModelFiles;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams, TruncationStrategy};
+
+/// Cross-encoder re-ranker (BertForSequenceClassification with a single logit).
+/// Unlike the bi-encoder in `EmbeddingEngine`, it reads query and candidate
+/// together, which is slower but far more precise for the final top-N.
+pub struct Reranker {
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+    device: Device,
+    batch_size: usize,
+}
+
+impl Reranker {
+    pub fn new(model_id: &str, batch_size: usize, offline: bool) -> Result<Self> {
+        // Small cross-encoders run fine on CPU and leave Metal to the embedder.
+        let device = Device::Cpu;
+        println!("🎯 Initializing Re-ranker ({}) on device: {:?}", model_id, device);
+
+        let files = ModelFiles::resolve(model_id, offline)?;
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(files.config)?)?;
+        let mut tokenizer = Tokenizer::from_file(files.tokenizer).map_err(|e| anyhow!(e))?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        // Long candidates lose their tail, never the query.
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                strategy: TruncationStrategy::OnlySecond,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!(e))?;
+
+        let tensors = candle_core::safetensors::load(&files.weights, &device)?;
+        let vb = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        Self::load(vb, &config, tokenizer, device, batch_size)
+    }
+
+    fn load(vb: VarBuilder, config: &Config, tokenizer: Tokenizer, device: Device, batch_size: usize) -> Result<Self> {
+        let model = BertModel::load(vb.clone(), config)?;
+        // Classification head: logit = classifier(tanh(pooler(CLS)))
+        let pooler = candle_nn::linear(config.hidden_size, config.hidden_size, vb.pp("bert.pooler.dense"))?;
+        let classifier = candle_nn::linear(config.hidden_size, 1, vb.pp("classifier"))?;
+
+        Ok(Self {
+            model,
+            pooler,
+            classifier,
+            tokenizer,
+            device,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// Relevance logit for each document against `query` (higher is better).
+    pub fn score(&self, query: &str, documents: &[String]) -> Result<Vec<f32>> {
+        let mut scores = Vec::with_capacity(documents.len());
+
+        for batch in documents.chunks(self.batch_size) {
+            let pairs: Vec<(String, String)> = batch.iter().map(|d| (query.to_string(), d.clone())).collect();
+            let encodings = self.tokenizer.encode_batch(pairs, true).map_err(|e| anyhow!(e))?;
+
+            let seq_len = encodings[0].get_ids().len();
+            let shape = (batch.len(), seq_len);
+            let ids: Vec<u32> = encodings.iter().flat_map(|e| e.get_ids().to_vec()).collect();
+            let type_ids: Vec<u32> = encodings.iter().flat_map(|e| e.get_type_ids().to_vec()).collect();
+            let mask: Vec<u32> = encodings.iter().flat_map(|e| e.get_attention_mask().to_vec()).collect();
+
+            let ids = Tensor::from_vec(ids, shape, &self.device)?;
+            let type_ids = Tensor::from_vec(type_ids, shape, &self.device)?;
+            let mask = Tensor::from_vec(mask, shape, &self.device)?;
+
+            let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let cls = hidden.i((.., 0, ..))?;
+            let pooled = self.pooler.forward(&cls)?.tanh()?;
+            let logits = self.classifier.forward(&pooled)?;
+
+            scores.extend(logits.flatten_all()?.to_vec1::<f32>()?);
+        }
+
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::engine_candle::{tiny_config, tiny_tokenizer};
+
+    #[test]
+    fn test_batched_scores_follow_document_order() {
+        // Same random weights behind two batch sizes
+        let varmap = candle_nn::VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let batched = Reranker::load(vb.clone(), &tiny_config(8).unwrap(), tiny_tokenizer().unwrap(), Device::Cpu, 2).unwrap();
+        let single = Reranker::load(vb, &tiny_config(8).unwrap(), tiny_tokenizer().unwrap(), Device::Cpu, 1).unwrap();
+
+        let documents: Vec<String> = ["fn struct", "docs", "cache docs fn struct", "fn struct"].iter().map(|d| d.to_string()).collect();
+        let scores = batched.score("fn cache", &documents).unwrap();
+        assert_eq!(scores.len(), documents.len());
+        for (a, b) in scores.iter().zip(single.score("fn cache", &documents).unwrap()) {
+            assert!((a - b).abs() < 1e-4, "padding a batch must not change a score: {} vs {}", a, b);
+        }
+        assert!((scores[0] - scores[3]).abs() < 1e-4, "equal documents score alike wherever they sit");
+    }
+}
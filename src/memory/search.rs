@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::metadata::MetadataFilter;
+
+/// Smoothing constant from the original RRF paper; keeps a single first-place
+/// ranking from dominating the fused list.
+pub const RRF_K: f64 = 60.0;
+
+/// Why a hit made it into the result set. A node can carry several reasons
+/// (e.g. matched both lexically and semantically, then reached again via an edge).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum HitReason {
+    Vector { rank: usize, distance: f64 },
+    Keyword { rank: usize, score: f64 },
+    Graph { via: String, hops: usize },
+    Rerank { score: f64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub path: String,
+    pub node_type: String,
+    pub content: String,
+    pub score: f64,
+    pub reasons: Vec<HitReason>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub limit: usize,
+    /// How many results each retriever contributes before fusion.
+    pub candidates: usize,
+    /// Edge hops to expand from the fused seeds (0 disables expansion).
+    pub hops: usize,
+    /// Score multiplier applied per hop away from a seed.
+    pub hop_decay: f64,
+    /// Cross-encoder re-ranking for this query; `None` follows the
+    /// `[rerank]` config default.
+    pub rerank: Option<bool>,
+    /// Metadata constraints; the default only hides expired memories.
+    pub filter: MetadataFilter,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 10,
+            candidates: 30,
+            hops: 1,
+            hop_decay: 0.5,
+            rerank: None,
+            filter: MetadataFilter::default(),
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn with_limit(limit: usize) -> Self {
+        Self { limit, candidates: (limit * 3).max(10), ..Self::default() }
+    }
+}
+
+// --- Pure Functions ---
+
+/// Accumulates scores from independent rankings (RRF) and graph neighbours.
+#[derive(Debug, Default)]
+pub struct Fusion {
+    hits: HashMap<String, (f64, Vec<HitReason>)>,
+}
+
+impl Fusion {
+    /// Adds one retriever's ranking. `ranking` is ordered best-first and
+    /// carries the retriever's raw score, which is only kept for the reason.
+    pub fn add_ranking(&mut self, ranking: &[(String, f64)], reason: impl Fn(usize, f64) -> HitReason) {
+        for (rank, (id, raw)) in ranking.iter().enumerate() {
+            let entry = self.hits.entry(id.clone()).or_insert((0.0, Vec::new()));
+            entry.0 += 1.0 / (RRF_K + rank as f64 + 1.0);
+            entry.1.push(reason(rank + 1, *raw));
+        }
+    }
+
+    /// Credits `id` with a decayed share of the seed's fused score.
+    pub fn add_neighbor(&mut self, id: &str, via: &str, hops: usize, seed_score: f64, decay: f64) {
+        if id == via {
+            return;
+        }
+        let entry = self.hits.entry(id.to_string()).or_insert((0.0, Vec::new()));
+        entry.0 += seed_score * decay.powi(hops as i32);
+        entry.1.push(HitReason::Graph { via: via.to_string(), hops });
+    }
+
+    pub fn score(&self, id: &str) -> Option<f64> {
+        self.hits.get(id).map(|(s, _)| *s)
+    }
+
+    /// Best-first (id, score, reasons); ties broken by id for stable output.
+    pub fn ranked(&self) -> Vec<(String, f64, Vec<HitReason>)> {
+        let mut out: Vec<_> = self.hits.iter()
+            .map(|(id, (score, reasons))| (id.clone(), *score, reasons.clone()))
+            .collect();
+        out.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+        out
+    }
+}
+
+/// Re-orders hits by cross-encoder score and keeps the best `limit`.
+/// The re-ranker's logit replaces the fused score; the fused reasons stay.
+pub fn apply_rerank(hits: Vec<SearchHit>, scores: &[f32], limit: usize) -> Vec<SearchHit> {
+    let mut scored: Vec<SearchHit> = hits.into_iter()
+        .zip(scores)
+        .map(|(mut hit, score)| {
+            hit.score = *score as f64;
+            hit.reasons.push(HitReason::Rerank { score: *score as f64 });
+            hit
+        })
+        .collect();
+    scored.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(limit);
+    scored
+}
+
+/// Turns free text into a Cozo FTS query: bare terms OR-ed together, with
+/// the query-language operators and punctuation stripped so user input
+/// cannot produce a parse error.
+pub fn fts_query(text: &str) -> Option<String> {
+    const MAX_TERMS: usize = 16;
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|t| t.len() > 1)
+        .filter(|t| !matches!(t.to_ascii_uppercase().as_str(), "AND" | "OR" | "NOT" | "NEAR"))
+        .map(|t| t.to_lowercase())
+        .take(MAX_TERMS)
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranking(ids: &[&str]) -> Vec<(String, f64)> {
+        ids.iter().map(|id| (id.to_string(), 0.0)).collect()
+    }
+
+    #[test]
+    fn test_rrf_rewards_agreement() {
+        let mut fusion = Fusion::default();
+        fusion.add_ranking(&ranking(&["a", "b", "c"]), |rank, distance| HitReason::Vector { rank, distance });
+        fusion.add_ranking(&ranking(&["c", "b"]), |rank, score| HitReason::Keyword { rank, score });
+
+        let ranked = fusion.ranked();
+        // "b" and "c" appear in both lists and outrank the vector-only "a".
+        assert_eq!(ranked[2].0, "a");
+        assert_eq!(ranked[0].2.len(), 2);
+    }
+
+    #[test]
+    fn test_neighbors_are_decayed_and_explained() {
+        let mut fusion = Fusion::default();
+        fusion.add_ranking(&ranking(&["seed"]), |rank, distance| HitReason::Vector { rank, distance });
+        let seed = fusion.score("seed").unwrap();
+        fusion.add_neighbor("callee", "seed", 1, seed, 0.5);
+
+        let ranked = fusion.ranked();
+        assert_eq!(ranked[1].0, "callee");
+        assert!((ranked[1].1 - seed * 0.5).abs() < 1e-12);
+        assert_eq!(ranked[1].2, vec![HitReason::Graph { via: "seed".to_string(), hops: 1 }]);
+    }
+
+    #[test]
+    fn test_rerank_reorders_and_truncates() {
+        let hit = |id: &str| SearchHit {
+            id: id.to_string(),
+            path: String::new(),
+            node_type: "fn".to_string(),
+            content: String::new(),
+            score: 0.0,
+            reasons: vec![],
+        };
+        let reranked = apply_rerank(vec![hit("a"), hit("b"), hit("c")], &[0.1, 2.5, -1.0], 2);
+        assert_eq!(reranked.iter().map(|h| h.id.as_str()).collect::<Vec<_>>(), vec!["b", "a"]);
+        assert_eq!(reranked[0].reasons, vec![HitReason::Rerank { score: 2.5 }]);
+    }
+
+    #[test]
+    fn test_fts_query_strips_operators() {
+        assert_eq!(fts_query("Who calls Auth::login AND?").as_deref(), Some("who OR calls OR auth OR login"));
+        assert_eq!(fts_query("?!"), None);
+    }
+}
@@ -1,12 +1,22 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use cozo::{DataValue, ScriptMutability};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 
-use super::backend_cozo::{CozoBackend, vec_to_datavalue};
+use super::backend_cozo::{detect_engine, CozoBackend, datavalue_to_vec, vec_to_datavalue};
+use super::embed_cache::{self, CacheStats, CacheStatsSnapshot};
+use super::metadata::{MetadataFilter, NodeMetadata, StoredMetadata};
+use super::chunker::{self, Chunk, ChunkKind, ChunkedEmbedding, ChunkerConfig, EmbeddedChunk};
 use super::engine_candle::EmbeddingEngine;
-use super::MemoryStore;
+use crate::core::state::{EmbeddingConfig, EventsConfig, RerankConfig, StorageEngine};
+use super::reranker::Reranker;
+use super::search::{self, Fusion, HitReason, SearchHit, SearchOptions};
+use super::events::{self, Correlation, EventQuery, EventRecord, RetentionReport};
+use super::replay;
+use super::{EventLog, GraphStore, LibraryStore, QueryStore, SessionStore, SkillStore, SyncLog};
+use crate::core::session::{AgentSession, MessageKind, Role, SessionMessage, SessionStatus};
 use async_trait::async_trait;
 use serde_json::Value;
 
@@ -21,34 +31,421 @@ pub struct GraphNode {
 
 pub type LibraryEntry = (String, String, String, String, String, String, Vec<f32>);
 
+/// A documentation entry before embedding. `chunk_type` is "documentation"
+/// for fetched docs and "metadata" for name-only placeholders.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryDoc {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub content: String,
+    pub chunk_type: String,
+}
+
 pub struct Memory {
     backend: CozoBackend,
     engine: Option<EmbeddingEngine>,
+    reranker: Option<Arc<Reranker>>,
+    rerank_candidates: usize,
+    offline: bool,
+    /// Opened read-only: fresh embeddings are not written to the cache.
+    read_only: bool,
+    cache_stats: CacheStats,
 }
 
 impl Memory {
-    pub async fn new(path: &str, read_only: bool) -> Result<Self> {
-        let backend = CozoBackend::new(path, read_only)?;
-        let engine = Some(EmbeddingEngine::new()?);
+    pub async fn new(path: &str, read_only: bool, embedding: &EmbeddingConfig) -> Result<Self> {
+        Self::open(path, read_only, detect_engine(path), embedding).await
+    }
+
+    /// Like `new`, with an explicit Cozo engine (`Memory` means Cozo's
+    /// in-process `mem` engine here).
+    pub async fn open(path: &str, read_only: bool, engine_kind: StorageEngine, embedding: &EmbeddingConfig) -> Result<Self> {
+        let backend = CozoBackend::open(path, read_only, engine_kind)?;
+        let engine = Some(EmbeddingEngine::new(embedding)?);
 
-        Ok(Self { backend, engine })
+        let mut memory = Self { backend, engine, reranker: None, rerank_candidates: 0, offline: embedding.offline, read_only, cache_stats: CacheStats::default() };
+        if !read_only {
+            let backup = (engine_kind != StorageEngine::Memory).then(|| reembed_backup_path(path, chrono::Utc::now().timestamp()));
+            memory.sync_embedding_model(backup.as_deref()).await?;
+        }
+        Ok(memory)
     }
 
     pub async fn new_light(path: &str, read_only: bool) -> Result<Self> {
         let backend = CozoBackend::new(path, read_only)?;
-        Ok(Self { backend, engine: None })
+        Ok(Self { backend, engine: None, reranker: None, rerank_candidates: 0, offline: false, read_only, cache_stats: CacheStats::default() })
+    }
+
+    /// Loads the cross-encoder; from then on queries are re-ranked by default.
+    pub fn enable_reranker(&mut self, config: &RerankConfig) -> Result<()> {
+        self.reranker = Some(Arc::new(Reranker::new(&config.model, config.batch_size, self.offline)?));
+        self.rerank_candidates = config.candidates;
+        Ok(())
+    }
+
+    /// Width of the vectors this store holds.
+    pub fn embedding_dim(&self) -> usize {
+        self.backend.dim
+    }
+
+    /// Re-embeds the whole store when the configured model is not the one
+    /// that produced the stored vectors. Stores without a record predate
+    /// configurable models and were embedded with the default one. A store
+    /// on disk is backed up to `backup` first; without a backup nothing is
+    /// re-embedded.
+    async fn sync_embedding_model(&mut self, backup: Option<&std::path::Path>) -> Result<()> {
+        let Some(engine) = &self.engine else { return Ok(()) };
+        let (model, dim) = (engine.model_id().to_string(), engine.dim());
+
+        let recorded = self.backend.embedding_meta()?;
+        let (stored_model, stored_dim) = recorded.clone()
+            .unwrap_or_else(|| (EmbeddingConfig::default().model, self.backend.dim));
+        if stored_model == model && stored_dim == dim {
+            if recorded.is_none() {
+                self.backend.set_embedding_meta(&model, dim)?;
+            }
+            return Ok(());
+        }
+
+        eprintln!("🔄 Embedding model changed ({} [{}] → {} [{}]). Re-embedding store...", stored_model, stored_dim, model, dim);
+        if let Some(backup) = backup {
+            self.backend.db.backup_db(backup)
+                .map_err(|e| anyhow!("Could not back up the store to {} before re-embedding, so it was left as is: {}", backup.display(), e))?;
+            eprintln!("   Backup written to {}", backup.display());
+        }
+
+        let counts = self.reembed_store(dim).await?;
+        self.backend.set_embedding_meta(&model, dim)?;
+        // Vectors from the old model can never be hit again.
+        let mut params = BTreeMap::new();
+        params.insert("model".to_string(), DataValue::from(model.as_str()));
+        self.remove_cached_embeddings("?[model, hash] := *embedding_cache{model, hash}, model != $model", params)?;
+        self.backend.record_event("reembed_store", serde_json::json!({
+            "from": { "model": stored_model, "dim": stored_dim },
+            "to": { "model": model, "dim": dim },
+            "nodes": counts.0,
+            "library": counts.1,
+            "cache": counts.2
+        }))?;
+        eprintln!("✅ Re-embedded {} nodes, {} library entries, {} cache entries.", counts.0, counts.1, counts.2);
+        Ok(())
+    }
+
+    /// Snapshots every row that carries a vector and embeds them all again.
+    /// Only once every batch is embedded are the vector relations rebuilt at
+    /// `dim` (when it changed) and the rows written back; chunk rows are
+    /// regenerated from their parents.
+    async fn reembed_store(&mut self, dim: usize) -> Result<(usize, usize, usize)> {
+        const BATCH: usize = 256;
+
+        let nodes = self.snapshot("?[id, content, type, path] := *nodes{id, content, type, path}")?;
+        let library = self.snapshot(
+            "?[id, name, version, content, language, chunk_type] := *library{id, name, version, content, language, chunk_type}",
+        )?;
+        let cache = self.snapshot("?[id, query, response] := *cache{id, query, response}")?;
+
+        let resize = dim != self.backend.dim;
+        if resize {
+            // Derived data: rebuilt first so the new vectors can be cached.
+            self.backend.recreate_vector_relations(&["embedding_cache"], dim)?;
+        }
+
+        let mut node_batches = Vec::new();
+        for batch in nodes.chunks(BATCH) {
+            let items: Vec<(String, ChunkKind)> = batch.iter()
+                .map(|r| (r[1].clone(), ChunkKind::for_node_type(&r[2])))
+                .collect();
+            let embedded = self.batch_embed_chunked(&items)?;
+
+            let mut rows = Vec::new();
+            let mut chunk_sets = Vec::new();
+            for (r, emb) in batch.iter().zip(embedded) {
+                rows.push(string_row(r, emb.embedding));
+                chunk_sets.push((r[0].clone(), emb.chunks));
+            }
+            node_batches.push((rows, chunk_sets));
+        }
+
+        let mut library_batches = Vec::new();
+        for batch in library.chunks(BATCH) {
+            // Metadata-only entries keep the zero vector they were registered with.
+            let documented: Vec<&Vec<String>> = batch.iter().filter(|r| r[5] != "metadata" && !r[3].is_empty()).collect();
+            let items: Vec<(String, ChunkKind)> = documented.iter().map(|r| (r[3].clone(), ChunkKind::Markdown)).collect();
+            let mut embedded: HashMap<&str, ChunkedEmbedding> = documented.iter()
+                .map(|r| r[0].as_str())
+                .zip(self.batch_embed_chunked(&items)?)
+                .collect();
+
+            let mut rows = Vec::new();
+            let mut chunk_sets = Vec::new();
+            for r in batch {
+                match embedded.remove(r[0].as_str()) {
+                    Some(emb) => {
+                        rows.push(string_row(r, emb.embedding));
+                        chunk_sets.push((r[0].clone(), emb.chunks));
+                    }
+                    None => rows.push(string_row(r, vec![0.0; dim])),
+                }
+            }
+            library_batches.push((rows, chunk_sets));
+        }
+
+        let mut cache_batches = Vec::new();
+        for batch in cache.chunks(BATCH) {
+            let queries: Vec<String> = batch.iter().map(|r| r[1].clone()).collect();
+            let vectors = self.batch_embed(&queries)?;
+            cache_batches.push(batch.iter().zip(vectors).map(|(r, v)| string_row(r, v)).collect::<Vec<_>>());
+        }
+
+        if resize {
+            self.backend.recreate_vector_relations(&["cache", "nodes", "library", "chunks"], dim)?;
+        }
+        for (rows, chunk_sets) in node_batches {
+            self.put_rows("?[id, content, type, path, embedding] <- $rows :put nodes { id => content, type, path, embedding }", rows)?;
+            self.replace_chunks("node", chunk_sets).await?;
+        }
+        for (rows, chunk_sets) in library_batches {
+            self.put_rows(
+                "?[id, name, version, content, language, chunk_type, embedding] <- $rows :put library { id => name, version, content, language, chunk_type, embedding }",
+                rows,
+            )?;
+            self.replace_chunks("library", chunk_sets).await?;
+        }
+        for rows in cache_batches {
+            self.put_rows("?[id, query, response, embedding] <- $rows :put cache { id => query, response, embedding }", rows)?;
+        }
+
+        Ok((nodes.len(), library.len(), cache.len()))
+    }
+
+    /// Runs a query whose columns are all strings.
+    fn snapshot(&self, script: &str) -> Result<Vec<Vec<String>>> {
+        let result = self.backend.run_script(script, Default::default(), ScriptMutability::Immutable)?;
+        Ok(result.rows.into_iter()
+            .map(|row| row.iter().map(|v| v.get_str().unwrap_or_default().to_string()).collect())
+            .collect())
+    }
+
+    fn put_rows(&self, script: &str, rows: Vec<DataValue>) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut params = BTreeMap::new();
+        params.insert("rows".to_string(), DataValue::List(rows));
+        self.backend.run_script(script, params, ScriptMutability::Mutable)?;
+        Ok(())
+    }
+
+    /// Resolves the per-query switch against what is actually loaded.
+    fn should_rerank(&self, requested: Option<bool>) -> bool {
+        match (requested, &self.reranker) {
+            (Some(true), None) => {
+                eprintln!("⚠️ Re-ranking requested but no re-ranker is loaded ([rerank] enabled = false)");
+                false
+            }
+            (requested, reranker) => requested.unwrap_or(true) && reranker.is_some(),
+        }
     }
 
     pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        self.engine.as_ref()
-            .ok_or_else(|| anyhow!("Embedding engine not initialized"))?
-            .embed(text)
+        self.batch_embed(&[text.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding returned no vector"))
     }
 
+    /// Embeds through the persistent cache: only texts never seen by the
+    /// current model reach the engine.
     pub fn batch_embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
-        self.engine.as_ref()
-            .ok_or_else(|| anyhow!("Embedding engine not initialized"))?
-            .batch_embed(texts)
+        let engine = self.engine.as_ref()
+            .ok_or_else(|| anyhow!("Embedding engine not initialized"))?;
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let hashes: Vec<String> = texts.iter().map(|t| embed_cache::content_hash(t)).collect();
+        let mut vectors = self.cached_embeddings(engine.model_id(), &hashes).unwrap_or_else(|e| {
+            eprintln!("⚠️ Embedding cache lookup failed: {}", e);
+            HashMap::new()
+        });
+
+        let mut missing: Vec<(String, String)> = Vec::new();
+        for (hash, text) in hashes.iter().zip(texts) {
+            if !vectors.contains_key(hash) && !missing.iter().any(|(h, _)| h == hash) {
+                missing.push((hash.clone(), text.clone()));
+            }
+        }
+        let misses = hashes.iter().filter(|h| !vectors.contains_key(*h)).count();
+        self.cache_stats.record(texts.len() - misses, misses);
+
+        if !missing.is_empty() {
+            let fresh = engine.batch_embed(&missing.iter().map(|(_, t)| t.clone()).collect::<Vec<_>>())?;
+            let fresh: Vec<(String, Vec<f32>)> = missing.into_iter().map(|(h, _)| h).zip(fresh).collect();
+            if !self.read_only {
+                if let Err(e) = self.store_embeddings(engine.model_id(), &fresh) {
+                    eprintln!("⚠️ Embedding cache write failed: {}", e);
+                }
+            }
+            vectors.extend(fresh);
+        }
+
+        hashes.iter()
+            .map(|h| vectors.get(h).cloned().ok_or_else(|| anyhow!("Missing embedding for {}", h)))
+            .collect()
+    }
+
+    /// Drops cached vectors older than `cache_max_age_days`, then the oldest
+    /// beyond `cache_max_rows`. `now` is in seconds, like `created_at`.
+    pub fn prune_embedding_cache(&self, config: &EmbeddingConfig, now: i64) -> Result<usize> {
+        let mut removed = 0;
+        if let Some(days) = config.cache_max_age_days {
+            let mut params = BTreeMap::new();
+            params.insert("cutoff".to_string(), DataValue::from(now - days as i64 * 24 * 60 * 60));
+            removed += self.remove_cached_embeddings(
+                "?[model, hash] := *embedding_cache{model, hash, created_at}, created_at < $cutoff",
+                params,
+            )?;
+        }
+        if let Some(max_rows) = config.cache_max_rows {
+            let script = format!(
+                "?[model, hash, created_at] := *embedding_cache{{model, hash, created_at}} :sort -created_at :offset {}",
+                max_rows
+            );
+            removed += self.remove_cached_embeddings(&script, BTreeMap::new())?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes the `embedding_cache` rows whose (model, hash) lead the rows
+    /// `select` returns; returns how many.
+    fn remove_cached_embeddings(&self, select: &str, params: BTreeMap<String, DataValue>) -> Result<usize> {
+        let keys: Vec<DataValue> = self.backend.run_script(select, params, ScriptMutability::Immutable)?.rows
+            .into_iter()
+            .map(|row| DataValue::List(row.into_iter().take(2).collect()))
+            .collect();
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let count = keys.len();
+        let mut params = BTreeMap::new();
+        params.insert("keys".to_string(), DataValue::List(keys));
+        self.backend.run_script("?[model, hash] <- $keys :rm embedding_cache { model, hash }", params, ScriptMutability::Mutable)?;
+        Ok(count)
+    }
+
+    /// Embedding cache hits and misses since this process started.
+    pub fn embedding_cache_stats(&self) -> CacheStatsSnapshot {
+        self.cache_stats.snapshot()
+    }
+
+    fn cached_embeddings(&self, model: &str, hashes: &[String]) -> Result<HashMap<String, Vec<f32>>> {
+        let script = "?[hash, embedding] := *embedding_cache{model: $model, hash, embedding}, is_in(hash, $hashes)";
+        let mut params = BTreeMap::new();
+        params.insert("model".to_string(), DataValue::from(model));
+        params.insert("hashes".to_string(), DataValue::List(hashes.iter().map(|h| DataValue::from(h.clone())).collect()));
+
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        Ok(result.rows.iter()
+            .filter_map(|row| Some((row.first()?.get_str()?.to_string(), datavalue_to_vec(row.get(1)?)?)))
+            .collect())
+    }
+
+    fn store_embeddings(&self, model: &str, vectors: &[(String, Vec<f32>)]) -> Result<()> {
+        let script = "
+            ?[model, hash, created_at, embedding] <- $rows
+            :put embedding_cache { model, hash => created_at, embedding }
+        ";
+        let now = chrono::Utc::now().timestamp();
+        let rows = vectors.iter()
+            .map(|(hash, v)| DataValue::List(vec![
+                DataValue::from(model),
+                DataValue::from(hash.clone()),
+                DataValue::from(now),
+                vec_to_datavalue(v.clone()),
+            ]))
+            .collect();
+        let mut params = BTreeMap::new();
+        params.insert("rows".to_string(), DataValue::List(rows));
+        self.backend.run_script(script, params, ScriptMutability::Mutable)?;
+        Ok(())
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        match &self.engine {
+            Some(engine) => engine.count_tokens(text),
+            None => chunker::estimate_tokens(text),
+        }
+    }
+
+    pub fn chunk(&self, content: &str, kind: ChunkKind) -> Vec<Chunk> {
+        chunker::chunk(content, kind, &ChunkerConfig::default(), &|t| self.count_tokens(t))
+    }
+
+    pub fn embed_chunked(&self, content: &str, kind: ChunkKind) -> Result<ChunkedEmbedding> {
+        self.batch_embed_chunked(&[(content.to_string(), kind)])?
+            .pop()
+            .ok_or_else(|| anyhow!("Chunked embedding returned no result"))
+    }
+
+    /// Embeds every chunk of every input in shared batches. Single-chunk inputs
+    /// get their vector directly; multi-chunk inputs get the mean-pooled vector
+    /// as the parent embedding plus one row per chunk.
+    pub fn batch_embed_chunked(&self, items: &[(String, ChunkKind)]) -> Result<Vec<ChunkedEmbedding>> {
+        const EMBED_BATCH: usize = 32;
+
+        let chunked: Vec<Vec<Chunk>> = items.iter().map(|(content, kind)| self.chunk(content, *kind)).collect();
+        let flat: Vec<String> = chunked.iter().flatten().map(|c| c.content.clone()).collect();
+
+        let mut vectors = Vec::with_capacity(flat.len());
+        for batch in flat.chunks(EMBED_BATCH) {
+            vectors.extend(self.batch_embed(batch)?);
+        }
+
+        Ok(assemble_chunked(chunked, vectors, self.embedding_dim()))
+    }
+
+    /// Replaces all chunk rows of the given parents. Parents with an empty
+    /// chunk list simply lose their stale rows.
+    pub async fn replace_chunks(&self, parent_kind: &str, chunk_sets: Vec<(String, Vec<EmbeddedChunk>)>) -> Result<()> {
+        if chunk_sets.is_empty() {
+            return Ok(());
+        }
+
+        let query_script = "
+            {
+                ?[parent_kind, parent_id, idx] := *chunks{parent_kind, parent_id, idx},
+                    parent_kind = $parent_kind, is_in(parent_id, $parents)
+                :rm chunks { parent_kind, parent_id, idx }
+            }
+            {
+                ?[parent_kind, parent_id, idx, content, embedding] <- $rows
+                :put chunks { parent_kind, parent_id, idx => content, embedding }
+            }
+        ";
+
+        let mut parents = Vec::new();
+        let mut rows = Vec::new();
+        for (parent_id, chunks) in chunk_sets {
+            parents.push(DataValue::from(parent_id.clone()));
+            for c in chunks {
+                rows.push(DataValue::List(vec![
+                    DataValue::from(parent_kind),
+                    DataValue::from(parent_id.clone()),
+                    DataValue::from(c.index as i64),
+                    DataValue::from(c.content),
+                    vec_to_datavalue(c.embedding),
+                ]));
+            }
+        }
+
+        let mut params = BTreeMap::new();
+        params.insert("parent_kind".to_string(), DataValue::from(parent_kind));
+        params.insert("parents".to_string(), DataValue::List(parents));
+        params.insert("rows".to_string(), DataValue::List(rows));
+
+        self.backend.run_script(query_script, params, ScriptMutability::Mutable)
+            .map_err(|e| anyhow!("Failed to replace {} chunks: {}", parent_kind, e))?;
+        Ok(())
     }
 
     pub fn record_event(&self, op: &str, data: Value) -> Result<()> {
@@ -119,12 +516,11 @@ impl Memory {
             return Ok(());
         }
 
-        let mut contents = Vec::new();
-        for node in &nodes {
-            contents.push(node.content.clone());
-        }
+        let items: Vec<(String, ChunkKind)> = nodes.iter()
+            .map(|n| (n.content.clone(), ChunkKind::for_node_type(&n.node_type)))
+            .collect();
 
-        let embeddings = self.batch_embed(&contents)?;
+        let embedded = self.batch_embed_chunked(&items)?;
 
         let query_script = "
             ?[id, content, type, path, embedding] <- $nodes
@@ -136,15 +532,17 @@ impl Memory {
 
         let mut node_rows = Vec::new();
         let mut edge_rows = Vec::new();
+        let mut chunk_sets = Vec::new();
 
-        for (i, node) in nodes.iter().enumerate() {
+        for (node, emb) in nodes.iter().zip(embedded) {
             node_rows.push(DataValue::List(vec![
                 DataValue::from(node.id.clone()),
                 DataValue::from(node.content.clone()),
                 DataValue::from(node.node_type.clone()),
                 DataValue::from(node.path.clone()),
-                vec_to_datavalue(embeddings[i].clone()),
+                vec_to_datavalue(emb.embedding),
             ]));
+            chunk_sets.push((node.id.clone(), emb.chunks));
 
             for target in &node.edges {
                 edge_rows.push(DataValue::List(vec![
@@ -161,6 +559,7 @@ impl Memory {
 
         self.backend.run_script(query_script, params, ScriptMutability::Mutable)
             .map_err(|e| anyhow!("Failed to batch add nodes: {}", e))?;
+        self.replace_chunks("node", chunk_sets).await?;
 
         // Record event (once for the whole batch)
         self.backend.record_event("batch_add_nodes", serde_json::json!({
@@ -172,60 +571,329 @@ impl Memory {
     }
 
     pub async fn store_lesson(&self, lesson: &str) -> Result<()> {
-        let id = uuid::Uuid::new_v4().to_string();
-        self.add_node(&GraphNode {
-            id,
-            content: lesson.to_string(),
-            node_type: "lesson".to_string(),
-            path: "global".to_string(),
-            edges: vec![],
-        })
-        .await
+        let meta = NodeMetadata { kind: "lesson".to_string(), ..Default::default() };
+        self.store_with_metadata(lesson, meta).await.map(|_| ())
     }
 
     pub async fn store_heuristic(&self, heuristic: &str) -> Result<()> {
+        self.store_with_metadata(heuristic, NodeMetadata::default()).await.map(|_| ())
+    }
+
+    /// Writes a free-standing memory node plus its `node_meta` row.
+    pub async fn store_with_metadata(&self, content: &str, meta: NodeMetadata) -> Result<String> {
         let id = uuid::Uuid::new_v4().to_string();
-        self.add_node(&GraphNode {
-            id,
-            content: heuristic.to_string(),
-            node_type: "heuristic".to_string(),
-            path: "global".to_string(),
-            edges: vec![],
-        })
-        .await
+        let stored = StoredMetadata::from_input(meta, chrono::Utc::now().timestamp_millis());
+        self.put_memory(&id, content, &stored).await?;
+        Ok(id)
+    }
+
+    /// Writes a memory under `id` and records it for replay. The node and its
+    /// `node_meta` row go in one transaction, so a failure cannot leave a
+    /// memory without its metadata.
+    async fn put_memory(&self, id: &str, content: &str, stored: &StoredMetadata) -> Result<()> {
+        let item = (content.to_string(), ChunkKind::for_node_type(&stored.kind));
+        let embedded = self.batch_embed_chunked(std::slice::from_ref(&item))?
+            .pop()
+            .ok_or_else(|| anyhow!("Missing embedding for memory {}", id))?;
+
+        let script = "
+            {
+                ?[id, content, type, path, embedding] <- [[$id, $content, $kind, 'global', $embedding]]
+                :put nodes { id => content, type, path, embedding }
+            }
+            {
+                ?[id, kind, source_session, tags, confidence, expires_at, created_at] <-
+                    [[$id, $kind, $source_session, $tags, $confidence, $expires_at, $created_at]]
+                :put node_meta { id => kind, source_session, tags, confidence, expires_at, created_at }
+            }
+        ";
+        let mut params = BTreeMap::new();
+        params.insert("id".to_string(), DataValue::from(id.to_string()));
+        params.insert("content".to_string(), DataValue::from(content.to_string()));
+        params.insert("embedding".to_string(), vec_to_datavalue(embedded.embedding));
+        params.insert("kind".to_string(), DataValue::from(stored.kind.clone()));
+        params.insert("source_session".to_string(), stored.source_session.clone().map(DataValue::from).unwrap_or(DataValue::Null));
+        params.insert("tags".to_string(), DataValue::List(stored.tags.iter().cloned().map(DataValue::from).collect()));
+        params.insert("confidence".to_string(), DataValue::from(stored.confidence));
+        params.insert("expires_at".to_string(), stored.expires_at.map(DataValue::from).unwrap_or(DataValue::Null));
+        params.insert("created_at".to_string(), DataValue::from(stored.created_at));
+
+        self.backend.run_script(script, params, ScriptMutability::Mutable)
+            .map_err(|e| anyhow!("Failed to store memory {}: {}", id, e))?;
+        self.replace_chunks("node", vec![(id.to_string(), embedded.chunks)]).await?;
+        self.backend.record_event(replay::MEMORY_STORED, replay::memory_stored_event(id, content, stored))
+    }
+
+    /// Every memory with a `node_meta` row, oldest first.
+    pub async fn stored_memories(&self) -> Result<Vec<(String, String, StoredMetadata)>> {
+        let script = "
+            ?[id, kind, source_session, tags, confidence, expires_at, created_at, content] :=
+                *node_meta{id, kind, source_session, tags, confidence, expires_at, created_at},
+                *nodes{id, content}
+            :sort created_at, id
+        ";
+        let result = self.backend.run_script(script, BTreeMap::new(), ScriptMutability::Immutable)?;
+        Ok(result.rows.iter()
+            .filter_map(|row| {
+                let id = row.first().and_then(|v| v.get_str())?;
+                let content = row.get(7).and_then(|v| v.get_str()).unwrap_or_default();
+                Some((id.to_string(), content.to_string(), metadata_from_row(row)))
+            })
+            .collect())
+    }
+
+    /// Deletes nodes together with their edges (both directions), chunk
+    /// vectors and metadata, atomically.
+    pub async fn forget_nodes(&self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let script = "
+            {
+                ?[id] := *nodes{id}, is_in(id, $ids)
+                :rm nodes { id }
+            }
+            {
+                ?[from, to] := *edges{from, to}, is_in(from, $ids)
+                ?[from, to] := *edges{from, to}, is_in(to, $ids)
+                :rm edges { from, to }
+            }
+            {
+                ?[parent_kind, parent_id, idx] := *chunks{parent_kind, parent_id, idx},
+                    parent_kind = 'node', is_in(parent_id, $ids)
+                :rm chunks { parent_kind, parent_id, idx }
+            }
+            {
+                ?[id] := *node_meta{id}, is_in(id, $ids)
+                :rm node_meta { id }
+            }
+        ";
+        let mut params = BTreeMap::new();
+        params.insert("ids".to_string(), DataValue::List(ids.iter().map(|id| DataValue::from(id.clone())).collect()));
+
+        self.backend.run_script(script, params, ScriptMutability::Mutable)
+            .map_err(|e| anyhow!("Failed to forget nodes: {}", e))?;
+
+        self.backend.record_event(replay::FORGET, serde_json::json!({ "ids": ids }))?;
+        Ok(())
+    }
+
+    /// Forgets every memory whose TTL has run out. Returns how many.
+    pub async fn purge_expired(&self) -> Result<usize> {
+        let script = "?[id] := *node_meta{id, expires_at}, !is_null(expires_at), expires_at <= $now";
+        let mut params = BTreeMap::new();
+        params.insert("now".to_string(), DataValue::from(chrono::Utc::now().timestamp_millis()));
+
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        let ids: Vec<String> = result.rows.iter()
+            .filter_map(|r| r.first().and_then(|v| v.get_str()).map(|s| s.to_string()))
+            .collect();
+        self.forget_nodes(&ids).await?;
+        Ok(ids.len())
+    }
+
+    fn node_metadata(&self, ids: &[String]) -> Result<HashMap<String, StoredMetadata>> {
+        let script = "
+            ?[id, kind, source_session, tags, confidence, expires_at, created_at] :=
+                *node_meta{id, kind, source_session, tags, confidence, expires_at, created_at},
+                is_in(id, $ids)
+        ";
+        let mut params = BTreeMap::new();
+        params.insert("ids".to_string(), DataValue::List(ids.iter().map(|id| DataValue::from(id.clone())).collect()));
+
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        let mut metadata = HashMap::new();
+        for row in result.rows {
+            let Some(id) = row.first().and_then(|v| v.get_str()) else { continue };
+            metadata.insert(id.to_string(), metadata_from_row(&row));
+        }
+        Ok(metadata)
     }
 
     pub async fn find_related(&self, query: &str, limit: usize) -> Result<Vec<String>> {
+        let hits = self.hybrid_search(query, &SearchOptions::with_limit(limit)).await?;
+        Ok(hits.into_iter().map(|h| h.content).collect())
+    }
+
+    /// Hybrid Search: vector similarity and BM25 fused with reciprocal rank
+    /// fusion, then expanded over `edges` from the best seeds.
+    pub async fn hybrid_search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<SearchHit>> {
+        let rerank = self.should_rerank(opts.rerank);
+        // The cross-encoder gets a wider pool than the caller asked for, so
+        // the retrievers have to supply at least that many.
+        let pool = if rerank { opts.limit.max(self.rerank_candidates) } else { opts.limit };
+        let candidates = if rerank { opts.candidates.max(self.rerank_candidates) } else { opts.candidates };
+        let mut fusion = Fusion::default();
+
+        let vector = self.vector_candidates(query, candidates)?;
+        fusion.add_ranking(&vector, |rank, distance| HitReason::Vector { rank, distance });
+
+        // Keyword search is best-effort: stores created before the FTS index
+        // existed, or odd query text, must not take the vector half down with them.
+        match self.keyword_candidates(query, candidates) {
+            Ok(keyword) => fusion.add_ranking(&keyword, |rank, score| HitReason::Keyword { rank, score }),
+            Err(e) => eprintln!("⚠️ Keyword search skipped: {}", e),
+        }
+
+        if opts.hops > 0 {
+            let seeds: Vec<(String, f64)> = fusion.ranked().into_iter()
+                .take(opts.limit)
+                .map(|(id, score, _)| (id, score))
+                .collect();
+            self.expand_neighbors(&mut fusion, &seeds, opts)?;
+        }
+
+        // Edge targets are often bare identifiers with no node behind them,
+        // so over-fetch before resolving and trimming.
+        // A metadata filter can reject most of the list, so resolve all of it.
+        let window = if opts.filter.is_constrained() { usize::MAX } else { pool * 2 };
+        let ranked: Vec<_> = fusion.ranked().into_iter().take(window).collect();
+        let ids: Vec<String> = ranked.iter().map(|(id, _, _)| id.clone()).collect();
+        let mut details = self.node_details(&ids)?;
+        let metadata = self.node_metadata(&ids)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let mut hits = Vec::new();
+        for (id, score, reasons) in ranked {
+            if !opts.filter.matches(metadata.get(&id), now) {
+                continue;
+            }
+            if let Some((path, node_type, content)) = details.remove(&id) {
+                hits.push(SearchHit { id, path, node_type, content, score, reasons });
+            }
+            if hits.len() >= pool {
+                break;
+            }
+        }
+
+        if let (true, Some(reranker)) = (rerank, &self.reranker) {
+            let contents: Vec<String> = hits.iter().map(|h| h.content.clone()).collect();
+            let scores = Self::rerank_scores(reranker, query, contents).await?;
+            return Ok(search::apply_rerank(hits, &scores, opts.limit));
+        }
+        Ok(hits)
+    }
+
+    /// Cross-encoder scores, computed off the async workers: a batch of
+    /// candidates takes long enough to stall every other task on the runtime.
+    async fn rerank_scores(reranker: &Arc<Reranker>, query: &str, documents: Vec<String>) -> Result<Vec<f32>> {
+        let reranker = reranker.clone();
+        let query = query.to_string();
+        tokio::task::spawn_blocking(move || reranker.score(&query, &documents)).await?
+    }
+
+    /// Best distance per node over whole-node vectors and chunk vectors.
+    fn vector_candidates(&self, query: &str, k: usize) -> Result<Vec<(String, f64)>> {
         let embedding = self.embed(query)?;
 
-        // Hybrid Search: Vector similarity + Graph traversal (placeholder logic for now)
         let query_script = format!(
             "
-            ?[content, dist] := ~nodes:idx {{
-                content |
+            hits[parent, dist] := ~nodes:idx{{
+                id: parent |
                 query: $query_vec,
-                k: {},
+                k: {k},
                 bind_distance: dist,
                 ef: 100
             }}
-            :sort dist
+            hits[parent, dist] := ~chunks:idx{{
+                parent_id: parent |
+                query: $query_vec,
+                k: {k},
+                bind_distance: dist,
+                ef: 100,
+                filter: parent_kind == 'node'
+            }}
+            ?[parent, min(dist)] := hits[parent, dist]
         ",
-            limit
+            k = k
         );
 
         let mut params = BTreeMap::new();
         params.insert("query_vec".to_string(), vec_to_datavalue(embedding));
 
         let result = self.backend.run_script(&query_script, params, ScriptMutability::Immutable)?;
+        let mut ranking = scored_rows(result.rows);
+        ranking.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(ranking)
+    }
 
-        let mut results = Vec::new();
-        for row in result.rows {
-            if let Some(DataValue::Str(s)) = row.first() {
-                results.push(s.to_string());
+    fn keyword_candidates(&self, query: &str, k: usize) -> Result<Vec<(String, f64)>> {
+        let Some(fts) = search::fts_query(query) else { return Ok(vec![]) };
+
+        let query_script = format!(
+            "
+            ?[id, score] := ~nodes:fts{{
+                id |
+                query: $q,
+                k: {},
+                bind_score: score
+            }}
+            :sort -score
+        ",
+            k
+        );
+
+        let mut params = BTreeMap::new();
+        params.insert("q".to_string(), DataValue::from(fts));
+
+        let result = self.backend.run_script(&query_script, params, ScriptMutability::Immutable)?;
+        Ok(scored_rows(result.rows))
+    }
+
+    /// Breadth-first walk over `edges` in both directions. Each reached node
+    /// is credited with a decayed share of the seed it was reached from.
+    fn expand_neighbors(&self, fusion: &mut Fusion, seeds: &[(String, f64)], opts: &SearchOptions) -> Result<()> {
+        let script = "
+            ?[src, dst] := *edges{from: src, to: dst}, is_in(src, $ids)
+            ?[src, dst] := *edges{from: dst, to: src}, is_in(src, $ids)
+        ";
+
+        // node -> (originating seed, seed score)
+        let mut origin: HashMap<String, (String, f64)> = seeds.iter()
+            .map(|(id, score)| (id.clone(), (id.clone(), *score)))
+            .collect();
+        let mut frontier: Vec<String> = seeds.iter().map(|(id, _)| id.clone()).collect();
+
+        for hop in 1..=opts.hops {
+            if frontier.is_empty() {
+                break;
+            }
+            let mut params = BTreeMap::new();
+            params.insert("ids".to_string(), DataValue::List(frontier.iter().map(|id| DataValue::from(id.clone())).collect()));
+            let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+
+            let mut next = Vec::new();
+            for row in result.rows {
+                let (Some(DataValue::Str(src)), Some(DataValue::Str(dst))) = (row.first(), row.get(1)) else { continue };
+                let Some((seed, seed_score)) = origin.get(src.as_str()).cloned() else { continue };
+                if origin.contains_key(dst.as_str()) {
+                    continue;
+                }
+                fusion.add_neighbor(dst.as_str(), &seed, hop, seed_score, opts.hop_decay);
+                origin.insert(dst.to_string(), (seed, seed_score));
+                next.push(dst.to_string());
             }
+            frontier = next;
         }
+        Ok(())
+    }
 
-        Ok(results)
+    fn node_details(&self, ids: &[String]) -> Result<HashMap<String, (String, String, String)>> {
+        let script = "?[id, path, type, content] := *nodes{id, path, type, content}, is_in(id, $ids)";
+        let mut params = BTreeMap::new();
+        params.insert("ids".to_string(), DataValue::List(ids.iter().map(|id| DataValue::from(id.clone())).collect()));
+
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        let mut details = HashMap::new();
+        for row in result.rows {
+            if let (Some(DataValue::Str(id)), Some(DataValue::Str(path)), Some(DataValue::Str(t)), Some(DataValue::Str(c))) =
+                (row.first(), row.get(1), row.get(2), row.get(3))
+            {
+                details.insert(id.to_string(), (path.to_string(), t.to_string(), c.to_string()));
+            }
+        }
+        Ok(details)
     }
 
     // --- Library / Autodidact Logic ---
@@ -273,27 +941,67 @@ impl Memory {
         Ok(())
     }
 
-    pub async fn search_library(&self, query: &str, limit: usize) -> Result<Vec<String>> {
+    /// Embeds documentation per section (READMEs routinely exceed the model
+    /// window) and writes the entries with their chunk rows. Metadata-only
+    /// entries get a zero vector.
+    pub async fn add_library_docs(&self, docs: Vec<LibraryDoc>) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut chunk_sets = Vec::new();
+        for doc in docs {
+            let embedded = if doc.chunk_type == "documentation" {
+                self.embed_chunked(&doc.content, ChunkKind::Markdown).ok()
+            } else {
+                None
+            };
+            let (embedding, chunks) = match embedded {
+                Some(e) => (e.embedding, e.chunks),
+                None => (vec![0.0; self.embedding_dim()], vec![]),
+            };
+            chunk_sets.push((doc.id.clone(), chunks));
+            entries.push((doc.id, doc.name, doc.version, doc.content, String::new(), doc.chunk_type, embedding));
+        }
+        if !entries.is_empty() {
+            self.batch_add_library_entries(entries).await?;
+            self.replace_chunks("library", chunk_sets).await?;
+        }
+        Ok(())
+    }
+
+    /// `rerank` is the per-query switch; `None` follows the config default.
+    pub async fn search_library_with(&self, query: &str, limit: usize, rerank: Option<bool>) -> Result<Vec<String>> {
+        let rerank = self.should_rerank(rerank);
+        let final_limit = limit;
+        let limit = if rerank { limit.max(self.rerank_candidates) } else { limit };
         let embedding = self.embed(query)?;
 
+        // Long READMEs are matched through their chunks and reported once per entry.
         let query_script = format!(
             "
-            ?[content, type, dist] := ~library:idx {{
-                content, chunk_type: type |
+            hits[id, dist] := ~library:idx{{
+                id |
                 query: $query_vec,
-                k: {},
+                k: {k},
                 bind_distance: dist,
                 ef: 100
             }}
+            hits[id, dist] := ~chunks:idx{{
+                parent_id: id |
+                query: $query_vec,
+                k: {k},
+                bind_distance: dist,
+                ef: 100,
+                filter: parent_kind == 'library'
+            }}
+            best[id, min(dist)] := hits[id, dist]
             // Apply weight: boost definitions
-            ?[content, score] := ?[content, type, dist],
-                weight = if type == \"definition\" {{ 0.8 }} else {{ 1.0 }},
+            ?[content, score] := best[id, dist], *library{{id, content, chunk_type: type}},
+                weight = if(type == \"definition\", 0.8, 1.0),
                 score = dist * weight
             :sort score
-            :limit {}
+            :limit {limit}
         ",
-            limit * 2,
-            limit
+            k = limit * 2,
+            limit = limit
         );
 
         let mut params = BTreeMap::new();
@@ -308,6 +1016,13 @@ impl Memory {
                 results.push(s.to_string());
             }
         }
+
+        if let (true, Some(reranker)) = (rerank, &self.reranker) {
+            let scores = Self::rerank_scores(reranker, query, results.clone()).await?;
+            let mut scored: Vec<(String, f32)> = results.into_iter().zip(scores).collect();
+            scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            results = scored.into_iter().take(final_limit).map(|(content, _)| content).collect();
+        }
         Ok(results)
     }
 
@@ -353,7 +1068,7 @@ impl Memory {
         params.insert("id".to_string(), DataValue::from(format!("{}_{}", name, version)));
         params.insert("name".to_string(), DataValue::from(name.to_string()));
         params.insert("version".to_string(), DataValue::from(version.to_string()));
-        params.insert("empty_vec".to_string(), vec_to_datavalue(vec![0.0; 384]));
+        params.insert("empty_vec".to_string(), vec_to_datavalue(vec![0.0; self.embedding_dim()]));
         
         self.backend.run_script(script, params, ScriptMutability::Mutable)
             .map_err(|e| anyhow!("Failed to register library {}: {}", name, e))?;
@@ -452,6 +1167,64 @@ impl Memory {
         Ok(())
     }
 
+    pub async fn indexed_paths(&self) -> Result<Vec<String>> {
+        let script = "?[path] := *sync_log{path} :sort path";
+        let result = self.backend.run_script(script, BTreeMap::new(), ScriptMutability::Immutable)?;
+        Ok(result.rows.iter()
+            .filter_map(|r| r.first().and_then(|v| v.get_str()).map(|s| s.to_string()))
+            .collect())
+    }
+
+    pub async fn query_events(&self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        self.backend.query_events(query)
+    }
+
+    /// Expires, then compacts, in batches so a long-neglected log does not
+    /// have to fit in one transaction.
+    pub async fn apply_retention(&self, config: &EventsConfig, now: i64) -> Result<RetentionReport> {
+        const BATCH: usize = 10_000;
+        let mut report = RetentionReport::default();
+
+        if let Some(days) = config.retention_days {
+            let mut query = EventQuery { until: Some(now - days as i64 * events::DAY_MS), limit: BATCH, ..Default::default() };
+            loop {
+                let batch = self.backend.query_events(&query)?;
+                let expired: Vec<EventRecord> = batch.iter().filter(|e| events::expires(e)).cloned().collect();
+                self.backend.replace_events(&expired, &[])?;
+                report.expired += expired.len();
+                // Retained events stay behind, so page past them; ones that
+                // share the boundary millisecond go on the next run.
+                match batch.last() {
+                    Some(last) if batch.len() == BATCH => query.since = Some(last.timestamp),
+                    _ => break,
+                }
+            }
+        }
+
+        if let (Some(days), false) = (config.compact_after_days, config.compact_ops.is_empty()) {
+            let mut query = EventQuery {
+                ops: config.compact_ops.clone(),
+                until: Some(now - days as i64 * events::DAY_MS),
+                limit: BATCH,
+                ..Default::default()
+            };
+            loop {
+                let batch = self.backend.query_events(&query)?;
+                for compaction in events::plan_compaction(&batch) {
+                    self.backend.replace_events(&compaction.replaces, std::slice::from_ref(&compaction.summary))?;
+                    report.compacted += compaction.replaces.len();
+                    report.summaries += 1;
+                }
+                // Groups that straddle a batch boundary merge on the next run.
+                match batch.last() {
+                    Some(last) if batch.len() == BATCH => query.since = Some(last.timestamp),
+                    _ => break,
+                }
+            }
+        }
+        Ok(report)
+    }
+
     pub fn backend_run_script(&self, script: &str) -> Result<cozo::NamedRows> {
         self.backend.run_script(script, BTreeMap::new(), ScriptMutability::Immutable)
     }
@@ -487,135 +1260,168 @@ impl Memory {
 
     // --- Session Persistence (Phase 5) ---
 
-    pub async fn create_session(&self, session: &crate::core::session::AgentSession) -> Result<()> {
-        let script = "
-            ?[id, status, depth, input, created_at] <- [[$id, $status, $depth, $input, $now]]
-            :put sessions { id => status, depth, input, created_at }
-        ";
+    pub async fn create_session(&self, session: &AgentSession) -> Result<()> {
+        self.put_session_row(session, true)?;
+        self.append_session_messages(&session.id, 0, &session.messages)?;
+        self.backend.record_event(replay::SESSION_CREATED, replay::session_created_event(session))
+    }
+
+    /// Stores status and depth, and appends the messages past those
+    /// already stored; earlier messages are never rewritten.
+    pub async fn update_session(&self, session: &AgentSession) -> Result<()> {
+        let previous_len = self.session_message_count(&session.id)?;
+        self.put_session_row(session, false)?;
+        self.append_session_messages(&session.id, previous_len, session.messages.get(previous_len..).unwrap_or_default())?;
+        self.backend.record_event(replay::SESSION_UPDATED, replay::session_updated_event(session, previous_len))
+    }
+
+    /// `input` and `created_at` are set on creation and kept on update.
+    fn put_session_row(&self, session: &AgentSession, create: bool) -> Result<()> {
+        let script = if create {
+            "
+            ?[id, status, error, depth, input, created_at] <- [[$id, $status, $error, $depth, $input, $now]]
+            :put sessions { id => status, error, depth, input, created_at }
+            "
+        } else {
+            "
+            ?[id, status, error, depth, input, created_at] := *sessions{id, input, created_at},
+                id = $id, status = $status, error = $error, depth = $depth
+            :put sessions { id => status, error, depth, input, created_at }
+            "
+        };
         let mut params = BTreeMap::new();
         params.insert("id".to_string(), DataValue::from(session.id.clone()));
-        params.insert("status".to_string(), DataValue::from(format!("{:?}", session.status)));
+        params.insert("status".to_string(), DataValue::from(session.status.name()));
+        params.insert("error".to_string(), session.status.error().map(DataValue::from).unwrap_or(DataValue::Null));
         params.insert("depth".to_string(), DataValue::from(session.depth as i64));
-        params.insert("input".to_string(), DataValue::from(session.messages.first().cloned().unwrap_or_default()));
+        params.insert("input".to_string(), DataValue::from(session.messages.first().map(|m| m.content.clone()).unwrap_or_default()));
         params.insert("now".to_string(), DataValue::from(chrono::Utc::now().timestamp()));
 
         self.backend.run_script(script, params, ScriptMutability::Mutable)?;
-
-        // Store initial messages
-        for (i, msg) in session.messages.iter().enumerate() {
-            self.add_session_message(&session.id, i, msg).await?;
-        }
         Ok(())
     }
 
-    pub async fn update_session(&self, session: &crate::core::session::AgentSession) -> Result<()> {
+    /// Writes `messages` as indices `from..`, in one statement.
+    fn append_session_messages(&self, session_id: &str, from: usize, messages: &[SessionMessage]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let rows = messages.iter().enumerate()
+            .map(|(i, m)| DataValue::List(vec![
+                DataValue::from(session_id.to_string()),
+                DataValue::from((from + i) as i64),
+                DataValue::from(m.role.as_str()),
+                DataValue::from(m.kind.as_str()),
+                DataValue::from(m.content.clone()),
+                DataValue::from(m.timestamp),
+                DataValue::from(m.tokens as i64),
+            ]))
+            .collect();
         let script = "
-            ?[id, status, depth, input, created_at] := *sessions{id, input, created_at}, 
-                id = $id, status = $status, depth = $depth
-            :put sessions { id => status, depth, input, created_at }
+            ?[session_id, msg_index, role, kind, content, timestamp, tokens] <- $rows
+            :put session_messages { session_id, msg_index => role, kind, content, timestamp, tokens }
         ";
         let mut params = BTreeMap::new();
-        params.insert("id".to_string(), DataValue::from(session.id.clone()));
-        params.insert("status".to_string(), DataValue::from(format!("{:?}", session.status)));
-        params.insert("depth".to_string(), DataValue::from(session.depth as i64));
+        params.insert("rows".to_string(), DataValue::List(rows));
 
-        self.backend.run_script(script, params, ScriptMutability::Mutable)?;
-
-        // Only add the NEWEST message to avoid re-writing everything? 
-        // For simplicity and to match the 'Fact' approach, we just ensure 
-        // the current list matches. In Datalog, we can just :put the messages.
-        for (i, msg) in session.messages.iter().enumerate() {
-            self.add_session_message(&session.id, i, msg).await?;
-        }
+        self.backend.run_script(script, params, ScriptMutability::Mutable)
+            .map_err(|e| anyhow!("Failed to append messages to session {}: {}", session_id, e))?;
         Ok(())
     }
 
-    async fn add_session_message(&self, session_id: &str, index: usize, content: &str) -> Result<()> {
+    fn session_message_count(&self, session_id: &str) -> Result<usize> {
+        let script = "?[count(msg_index)] := *session_messages{session_id: $id, msg_index}";
+        let mut params = BTreeMap::new();
+        params.insert("id".to_string(), DataValue::from(session_id.to_string()));
+
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        Ok(result.rows.first().and_then(|r| r.first()).and_then(|v| v.get_int()).unwrap_or(0) as usize)
+    }
+
+    pub async fn list_sessions(&self) -> Result<Vec<String>> {
+        let script = "?[id, created_at] := *sessions{id, created_at} :sort created_at, id";
+        let result = self.backend.run_script(script, BTreeMap::new(), ScriptMutability::Immutable)?;
+        Ok(result.rows.iter()
+            .filter_map(|r| r.first().and_then(|v| v.get_str()).map(|s| s.to_string()))
+            .collect())
+    }
+
+    /// Messages `offset..offset + limit` of a session, in order.
+    pub async fn session_messages(&self, id: &str, offset: usize, limit: usize) -> Result<Vec<SessionMessage>> {
         let script = "
-            ?[session_id, msg_index, content] <- [[$session_id, $index, $content]]
-            :put session_messages { session_id, msg_index => content }
+            ?[msg_index, role, kind, content, timestamp, tokens] :=
+                *session_messages{session_id: $id, msg_index, role, kind, content, timestamp, tokens},
+                msg_index >= $start, msg_index < $end
+            :sort msg_index
         ";
         let mut params = BTreeMap::new();
-        params.insert("session_id".to_string(), DataValue::from(session_id.to_string()));
-        params.insert("index".to_string(), DataValue::from(index as i64));
-        params.insert("content".to_string(), DataValue::from(content.to_string()));
+        params.insert("id".to_string(), DataValue::from(id.to_string()));
+        params.insert("start".to_string(), DataValue::from(offset as i64));
+        params.insert("end".to_string(), DataValue::from(offset.saturating_add(limit).min(i64::MAX as usize) as i64));
 
-        self.backend.run_script(script, params, ScriptMutability::Mutable)?;
-        Ok(())
+        let result = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
+        Ok(result.rows.iter().map(|row| message_from_row(row)).collect())
     }
 
-    pub async fn get_session(&self, id: &str) -> Result<Option<crate::core::session::AgentSession>> {
-        let script = "?[status, depth, input] := *sessions{id: $id, status, depth, input}";
+    pub async fn get_session(&self, id: &str) -> Result<Option<AgentSession>> {
+        let script = "?[status, error, depth] := *sessions{id: $id, status, error, depth}";
         let mut params = BTreeMap::new();
         params.insert("id".to_string(), DataValue::from(id.to_string()));
 
         let res = self.backend.run_script(script, params, ScriptMutability::Immutable)?;
-        if let Some(row) = res.rows.first() {
-            let status_str = match row.first() {
-                Some(DataValue::Str(s)) => s.as_str(),
-                _ => "Idle",
-            };
-            let depth = match row.get(1) {
-                Some(DataValue::Num(n)) => {
-                    let s = format!("{:?}", n);
-                    s.parse::<usize>().unwrap_or(0)
-                }
-                _ => 0,
-            };
-            let status = match status_str {
-                "Thinking" => crate::core::session::SessionStatus::Thinking,
-                "AwaitingObservation" => crate::core::session::SessionStatus::AwaitingObservation,
-                "Completed" => crate::core::session::SessionStatus::Completed,
-                s if s.starts_with("Error") => crate::core::session::SessionStatus::Error(s.replace("Error(\"", "").replace("\")", "")),
-                _ => crate::core::session::SessionStatus::Idle,
-            };
-
-            // Fetch messages
-            let msg_script = "?[content] := *session_messages{session_id: $id, msg_index, content} :sort msg_index";
-            let mut msg_params = BTreeMap::new();
-            msg_params.insert("id".to_string(), DataValue::from(id.to_string()));
-            let msg_res = self.backend.run_script(msg_script, msg_params, ScriptMutability::Immutable)?;
-            
-            let mut messages = Vec::new();
-            for m_row in msg_res.rows {
-                if let Some(DataValue::Str(c)) = m_row.first() {
-                    messages.push(c.to_string());
-                }
-            }
-
-            Ok(Some(crate::core::session::AgentSession {
-                id: id.to_string(),
-                messages,
-                depth,
-                status,
-            }))
-        } else {
-            Ok(None)
-        }
+        let Some(row) = res.rows.first() else { return Ok(None) };
+        let status = SessionStatus::from_parts(
+            row.first().and_then(|v| v.get_str()).unwrap_or_default(),
+            row.get(1).and_then(|v| v.get_str()),
+        );
+        let depth = row.get(2).and_then(|v| v.get_int()).unwrap_or(0) as usize;
+
+        Ok(Some(AgentSession {
+            id: id.to_string(),
+            messages: self.session_messages(id, 0, usize::MAX).await?,
+            depth,
+            status,
+        }))
     }
 }
 
 #[async_trait]
-impl MemoryStore for Memory {
+impl GraphStore for Memory {
     async fn recall(&self, query: &str, limit: usize) -> Result<Vec<String>> {
         self.find_related(query, limit).await
     }
 
+    async fn recall_with(&self, query: &str, limit: usize, filter: &MetadataFilter) -> Result<Vec<String>> {
+        let opts = SearchOptions { filter: filter.clone(), ..SearchOptions::with_limit(limit) };
+        let hits = self.hybrid_search(query, &opts).await?;
+        Ok(hits.into_iter().map(|h| h.content).collect())
+    }
+
     async fn recall_facts(&self, query: &str) -> Result<Vec<String>> {
         self.find_related(query, 5).await
     }
 
-    async fn search_library(&self, query: &str, limit: usize) -> Result<Vec<String>> {
-        self.search_library(query, limit).await
+    async fn search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<SearchHit>> {
+        self.hybrid_search(query, opts).await
     }
 
-    async fn store(&self, content: &str, _metadata: Option<Value>) -> Result<String> {
-        self.store_heuristic(content).await?;
-        Ok("stored".to_string())
+    async fn store(&self, content: &str, metadata: Option<Value>) -> Result<String> {
+        let meta = match metadata {
+            Some(value) => serde_json::from_value(value).context("Invalid memory metadata")?,
+            None => NodeMetadata::default(),
+        };
+        self.store_with_metadata(content, meta).await
     }
 
-    async fn forget(&self, _id: &str) -> Result<()> {
-        Ok(())
+    async fn forget(&self, id: &str) -> Result<()> {
+        if self.node_details(&[id.to_string()])?.is_empty() {
+            bail!("No memory node with id {}", id);
+        }
+        self.forget_nodes(&[id.to_string()]).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        self.purge_expired().await
     }
 
     async fn count_nodes(&self) -> Result<usize> {
@@ -637,6 +1443,75 @@ impl MemoryStore for Memory {
         }
     }
 
+    async fn add_nodes(&self, nodes: Vec<GraphNode>) -> Result<()> {
+        self.batch_add_nodes(nodes).await
+    }
+
+    async fn neighborhood(&self, path: &str) -> Result<Vec<String>> {
+        self.get_neighborhood(path).await
+    }
+
+    async fn stored_memories(&self) -> Result<Vec<(String, String, StoredMetadata)>> {
+        self.stored_memories().await
+    }
+
+    async fn restore_memory(&self, id: &str, content: &str, meta: &StoredMetadata) -> Result<()> {
+        self.put_memory(id, content, meta).await
+    }
+
+    async fn get_memory(&self, id: &str) -> Result<Option<String>> {
+        Ok(self.node_details(&[id.to_string()])?.remove(id).map(|(_, _, content)| content))
+    }
+
+    fn embedding_cache_stats(&self) -> Option<CacheStatsSnapshot> {
+        self.engine.as_ref().map(|_| self.embedding_cache_stats())
+    }
+
+    fn prune_embedding_cache(&self, config: &EmbeddingConfig, now: i64) -> Result<usize> {
+        self.prune_embedding_cache(config, now)
+    }
+}
+
+#[async_trait]
+impl LibraryStore for Memory {
+    async fn search_library(&self, query: &str, limit: usize, rerank: Option<bool>) -> Result<Vec<String>> {
+        self.search_library_with(query, limit, rerank).await
+    }
+
+    async fn add_library_docs(&self, docs: Vec<LibraryDoc>) -> Result<()> {
+        self.add_library_docs(docs).await
+    }
+
+    async fn known_libraries(&self) -> Result<Vec<(String, String)>> {
+        self.get_known_libraries_with_versions().await
+    }
+}
+
+#[async_trait]
+impl SessionStore for Memory {
+    async fn create_session(&self, session: &AgentSession) -> Result<()> {
+        self.create_session(session).await
+    }
+
+    async fn update_session(&self, session: &AgentSession) -> Result<()> {
+        self.update_session(session).await
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<AgentSession>> {
+        self.get_session(id).await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        self.list_sessions().await
+    }
+
+    async fn session_messages(&self, id: &str, offset: usize, limit: usize) -> Result<Vec<SessionMessage>> {
+        self.session_messages(id, offset, limit).await
+    }
+}
+
+#[async_trait]
+impl SkillStore for Memory {
     async fn register_skill(&self, name: &str, code: &str, desc: &str, signature: &str) -> Result<()> {
         self.register_skill(name, code, desc, signature).await
     }
@@ -645,3 +1520,178 @@ impl MemoryStore for Memory {
         self.get_skill(name).await
     }
 }
+
+#[async_trait]
+impl EventLog for Memory {
+    fn record_correlated(&self, op: &str, data: Value, correlation: &Correlation) -> Result<()> {
+        self.backend.record_correlated(op, data, correlation)
+    }
+
+    async fn query_events(&self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        self.query_events(query).await
+    }
+
+    async fn apply_retention(&self, config: &EventsConfig, now: i64) -> Result<RetentionReport> {
+        self.apply_retention(config, now).await
+    }
+}
+
+impl QueryStore for Memory {
+    fn query(&self, script: &str) -> Result<Value> {
+        let rows = self.backend_run_script(script)?;
+        Ok(serde_json::to_value(&rows)?)
+    }
+}
+
+#[async_trait]
+impl SyncLog for Memory {
+    async fn check_sync_status(&self, path: &str) -> Result<Option<(i64, String)>> {
+        self.check_sync_status(path).await
+    }
+
+    async fn update_sync_status(&self, path: &str, hash: &str) -> Result<()> {
+        self.update_sync_status(path, hash).await
+    }
+
+    async fn indexed_paths(&self) -> Result<Vec<String>> {
+        self.indexed_paths().await
+    }
+}
+
+/// Pairs the flat chunk vectors back up with their inputs, in order.
+/// Single-chunk inputs get their vector directly; multi-chunk inputs get the
+/// mean-pooled vector plus one row per chunk; inputs with no chunks (empty
+/// content) took no vector and get a zero one to satisfy the schema.
+fn assemble_chunked(chunked: Vec<Vec<Chunk>>, vectors: Vec<Vec<f32>>, dim: usize) -> Vec<ChunkedEmbedding> {
+    let mut vectors = vectors.into_iter();
+    let mut results = Vec::with_capacity(chunked.len());
+    for chunks in chunked {
+        match chunks.len() {
+            0 => results.push(ChunkedEmbedding { embedding: vec![0.0; dim], chunks: vec![] }),
+            1 => {
+                let embedding = vectors.next().unwrap_or_else(|| vec![0.0; dim]);
+                results.push(ChunkedEmbedding { embedding, chunks: vec![] });
+            }
+            _ => {
+                let embedded: Vec<EmbeddedChunk> = chunks.into_iter()
+                    .zip(vectors.by_ref())
+                    .map(|(c, embedding)| EmbeddedChunk { index: c.index, content: c.content, embedding })
+                    .collect();
+                let pooled = chunker::mean_pool(&embedded.iter().map(|c| c.embedding.clone()).collect::<Vec<_>>());
+                results.push(ChunkedEmbedding { embedding: pooled, chunks: embedded });
+            }
+        }
+    }
+    results
+}
+
+/// Where the store at `path` is backed up before a re-embed: beside it,
+/// outside the engine's own directory.
+fn reembed_backup_path(path: &str, timestamp: i64) -> std::path::PathBuf {
+    let store = std::path::Path::new(path);
+    let name = store.file_name().map_or_else(|| "store".to_string(), |n| n.to_string_lossy().into_owned());
+    store.with_file_name(format!("{}.pre-reembed-{}.db", name, timestamp))
+}
+
+/// String columns followed by an embedding, as a Cozo input row.
+fn string_row(columns: &[String], embedding: Vec<f32>) -> DataValue {
+    let mut row: Vec<DataValue> = columns.iter().map(|c| DataValue::from(c.clone())).collect();
+    row.push(vec_to_datavalue(embedding));
+    DataValue::List(row)
+}
+
+/// A `node_meta` row laid out as `[id, kind, source_session, tags,
+/// confidence, expires_at, created_at, ..]`.
+fn metadata_from_row(row: &[DataValue]) -> StoredMetadata {
+    StoredMetadata {
+        kind: row.get(1).and_then(|v| v.get_str()).unwrap_or_default().to_string(),
+        source_session: row.get(2).and_then(|v| v.get_str()).map(|s| s.to_string()),
+        tags: row.get(3)
+            .and_then(|v| v.get_slice())
+            .map(|tags| tags.iter().filter_map(|t| t.get_str()).map(|t| t.to_string()).collect())
+            .unwrap_or_default(),
+        confidence: row.get(4).and_then(|v| v.get_float()).unwrap_or(1.0),
+        expires_at: row.get(5).and_then(|v| v.get_int()),
+        created_at: row.get(6).and_then(|v| v.get_int()).unwrap_or_default(),
+    }
+}
+
+/// A `session_messages` row laid out as `[msg_index, role, kind, content,
+/// timestamp, tokens]`.
+fn message_from_row(row: &[DataValue]) -> SessionMessage {
+    let content = row.get(3).and_then(|v| v.get_str()).unwrap_or_default().to_string();
+    let kind = row.get(2).and_then(|v| v.get_str()).and_then(MessageKind::parse).unwrap_or_else(|| MessageKind::infer(&content));
+    SessionMessage {
+        role: row.get(1).and_then(|v| v.get_str()).and_then(Role::parse).unwrap_or(kind.role()),
+        kind,
+        timestamp: row.get(4).and_then(|v| v.get_int()).unwrap_or(0),
+        tokens: row.get(5).and_then(|v| v.get_int()).unwrap_or(0) as usize,
+        content,
+    }
+}
+
+/// `[id, number]` rows as (id, f64) pairs, skipping anything malformed.
+fn scored_rows(rows: Vec<Vec<DataValue>>) -> Vec<(String, f64)> {
+    rows.into_iter()
+        .filter_map(|row| match (row.first(), row.get(1).and_then(|v| v.get_float())) {
+            (Some(DataValue::Str(id)), Some(score)) => Some((id.to_string(), score)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_item_keeps_later_vectors_aligned() {
+        let one = |content: &str| vec![Chunk { index: 0, content: content.to_string() }];
+        let two = vec![Chunk { index: 0, content: "c1".into() }, Chunk { index: 1, content: "c2".into() }];
+        let chunked = vec![one("a"), vec![], one("b"), two];
+        let vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 0.0]];
+
+        let results = assemble_chunked(chunked, vectors, 2);
+        let embeddings: Vec<&Vec<f32>> = results.iter().map(|r| &r.embedding).collect();
+        assert_eq!(embeddings, [&vec![1.0, 0.0], &vec![0.0, 0.0], &vec![0.0, 1.0], &vec![1.0, 0.0]]);
+        assert_eq!(results[3].chunks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reembed_keeps_rows_at_new_dim() {
+        let backend = CozoBackend::open("", false, StorageEngine::Memory).unwrap();
+        let engine = EmbeddingEngine::tiny("tiny-a", 8).unwrap();
+        let mut memory = Memory { backend, engine: Some(engine), reranker: None, rerank_candidates: 0, offline: true, read_only: false, cache_stats: CacheStats::default() };
+        memory.sync_embedding_model(None).await.unwrap();
+        assert_eq!(memory.embedding_dim(), 8);
+
+        memory.add_node(&GraphNode { id: "n1".into(), content: "fn main".into(), node_type: "fn".into(), path: "src/main.rs".into(), edges: vec![] }).await.unwrap();
+        let doc = LibraryDoc { id: "serde_1".into(), name: "serde".into(), version: "1".into(), content: "docs".into(), chunk_type: "documentation".into() };
+        memory.add_library_docs(vec![doc]).await.unwrap();
+        memory.register_library("tokio", "1").await.unwrap();
+        memory.store_cache("cache", "answer").await.unwrap();
+
+        memory.engine = Some(EmbeddingEngine::tiny("tiny-b", 16).unwrap());
+        memory.sync_embedding_model(None).await.unwrap();
+
+        assert_eq!(memory.embedding_dim(), 16);
+        assert_eq!(memory.backend.embedding_meta().unwrap(), Some(("tiny-b".to_string(), 16)));
+        for (relation, expected) in [("nodes", 1), ("library", 2), ("cache", 1)] {
+            let rows = memory.backend_run_script(&format!("?[id, embedding] := *{}{{id, embedding}}", relation)).unwrap().rows;
+            assert_eq!(rows.len(), expected, "{} rows", relation);
+            assert!(rows.iter().all(|r| datavalue_to_vec(&r[1]).map(|v| v.len()) == Some(16)), "{} vectors", relation);
+        }
+        assert_eq!(reembed_backup_path(".sly/cozo", 7), std::path::PathBuf::from(".sly/cozo.pre-reembed-7.db"));
+
+        // Same width, other model: the old model's cached vectors go.
+        memory.engine = Some(EmbeddingEngine::tiny("tiny-c", 16).unwrap());
+        memory.sync_embedding_model(None).await.unwrap();
+        let models = memory.backend_run_script("?[model] := *embedding_cache{model}").unwrap().rows;
+        assert!(!models.is_empty());
+        assert!(models.iter().all(|r| r[0].get_str() == Some("tiny-c")), "{:?}", models);
+
+        let keep_one = EmbeddingConfig { cache_max_age_days: None, cache_max_rows: Some(1), ..Default::default() };
+        memory.prune_embedding_cache(&keep_one, chrono::Utc::now().timestamp()).unwrap();
+        assert_eq!(memory.backend_run_script("?[hash] := *embedding_cache{hash}").unwrap().rows.len(), 1);
+    }
+}
@@ -0,0 +1,390 @@
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+use super::metadata::{MetadataFilter, NodeMetadata, StoredMetadata};
+use super::search::{HitReason, SearchHit, SearchOptions};
+use super::store_graph::{GraphNode, LibraryDoc};
+use super::events::{self, Correlation, EventQuery, EventRecord, RetentionReport, EVENT_VERSION};
+use super::replay;
+use super::{EventLog, GraphStore, LibraryStore, QueryStore, SessionStore, SkillStore, SyncLog};
+use crate::core::session::{AgentSession, SessionMessage};
+use crate::core::state::EventsConfig;
+
+/// Process-local store with no database and no embedding model. Recall is
+/// plain keyword overlap, which is enough for tests and throwaway runs.
+/// Code that holds more than one lock takes them in field order (nodes,
+/// meta, edges), so no two callers can wait on each other.
+#[derive(Default)]
+pub struct InMemoryStore {
+    nodes: RwLock<BTreeMap<String, GraphNode>>,
+    meta: RwLock<HashMap<String, StoredMetadata>>,
+    edges: RwLock<HashSet<(String, String)>>,
+    library: RwLock<BTreeMap<String, LibraryDoc>>,
+    sessions: RwLock<HashMap<String, AgentSession>>,
+    skills: RwLock<HashMap<String, String>>,
+    events: RwLock<Vec<EventRecord>>,
+    sync: RwLock<HashMap<String, (i64, String)>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn keyword_hits(&self, query: &str, opts: &SearchOptions) -> Vec<SearchHit> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let nodes = self.nodes.read().unwrap();
+        let meta = self.meta.read().unwrap();
+
+        let mut scored: Vec<(&GraphNode, f64)> = nodes.values()
+            .filter(|n| opts.filter.matches(meta.get(&n.id), now))
+            .map(|n| (n, keyword_score(query, &n.content)))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.id.cmp(&b.0.id)));
+
+        scored.into_iter()
+            .take(opts.limit)
+            .enumerate()
+            .map(|(i, (node, score))| SearchHit {
+                id: node.id.clone(),
+                path: node.path.clone(),
+                node_type: node.node_type.clone(),
+                content: node.content.clone(),
+                score,
+                reasons: vec![HitReason::Keyword { rank: i + 1, score }],
+            })
+            .collect()
+    }
+}
+
+// --- Pure Functions ---
+
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// Share of the query's terms that occur in `content`.
+fn keyword_score(query: &str, content: &str) -> f64 {
+    let wanted = terms(query);
+    if wanted.is_empty() {
+        return 0.0;
+    }
+    let present = terms(content);
+    wanted.iter().filter(|t| present.contains(*t)).count() as f64 / wanted.len() as f64
+}
+
+#[async_trait]
+impl GraphStore for InMemoryStore {
+    async fn recall(&self, query: &str, limit: usize) -> Result<Vec<String>> {
+        self.recall_with(query, limit, &MetadataFilter::default()).await
+    }
+
+    async fn recall_with(&self, query: &str, limit: usize, filter: &MetadataFilter) -> Result<Vec<String>> {
+        let opts = SearchOptions { filter: filter.clone(), ..SearchOptions::with_limit(limit) };
+        Ok(self.keyword_hits(query, &opts).into_iter().map(|h| h.content).collect())
+    }
+
+    async fn recall_facts(&self, query: &str) -> Result<Vec<String>> {
+        self.recall(query, 5).await
+    }
+
+    async fn search(&self, query: &str, opts: &SearchOptions) -> Result<Vec<SearchHit>> {
+        Ok(self.keyword_hits(query, opts))
+    }
+
+    async fn store(&self, content: &str, metadata: Option<Value>) -> Result<String> {
+        let meta: NodeMetadata = match metadata {
+            Some(value) => serde_json::from_value(value).context("Invalid memory metadata")?,
+            None => NodeMetadata::default(),
+        };
+        let id = Uuid::new_v4().to_string();
+        let stored = StoredMetadata::from_input(meta, chrono::Utc::now().timestamp_millis());
+        self.restore_memory(&id, content, &stored).await?;
+        Ok(id)
+    }
+
+    async fn forget(&self, id: &str) -> Result<()> {
+        if self.nodes.write().unwrap().remove(id).is_none() {
+            bail!("No memory node with id {}", id);
+        }
+        self.meta.write().unwrap().remove(id);
+        self.edges.write().unwrap().retain(|(from, to)| from != id && to != id);
+        self.record_event(replay::FORGET, serde_json::json!({ "ids": [id] }))
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expired: Vec<String> = self.meta.read().unwrap().iter()
+            .filter(|(_, m)| m.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.forget(id).await?;
+        }
+        Ok(expired.len())
+    }
+
+    async fn count_nodes(&self) -> Result<usize> {
+        Ok(self.nodes.read().unwrap().len())
+    }
+
+    async fn add_nodes(&self, nodes: Vec<GraphNode>) -> Result<()> {
+        let mut stored = self.nodes.write().unwrap();
+        let mut edges = self.edges.write().unwrap();
+        for node in nodes {
+            edges.extend(node.edges.iter().map(|to| (node.id.clone(), to.clone())));
+            stored.insert(node.id.clone(), node);
+        }
+        Ok(())
+    }
+
+    async fn neighborhood(&self, path: &str) -> Result<Vec<String>> {
+        let nodes = self.nodes.read().unwrap();
+        let edges = self.edges.read().unwrap();
+        let mut results: Vec<String> = nodes.values().filter(|n| n.path == path).map(|n| n.content.clone()).collect();
+        for (from, to) in edges.iter() {
+            let other = if from == path { to } else if to == path { from } else { continue };
+            if let Some(node) = nodes.get(other) {
+                results.push(node.content.clone());
+            }
+        }
+        Ok(results)
+    }
+
+    async fn stored_memories(&self) -> Result<Vec<(String, String, StoredMetadata)>> {
+        let nodes = self.nodes.read().unwrap();
+        let mut memories: Vec<(String, String, StoredMetadata)> = self.meta.read().unwrap().iter()
+            .filter_map(|(id, meta)| nodes.get(id).map(|n| (id.clone(), n.content.clone(), meta.clone())))
+            .collect();
+        memories.sort_by(|a, b| a.2.created_at.cmp(&b.2.created_at).then_with(|| a.0.cmp(&b.0)));
+        Ok(memories)
+    }
+
+    async fn restore_memory(&self, id: &str, content: &str, meta: &StoredMetadata) -> Result<()> {
+        self.nodes.write().unwrap().insert(id.to_string(), GraphNode {
+            id: id.to_string(),
+            content: content.to_string(),
+            node_type: meta.kind.clone(),
+            path: "memory".to_string(),
+            edges: vec![],
+        });
+        self.meta.write().unwrap().insert(id.to_string(), meta.clone());
+        self.record_event(replay::MEMORY_STORED, replay::memory_stored_event(id, content, meta))
+    }
+
+    async fn get_memory(&self, id: &str) -> Result<Option<String>> {
+        Ok(self.nodes.read().unwrap().get(id).map(|n| n.content.clone()))
+    }
+}
+
+#[async_trait]
+impl LibraryStore for InMemoryStore {
+    async fn search_library(&self, query: &str, limit: usize, _rerank: Option<bool>) -> Result<Vec<String>> {
+        let library = self.library.read().unwrap();
+        let mut scored: Vec<(&LibraryDoc, f64)> = library.values()
+            .map(|doc| (doc, keyword_score(query, &format!("{} {}", doc.name, doc.content))))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(scored.into_iter().take(limit).map(|(doc, _)| doc.content.clone()).collect())
+    }
+
+    async fn add_library_docs(&self, docs: Vec<LibraryDoc>) -> Result<()> {
+        let mut library = self.library.write().unwrap();
+        for doc in docs {
+            library.insert(doc.id.clone(), doc);
+        }
+        Ok(())
+    }
+
+    async fn known_libraries(&self) -> Result<Vec<(String, String)>> {
+        let mut libs: Vec<(String, String)> = self.library.read().unwrap().values()
+            .map(|doc| (doc.name.clone(), doc.version.clone()))
+            .collect();
+        libs.sort();
+        libs.dedup();
+        Ok(libs)
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemoryStore {
+    async fn create_session(&self, session: &AgentSession) -> Result<()> {
+        self.sessions.write().unwrap().insert(session.id.clone(), session.clone());
+        self.record_event(replay::SESSION_CREATED, replay::session_created_event(session))
+    }
+
+    async fn update_session(&self, session: &AgentSession) -> Result<()> {
+        let previous = self.sessions.write().unwrap().insert(session.id.clone(), session.clone());
+        let previous_len = previous.map(|s| s.messages.len()).unwrap_or(0);
+        self.record_event(replay::SESSION_UPDATED, replay::session_updated_event(session, previous_len))
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<AgentSession>> {
+        Ok(self.sessions.read().unwrap().get(id).cloned())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = self.sessions.read().unwrap().keys().cloned().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn session_messages(&self, id: &str, offset: usize, limit: usize) -> Result<Vec<SessionMessage>> {
+        let sessions = self.sessions.read().unwrap();
+        let messages = sessions.get(id).map(|s| s.messages.as_slice()).unwrap_or_default();
+        Ok(messages.iter().skip(offset).take(limit).cloned().collect())
+    }
+}
+
+#[async_trait]
+impl SkillStore for InMemoryStore {
+    async fn register_skill(&self, name: &str, code: &str, _desc: &str, _signature: &str) -> Result<()> {
+        self.skills.write().unwrap().insert(name.to_string(), code.to_string());
+        Ok(())
+    }
+
+    async fn get_skill(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.skills.read().unwrap().get(name).cloned())
+    }
+}
+
+#[async_trait]
+impl EventLog for InMemoryStore {
+    fn record_correlated(&self, op: &str, data: Value, correlation: &Correlation) -> Result<()> {
+        self.events.write().unwrap().push(EventRecord {
+            id: Uuid::new_v4().to_string(),
+            op: op.to_string(),
+            data,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            version: EVENT_VERSION,
+            session_id: correlation.session_id.clone(),
+            directive_id: correlation.directive_id.clone(),
+        });
+        Ok(())
+    }
+
+    async fn query_events(&self, query: &EventQuery) -> Result<Vec<EventRecord>> {
+        Ok(query.select(self.events.read().unwrap().iter()))
+    }
+
+    async fn apply_retention(&self, config: &EventsConfig, now: i64) -> Result<RetentionReport> {
+        let mut report = RetentionReport::default();
+        let mut log = self.events.write().unwrap();
+
+        if let Some(days) = config.retention_days {
+            let before = log.len();
+            log.retain(|e| e.timestamp > now - days as i64 * events::DAY_MS || !events::expires(e));
+            report.expired = before - log.len();
+        }
+        if let Some(days) = config.compact_after_days {
+            let cutoff = now - days as i64 * events::DAY_MS;
+            let old: Vec<EventRecord> = log.iter()
+                .filter(|e| e.timestamp <= cutoff && config.compact_ops.contains(&e.op))
+                .cloned()
+                .collect();
+            for compaction in events::plan_compaction(&old) {
+                log.retain(|e| !compaction.replaces.iter().any(|r| r.id == e.id));
+                report.compacted += compaction.replaces.len();
+                report.summaries += 1;
+                log.push(compaction.summary);
+            }
+            log.sort_by_key(|e| e.timestamp);
+        }
+        Ok(report)
+    }
+}
+
+impl QueryStore for InMemoryStore {
+    fn query(&self, _script: &str) -> Result<Value> {
+        bail!("The in-memory store does not support Datalog queries")
+    }
+}
+
+#[async_trait]
+impl SyncLog for InMemoryStore {
+    async fn check_sync_status(&self, path: &str) -> Result<Option<(i64, String)>> {
+        Ok(self.sync.read().unwrap().get(path).cloned())
+    }
+
+    async fn update_sync_status(&self, path: &str, hash: &str) -> Result<()> {
+        self.sync.write().unwrap().insert(path.to_string(), (chrono::Utc::now().timestamp(), hash.to_string()));
+        Ok(())
+    }
+
+    async fn indexed_paths(&self) -> Result<Vec<String>> {
+        let mut paths: Vec<String> = self.sync.read().unwrap().keys().cloned().collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStore;
+
+    fn store() -> std::sync::Arc<dyn MemoryStore> {
+        std::sync::Arc::new(InMemoryStore::new())
+    }
+
+    #[tokio::test]
+    async fn test_store_recall_filter_and_forget() {
+        let memory = store();
+        let lesson = memory.store("Retry the auth token refresh", Some(serde_json::json!({ "kind": "lesson", "tags": ["auth"] }))).await.unwrap();
+        memory.store("Auth errors are logged upstream", None).await.unwrap();
+
+        assert_eq!(memory.recall("auth refresh", 5).await.unwrap()[0], "Retry the auth token refresh");
+        let lessons = MetadataFilter { kinds: vec!["lesson".to_string()], ..Default::default() };
+        assert_eq!(memory.recall_with("auth", 5, &lessons).await.unwrap().len(), 1);
+
+        memory.forget(&lesson).await.unwrap();
+        assert_eq!(memory.count_nodes().await.unwrap(), 1);
+        assert!(memory.forget(&lesson).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_skills_and_sync_round_trip() {
+        let memory = store();
+        let session = AgentSession::new("hello".to_string());
+        memory.create_session(&session).await.unwrap();
+        memory.update_session(&session.clone().with_message(SessionMessage::observation("world".to_string()))).await.unwrap();
+        assert_eq!(memory.get_session(&session.id).await.unwrap().unwrap().messages.len(), 2);
+        assert_eq!(memory.session_messages(&session.id, 1, 10).await.unwrap()[0].content, "world");
+
+        memory.register_skill("add", "(module)", "adds", "(i32,i32)->i32").await.unwrap();
+        assert_eq!(memory.get_skill("add").await.unwrap().as_deref(), Some("(module)"));
+
+        assert!(memory.check_sync_status("src/a.rs").await.unwrap().is_none());
+        memory.update_sync_status("src/a.rs", "abc").await.unwrap();
+        assert_eq!(memory.check_sync_status("src/a.rs").await.unwrap().unwrap().1, "abc");
+    }
+
+    #[tokio::test]
+    async fn test_retention_keeps_replayable_events() {
+        let memory = store();
+        memory.create_session(&AgentSession::new("hello".to_string())).await.unwrap();
+        memory.record_event("EXEC:think", serde_json::Value::Null).unwrap();
+
+        let config = EventsConfig { retention_days: Some(1), compact_after_days: None, ..Default::default() };
+        let report = memory.apply_retention(&config, chrono::Utc::now().timestamp_millis() + 2 * events::DAY_MS).await.unwrap();
+        assert_eq!(report.expired, 1);
+        let left = memory.query_events(&EventQuery::default()).await.unwrap();
+        assert_eq!(left.iter().map(|e| e.op.as_str()).collect::<Vec<_>>(), vec![replay::SESSION_CREATED]);
+    }
+
+    #[test]
+    fn test_keyword_score() {
+        assert_eq!(keyword_score("Token refresh", "fn refresh_token() / token refresh"), 1.0);
+        assert_eq!(keyword_score("token cache", "token"), 0.5);
+        assert_eq!(keyword_score("", "anything"), 0.0);
+    }
+}
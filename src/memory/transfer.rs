@@ -0,0 +1,472 @@
+use anyhow::{anyhow, bail, Context, Result};
+use cozo::{DataValue, JsonData, Num, ScriptMutability};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use super::backend_cozo::{datavalue_to_vec, vec_to_datavalue, CozoBackend, VECTOR_RELATIONS};
+use super::migrations::{self, SCHEMA_VERSION};
+use crate::core::session::MessageKind;
+
+pub const EXPORT_FORMAT: &str = "sly-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Relations that make up the shareable knowledge base. Heuristics and
+/// lessons live in `nodes`; `sync_log` lets an importer skip re-ingesting
+/// files that have not changed since the export.
+pub const EXPORT_RELATIONS: &[&str] = &[
+    "nodes", "node_meta", "edges", "chunks", "library", "skills",
+    "sessions", "session_messages", "event_log", "event_by_time", "sync_log",
+];
+
+/// Rows per `:put` on import.
+const IMPORT_BATCH: usize = 500;
+
+/// First line of a JSON Lines export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub format: String,
+    pub format_version: u32,
+    pub schema_version: i64,
+    pub embedding_model: Option<String>,
+    pub dim: usize,
+    pub exported_at: i64,
+}
+
+/// Every other line: one row as a column -> value object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRow {
+    pub relation: String,
+    pub row: Map<String, Value>,
+}
+
+/// What to do when an imported row's key already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keep the existing row.
+    Skip,
+    /// Replace the existing row.
+    Overwrite,
+    /// Abort before writing anything.
+    Fail,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "fail" => Ok(Self::Fail),
+            other => Err(anyhow!("Unknown conflict policy '{}' (expected skip, overwrite or fail)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// relation -> (rows in the file, rows whose key already existed)
+    pub relations: BTreeMap<String, (usize, usize)>,
+}
+
+/// A relation's rows, converted and ready for `:put`.
+type PreparedRelation = (String, Vec<Column>, Vec<DataValue>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColType {
+    String,
+    Int,
+    Float,
+    Json,
+    List,
+    Vector,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub ty: ColType,
+    pub key: bool,
+}
+
+// --- Pure Functions ---
+
+/// Splits a relation spec (`a: String, b: Int => c: Json`) into columns.
+pub fn parse_spec(spec: &str) -> Vec<Column> {
+    let (keys, values) = spec.split_once("=>").unwrap_or((spec, ""));
+    let parse = |part: &str, key: bool| -> Vec<Column> {
+        part.split(',')
+            .filter_map(|c| c.split_once(':'))
+            .map(|(name, ty)| {
+                // Nullable columns (`Int?`) import the same way; nulls pass through.
+                let ty = ty.trim().trim_end_matches('?');
+                Column {
+                    name: name.trim().to_string(),
+                    ty: match ty {
+                        "Int" => ColType::Int,
+                        "Float" => ColType::Float,
+                        "Json" => ColType::Json,
+                        t if t.starts_with('<') => ColType::Vector,
+                        t if t.starts_with('[') => ColType::List,
+                        _ => ColType::String,
+                    },
+                    key,
+                }
+            })
+            .collect()
+    };
+    // Vector and list types (`<F32; N>`, `[String]`) contain no commas, so a plain split is safe.
+    let mut columns = parse(keys, true);
+    columns.extend(parse(values, false));
+    columns
+}
+
+/// Brings a row exported at schema `from` to the current shape of its
+/// relation, by the same rules as the migrations a store of that version
+/// would go through.
+pub fn upgrade_row(relation: &str, from: i64, dim: usize, row: &mut Map<String, Value>) {
+    match relation {
+        // v1 `AddEmbedding`: rows from before embeddings get a zero vector.
+        "nodes" | "library" if from < 1 && !row.contains_key("embedding") => {
+            row.insert("embedding".to_string(), Value::from(vec![0.0f32; dim]));
+        }
+        // v7 `Retype`: status used to be the `Debug` string, e.g. `Error("boom")`.
+        "sessions" if from < 7 => {
+            let old = row.get("status").and_then(Value::as_str).unwrap_or_default().to_string();
+            let error = old.strip_prefix("Error").map(|e| e.trim_start_matches("(\"").trim_end_matches("\")").to_string());
+            if error.is_some() {
+                row.insert("status".to_string(), Value::from("Error"));
+            }
+            row.insert("error".to_string(), error.map_or(Value::Null, Value::from));
+        }
+        // v7 `Retype`: kind from the content prefix; the original times are unknown.
+        "session_messages" if from < 7 => {
+            let content = row.get("content").and_then(Value::as_str).unwrap_or_default();
+            let kind = MessageKind::infer(content);
+            let tokens = content.chars().count().div_ceil(4);
+            row.insert("kind".to_string(), Value::from(kind.as_str()));
+            row.insert("role".to_string(), Value::from(kind.role().as_str()));
+            row.insert("timestamp".to_string(), Value::from(0));
+            row.insert("tokens".to_string(), Value::from(tokens));
+        }
+        _ => {}
+    }
+}
+
+/// Relations an export at schema `from` predates, derived from the rows it
+/// has (v6 `Backfill`: the time index over `event_log`, without correlation ids).
+pub fn backfill_rows(from: i64, grouped: &mut BTreeMap<String, Vec<Map<String, Value>>>) {
+    if from >= 6 || grouped.contains_key("event_by_time") {
+        return;
+    }
+    let Some(events) = grouped.get("event_log") else { return };
+    let rows = events.iter()
+        .map(|event| {
+            let mut row = Map::new();
+            for column in ["timestamp", "id", "op"] {
+                row.insert(column.to_string(), event.get(column).cloned().unwrap_or(Value::Null));
+            }
+            row.insert("session_id".to_string(), Value::Null);
+            row.insert("directive_id".to_string(), Value::Null);
+            row
+        })
+        .collect();
+    grouped.insert("event_by_time".to_string(), rows);
+}
+
+pub fn datavalue_to_json(v: &DataValue) -> Value {
+    match v {
+        DataValue::Null => Value::Null,
+        DataValue::Bool(b) => Value::Bool(*b),
+        DataValue::Num(Num::Int(i)) => Value::from(*i),
+        DataValue::Num(Num::Float(f)) => Value::from(*f),
+        DataValue::Str(s) => Value::String(s.to_string()),
+        DataValue::Json(j) => j.0.clone(),
+        DataValue::List(items) => Value::Array(items.iter().map(datavalue_to_json).collect()),
+        DataValue::Vec(_) => Value::from(datavalue_to_vec(v).unwrap_or_default()),
+        other => Value::String(format!("{:?}", other)),
+    }
+}
+
+pub fn json_to_datavalue(v: Value, ty: ColType) -> Result<DataValue> {
+    Ok(match (ty, v) {
+        (ColType::Json, v) => DataValue::Json(JsonData(v)),
+        (_, Value::Null) => DataValue::Null,
+        (ColType::String, Value::String(s)) => DataValue::from(s),
+        (ColType::Int, Value::Number(n)) => DataValue::from(n.as_i64().ok_or_else(|| anyhow!("{} is not an integer", n))?),
+        (ColType::Float, Value::Number(n)) => DataValue::from(n.as_f64().unwrap_or_default()),
+        (ColType::List, Value::Array(items)) => DataValue::List(
+            items.into_iter()
+                .map(|item| match item {
+                    Value::String(s) => DataValue::from(s),
+                    Value::Number(n) if n.is_i64() => DataValue::from(n.as_i64().unwrap_or_default()),
+                    Value::Number(n) => DataValue::from(n.as_f64().unwrap_or_default()),
+                    other => DataValue::Json(JsonData(other)),
+                })
+                .collect(),
+        ),
+        (ColType::Vector, Value::Array(items)) => {
+            vec_to_datavalue(items.iter().map(|x| x.as_f64().unwrap_or_default() as f32).collect())
+        }
+        (ty, v) => bail!("Cannot read {} as {:?}", v, ty),
+    })
+}
+
+// --- IO ---
+
+/// Writes the header and every row of `relations` as JSON Lines.
+/// Returns the row count per relation.
+pub fn export_jsonl(backend: &CozoBackend, out: &mut impl Write, relations: &[&str]) -> Result<BTreeMap<String, usize>> {
+    let header = ExportHeader {
+        format: EXPORT_FORMAT.to_string(),
+        format_version: EXPORT_FORMAT_VERSION,
+        schema_version: backend.schema_version()?,
+        embedding_model: backend.embedding_meta()?.map(|(model, _)| model),
+        dim: backend.dim,
+        exported_at: chrono::Utc::now().timestamp_millis(),
+    };
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+
+    let mut counts = BTreeMap::new();
+    for relation in relations {
+        let columns = parse_spec(&migrations::relation_spec(relation, backend.dim));
+        let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+        let script = format!("?[{cols}] := *{rel}{{{cols}}}", cols = names.join(", "), rel = relation);
+        let result = backend.run_script(&script, Default::default(), ScriptMutability::Immutable)
+            .with_context(|| format!("Failed to export `{}`", relation))?;
+
+        for row in &result.rows {
+            let row: Map<String, Value> = names.iter()
+                .zip(row)
+                .map(|(name, v)| (name.to_string(), datavalue_to_json(v)))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string(&ExportRow { relation: relation.to_string(), row })?)?;
+        }
+        counts.insert(relation.to_string(), result.rows.len());
+    }
+    Ok(counts)
+}
+
+/// Reads a JSON Lines export and merges it into the store under `policy`.
+/// With `ConflictPolicy::Fail` every relation is checked before any write.
+pub fn import_jsonl(backend: &mut CozoBackend, input: impl BufRead, policy: ConflictPolicy) -> Result<ImportReport> {
+    let mut lines = input.lines();
+    let header = read_header(&lines.next().ok_or_else(|| anyhow!("Empty export file"))??)?;
+    adopt_embedding_model(backend, &header)?;
+    let prepared = prepare_rows(lines, header.schema_version, backend.dim)?;
+
+    let mut report = ImportReport::default();
+    for (relation, columns, rows) in &prepared {
+        let conflicts = count_conflicts(backend, relation, columns, rows)?;
+        report.relations.insert(relation.clone(), (rows.len(), conflicts));
+    }
+    if policy == ConflictPolicy::Fail {
+        let clashing: Vec<String> = report.relations.iter()
+            .filter(|(_, (_, conflicts))| *conflicts > 0)
+            .map(|(rel, (_, conflicts))| format!("{} ({})", rel, conflicts))
+            .collect();
+        if !clashing.is_empty() {
+            bail!("Import aborted, existing keys in: {}", clashing.join(", "));
+        }
+    }
+
+    for (relation, columns, rows) in prepared {
+        let script = import_script(&relation, &columns, policy);
+        for batch in rows.chunks(IMPORT_BATCH) {
+            let mut params = BTreeMap::new();
+            params.insert("rows".to_string(), DataValue::List(batch.to_vec()));
+            backend.run_script(&script, params, ScriptMutability::Mutable)
+                .with_context(|| format!("Failed to import `{}`", relation))?;
+        }
+    }
+    Ok(report)
+}
+
+/// The export header, refused unless this binary can read the format and
+/// migrate rows up from its schema version.
+fn read_header(line: &str) -> Result<ExportHeader> {
+    let header: ExportHeader = serde_json::from_str(line).context("Missing or invalid export header")?;
+    if header.format != EXPORT_FORMAT || header.format_version > EXPORT_FORMAT_VERSION {
+        bail!("Unsupported export format {} v{}", header.format, header.format_version);
+    }
+    if header.schema_version > SCHEMA_VERSION {
+        bail!("Export is from schema v{} but this binary only understands up to v{}", header.schema_version, SCHEMA_VERSION);
+    }
+    Ok(header)
+}
+
+/// Every row of the export body, upgraded from schema `from` and converted
+/// to the current column types up front, so a bad row aborts before any write.
+fn prepare_rows(lines: impl Iterator<Item = std::io::Result<String>>, from: i64, dim: usize) -> Result<Vec<PreparedRelation>> {
+    let mut grouped: BTreeMap<String, Vec<Map<String, Value>>> = BTreeMap::new();
+    for (n, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row: ExportRow = serde_json::from_str(&line).with_context(|| format!("Invalid row on line {}", n + 2))?;
+        if !EXPORT_RELATIONS.contains(&row.relation.as_str()) {
+            bail!("Line {}: `{}` is not an importable relation", n + 2, row.relation);
+        }
+        grouped.entry(row.relation).or_default().push(row.row);
+    }
+    backfill_rows(from, &mut grouped);
+
+    let mut prepared = Vec::new();
+    for (relation, rows) in grouped {
+        let columns = parse_spec(&migrations::relation_spec(&relation, dim));
+        let rows = rows.into_iter()
+            .map(|mut row| -> Result<DataValue> {
+                upgrade_row(&relation, from, dim, &mut row);
+                let values = columns.iter()
+                    .map(|c| json_to_datavalue(row.remove(&c.name).unwrap_or(Value::Null), c.ty)
+                        .with_context(|| format!("{}.{}", relation, c.name)))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(DataValue::List(values))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        prepared.push((relation, columns, rows));
+    }
+    Ok(prepared)
+}
+
+/// Vectors are only comparable within one model. A store that has never
+/// recorded a model takes the export's; otherwise the models must match.
+fn adopt_embedding_model(backend: &mut CozoBackend, header: &ExportHeader) -> Result<()> {
+    let Some(exported) = &header.embedding_model else { return Ok(()) };
+    match backend.embedding_meta()? {
+        Some((model, dim)) if model == *exported && dim == header.dim => Ok(()),
+        Some((model, _)) => bail!(
+            "Export was embedded with {} but this store uses {}. Set [embedding] model = \"{}\" before importing; switching back afterwards re-embeds everything.",
+            exported, model, exported
+        ),
+        None => {
+            if header.dim != backend.dim {
+                let nodes = backend.run_script("?[count(id)] := *nodes{id}", Default::default(), ScriptMutability::Immutable)?;
+                if nodes.rows.first().and_then(|r| r.first()).and_then(|v| v.get_int()).unwrap_or(0) > 0 {
+                    bail!("Store holds {}-dim vectors of an unknown model; import into an empty store instead", backend.dim);
+                }
+                backend.recreate_vector_relations(&VECTOR_RELATIONS, header.dim)?;
+            }
+            backend.set_embedding_meta(exported, header.dim)
+        }
+    }
+}
+
+fn count_conflicts(backend: &CozoBackend, relation: &str, columns: &[Column], rows: &[DataValue]) -> Result<usize> {
+    let all = column_list(columns, |_| true);
+    let keys = column_list(columns, |c| c.key);
+    let script = format!(
+        "rows[{all}] <- $rows\n?[count({first})] := rows[{all}], *{rel}{{{keys}}}",
+        all = all,
+        first = columns[0].name,
+        rel = relation,
+        keys = keys
+    );
+    let mut params = BTreeMap::new();
+    params.insert("rows".to_string(), DataValue::List(rows.to_vec()));
+    let result = backend.run_script(&script, params, ScriptMutability::Immutable)?;
+    Ok(result.rows.first().and_then(|r| r.first()).and_then(|v| v.get_int()).unwrap_or(0) as usize)
+}
+
+fn import_script(relation: &str, columns: &[Column], policy: ConflictPolicy) -> String {
+    let all = column_list(columns, |_| true);
+    let keys = column_list(columns, |c| c.key);
+    let values = column_list(columns, |c| !c.key);
+    let put = format!(":put {} {{ {} => {} }}", relation, keys, values);
+    match policy {
+        ConflictPolicy::Skip => format!(
+            "rows[{all}] <- $rows\n?[{all}] := rows[{all}], not *{rel}{{{keys}}}\n{put}",
+            all = all, rel = relation, keys = keys, put = put
+        ),
+        ConflictPolicy::Overwrite | ConflictPolicy::Fail => format!("?[{}] <- $rows\n{}", all, put),
+    }
+}
+
+fn column_list(columns: &[Column], keep: impl Fn(&Column) -> bool) -> String {
+    columns.iter().filter(|c| keep(c)).map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+/// Cozo's native backup (a single SQLite file) of the whole store.
+pub fn export_backup(backend: &CozoBackend, out: &Path) -> Result<()> {
+    backend.db.backup_db(out).map_err(|e| anyhow!("Backup failed: {}", e))
+}
+
+/// Restores the export relations from a native backup. Rows are written
+/// as-is, so existing keys are always overwritten.
+pub fn import_backup(backend: &CozoBackend, input: &Path) -> Result<()> {
+    let relations: Vec<String> = EXPORT_RELATIONS.iter().map(|r| r.to_string()).collect();
+    backend.db.import_from_backup(input, &relations).map_err(|e| anyhow!("Backup import failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec_splits_keys_and_types() {
+        let columns = parse_spec(&migrations::relation_spec("chunks", 4));
+        let keys: Vec<&str> = columns.iter().filter(|c| c.key).map(|c| c.name.as_str()).collect();
+        assert_eq!(keys, vec!["parent_kind", "parent_id", "idx"]);
+        assert_eq!(columns.last().map(|c| (c.name.as_str(), c.ty)), Some(("embedding", ColType::Vector)));
+        assert_eq!(columns[2].ty, ColType::Int);
+
+        let meta = parse_spec(&migrations::relation_spec("node_meta", 4));
+        assert_eq!(meta.iter().map(|c| c.ty).collect::<Vec<_>>(), vec![
+            ColType::String, ColType::String, ColType::String, ColType::List, ColType::Float, ColType::Int, ColType::Int,
+        ]);
+    }
+
+    #[test]
+    fn test_json_round_trip_keeps_types() {
+        let json = datavalue_to_json(&DataValue::from(42i64));
+        assert_eq!(json, Value::from(42));
+        assert_eq!(json_to_datavalue(json, ColType::Int).unwrap(), DataValue::from(42i64));
+        assert!(json_to_datavalue(Value::from("x"), ColType::Int).is_err());
+        assert_eq!(
+            json_to_datavalue(serde_json::json!({"a": 1}), ColType::Json).unwrap(),
+            DataValue::Json(JsonData(serde_json::json!({"a": 1})))
+        );
+    }
+
+    #[test]
+    fn test_v4_export_rows_are_upgraded() {
+        let export = [
+            r#"{"format":"sly-export","format_version":1,"schema_version":4,"embedding_model":null,"dim":4,"exported_at":0}"#,
+            r#"{"relation":"sessions","row":{"id":"s1","status":"Error(\"boom\")","depth":2,"input":"hi","created_at":5}}"#,
+            r#"{"relation":"sessions","row":{"id":"s2","status":"Completed","depth":1,"input":"yo","created_at":6}}"#,
+            r#"{"relation":"session_messages","row":{"session_id":"s1","msg_index":1,"content":"**Sly (Step 1):** ok"}}"#,
+            r#"{"relation":"event_log","row":{"id":"e1","op":"session_created","data":{},"timestamp":9,"version":1}}"#,
+        ];
+        let mut lines = export.iter().map(|l| Ok(l.to_string()));
+        let header = read_header(&lines.next().unwrap().unwrap()).unwrap();
+        let prepared = prepare_rows(lines, header.schema_version, 4).unwrap();
+        let rows = |relation: &str| prepared.iter().find(|(r, _, _)| r == relation).map(|(_, _, rows)| rows.clone()).unwrap();
+        let row = |relation: &str, i: usize| match &rows(relation)[i] {
+            DataValue::List(values) => values.iter().map(datavalue_to_json).collect::<Vec<_>>(),
+            other => panic!("{:?}", other),
+        };
+
+        // id, status, error, depth, input, created_at
+        assert_eq!(row("sessions", 0)[1..3], [Value::from("Error"), Value::from("boom")]);
+        assert_eq!(row("sessions", 1)[1..3], [Value::from("Completed"), Value::Null]);
+        // session_id, msg_index, role, kind, content, timestamp, tokens
+        assert_eq!(row("session_messages", 0)[2..4], [Value::from("assistant"), Value::from("response")]);
+        // timestamp, id, op, session_id, directive_id
+        assert_eq!(row("event_by_time", 0), vec![Value::from(9), Value::from("e1"), Value::from("session_created"), Value::Null, Value::Null]);
+
+        let newer = r#"{"format":"sly-export","format_version":1,"schema_version":99,"embedding_model":null,"dim":4,"exported_at":0}"#;
+        assert!(read_header(newer).is_err());
+    }
+
+    #[test]
+    fn test_skip_policy_filters_existing_keys() {
+        let columns = parse_spec(&migrations::relation_spec("edges", 4));
+        let script = import_script("edges", &columns, ConflictPolicy::Skip);
+        assert!(script.contains("not *edges{from, to}"));
+        assert!(script.ends_with(":put edges { from, to => rel_type }"));
+    }
+}
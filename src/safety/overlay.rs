@@ -20,7 +20,7 @@ impl OverlayFS {
     /// `base_dir`: The real workspace (e.g., user's project).
     /// `overlay_id`: Unique ID for this transaction (e.g., task ID).
     pub fn new(base_dir: &Path, overlay_id: &str) -> Result<Self> {
-        let temp_dir = std::env::temp_dir().join("sly_overlays").join(overlay_id);
+        let temp_dir = overlay_path(overlay_id);
         
         if temp_dir.exists() {
             fs::remove_dir_all(&temp_dir)?;
@@ -33,6 +33,13 @@ impl OverlayFS {
         })
     }
 
+    /// Like `new`, but keeps whatever the overlay already holds.
+    pub fn open(base_dir: &Path, overlay_id: &str) -> Result<Self> {
+        let overlay_dir = overlay_path(overlay_id);
+        fs::create_dir_all(&overlay_dir)?;
+        Ok(Self { base_dir: base_dir.to_path_buf(), overlay_dir })
+    }
+
     /// Reads a file, transparently checking overlay then base.
     pub fn read_file(&self, path: &Path) -> Result<String> {
         let rel_path = self.get_relative_path(path)?;
@@ -120,6 +127,19 @@ impl OverlayFS {
     }
 }
 
+/// Overlay of every session that has none of its own.
+pub const SHARED_OVERLAY_ID: &str = "godmode_session";
+
+/// Where the overlay named `overlay_id` keeps its files.
+pub fn overlay_path(overlay_id: &str) -> PathBuf {
+    std::env::temp_dir().join("sly_overlays").join(overlay_id)
+}
+
+/// Overlay id of a session that has an overlay of its own (forks).
+pub fn session_overlay_id(session_id: &str) -> String {
+    format!("session_{}", session_id)
+}
+
 // Ensure cleanup on drop if not committed? 
 // Ideally yes, but strict transactional logic (commit consumed) is safer.
 // For now, let's leave drop explicit or rely on OS temp cleanup, 